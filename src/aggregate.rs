@@ -0,0 +1,266 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::stats::VolleyStats;
+use crate::volley::VolleyInfo;
+
+/// Version of the `--state-file` format. Files of other versions are rejected rather than
+/// misread, so bump it whenever `TargetAggregate` changes incompatibly.
+const STATE_VERSION: u32 = 1;
+
+/// State of a target carried across the volleys of a run.
+#[derive(Default, Serialize, Deserialize)]
+pub struct TargetAggregate {
+    /// Requests sent, received and lost over all volleys. Received is missing in state files
+    /// from before it was counted.
+    pub sent: usize,
+    #[serde(default)]
+    pub received: usize,
+    pub lost: usize,
+    /// Share of requests lost in each of the most recent volleys, oldest first.
+    pub loss_history: VecDeque<f64>,
+    /// Number of volleys with at least one reply.
+    volleys: usize,
+    /// Running mean and sum of squared deviations of the volley averages in milliseconds.
+    avg_mean: f64,
+    avg_m2: f64,
+    /// Exponentially weighted moving averages of the volley averages in milliseconds and of
+    /// the loss ratios, if --ewma is given. Missing in state files from before they were kept.
+    #[serde(default)]
+    ewma_avg: Option<f64>,
+    #[serde(default)]
+    ewma_loss: Option<f64>,
+    /// Latencies of the most recent replies, if a rolling window is configured. Not saved,
+    /// so the window starts over after a restart.
+    #[serde(skip)]
+    rolling: RollingQuantile,
+    /// Summary of the most recent volley, if any since the start.
+    #[serde(skip)]
+    pub latest: Option<LatestVolley>,
+}
+
+/// Latencies and loss of a target's most recent volley.
+#[derive(Clone, Copy)]
+pub struct LatestVolley {
+    /// Share of requests lost.
+    pub loss: f64,
+    /// Latencies, if any request got a reply.
+    pub avg: Option<Duration>,
+    /// Percentiles, if there were at least --min-samples replies.
+    pub percentile50: Option<Duration>,
+    pub percentile99: Option<Duration>,
+}
+
+/// Ratio between the bounds of consecutive `RollingQuantile` buckets.
+const BUCKET_GROWTH: f64 = 1.01;
+/// Number of buckets, reaching past 10 minutes.
+const BUCKETS: usize = 2048;
+
+/// Latencies of a sliding window of replies, bucketed so quantiles can be read without sorting
+/// and replies leaving the window can be removed.
+///
+/// Buckets are 1% wide on a log scale starting at 1 us, and a quantile is reported as the
+/// geometric middle of its bucket, so it's within 0.5% of the exact value. Latencies below
+/// 1 us share the first bucket.
+#[derive(Default)]
+pub struct RollingQuantile {
+    /// Buckets of the replies in the window, oldest first.
+    recent: VecDeque<u16>,
+    counts: Vec<u32>,
+}
+
+impl RollingQuantile {
+    fn bucket(latency: Duration) -> u16 {
+        let micros = latency.as_secs_f64() * 1e6;
+        if micros < 1.0 {
+            return 0;
+        }
+        ((micros.ln() / BUCKET_GROWTH.ln()) as usize + 1).min(BUCKETS - 1) as u16
+    }
+
+    /// Adds a reply, keeping at most `window` of them.
+    pub fn add(&mut self, latency: Duration, window: usize) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; BUCKETS];
+        }
+        let bucket = Self::bucket(latency);
+        self.recent.push_back(bucket);
+        self.counts[bucket as usize] += 1;
+        while self.recent.len() > window {
+            let oldest = self.recent.pop_front().unwrap();
+            self.counts[oldest as usize] -= 1;
+        }
+    }
+
+    /// Approximate `q` quantile of the window, ranked like the per-volley percentiles, if it
+    /// has any replies.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let len = self.recent.len();
+        if len == 0 {
+            return None;
+        }
+        let rank = ((len as f64 * q) as usize).min(len - 1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += *count as usize;
+            if seen > rank {
+                let micros = match bucket {
+                    0 => 0.5,
+                    _ => BUCKET_GROWTH.powf(bucket as f64 - 0.5),
+                };
+                return Some(Duration::from_secs_f64(micros / 1e6));
+            }
+        }
+        None
+    }
+}
+
+impl TargetAggregate {
+    /// Adds a volley, keeping the loss ratios of at most `history` volleys and the latencies of
+    /// at most `rolling_window` replies, and moving the averages by `ewma_weight`.
+    pub fn add(
+        &mut self,
+        info: &VolleyInfo,
+        stats: &VolleyStats,
+        history: usize,
+        rolling_window: Option<usize>,
+        ewma_weight: Option<f64>,
+    ) {
+        let loss = match info.results.len() {
+            0 => 0.0,
+            total => info.lost as f64 / total as f64,
+        };
+        self.sent += info.sent;
+        self.received += info.received;
+        self.lost += info.lost;
+        self.latest = Some(LatestVolley {
+            loss,
+            avg: (stats.received > 0).then_some(stats.avg),
+            percentile50: stats.percentiles.then_some(stats.percentile50),
+            percentile99: stats.percentiles.then_some(stats.percentile99),
+        });
+        self.loss_history.push_back(loss);
+        while self.loss_history.len() > history {
+            self.loss_history.pop_front();
+        }
+        if let Some(window) = rolling_window {
+            for result in info.results.iter().flatten() {
+                self.rolling.add(result.latency, window);
+            }
+        }
+
+        if let Some(weight) = ewma_weight {
+            let moved = |average: Option<f64>, value: f64| match average {
+                None => value,
+                Some(average) => average + weight * (value - average),
+            };
+            self.ewma_loss = Some(moved(self.ewma_loss, loss));
+            if info.received > 0 {
+                self.ewma_avg = Some(moved(self.ewma_avg, stats.avg.as_secs_f64() * 1000.0));
+            }
+        }
+
+        if info.received > 0 {
+            // Welford's algorithm, so long runs don't need to keep every average.
+            let avg = stats.avg.as_secs_f64() * 1000.0;
+            self.volleys += 1;
+            let delta = avg - self.avg_mean;
+            self.avg_mean += delta / self.volleys as f64;
+            self.avg_m2 += delta * (avg - self.avg_mean);
+        }
+    }
+
+    /// 99th percentile latency of the rolling window, if it has any replies.
+    pub fn rolling_p99(&self) -> Option<Duration> {
+        self.rolling.quantile(0.99)
+    }
+
+    /// Moving average of the volley averages, if any volley got a reply since --ewma was given.
+    pub fn ewma_avg(&self) -> Option<Duration> {
+        self.ewma_avg
+            .map(|millis| Duration::from_secs_f64(millis / 1000.0))
+    }
+
+    /// Moving average of the share of requests lost, if --ewma is given.
+    pub fn ewma_loss(&self) -> Option<f64> {
+        self.ewma_loss
+    }
+
+    /// Number of volleys that contributed to `avg_cov`.
+    pub fn volleys(&self) -> usize {
+        self.volleys
+    }
+
+    /// Coefficient of variation (standard deviation over mean) of the volley averages, if any
+    /// volley got a reply.
+    pub fn avg_cov(&self) -> Option<f64> {
+        if self.volleys == 0 || self.avg_mean <= 0.0 {
+            return None;
+        }
+        let stddev = (self.avg_m2 / self.volleys as f64).sqrt();
+        Some(stddev / self.avg_mean)
+    }
+}
+
+#[derive(Serialize)]
+struct SavedState<'a> {
+    version: u32,
+    targets: &'a HashMap<String, TargetAggregate>,
+}
+
+#[derive(Deserialize)]
+struct LoadedState {
+    version: u32,
+    targets: HashMap<String, TargetAggregate>,
+}
+
+/// Loads aggregates saved with `save_state`, or none if `path` doesn't exist yet.
+pub fn load_state(path: &Path) -> io::Result<HashMap<String, TargetAggregate>> {
+    let contents = match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Failed to read {}: {}", path.display(), e),
+            ))
+        }
+        Ok(contents) => contents,
+    };
+    let state: LoadedState = match serde_json::from_str(&contents) {
+        Err(e) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: invalid state file: {}", path.display(), e),
+            ))
+        }
+        Ok(state) => state,
+    };
+    if state.version != STATE_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: state file version {} isn't supported, expected {}",
+                path.display(),
+                state.version,
+                STATE_VERSION
+            ),
+        ));
+    }
+    Ok(state.targets)
+}
+
+/// Saves aggregates to `path`, replacing the previous state only once the new one is written.
+pub fn save_state(path: &Path, targets: &HashMap<String, TargetAggregate>) -> io::Result<()> {
+    let state = SavedState {
+        version: STATE_VERSION,
+        targets,
+    };
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    fs::write(&temp, serde_json::to_string(&state)?)?;
+    fs::rename(&temp, path)
+}
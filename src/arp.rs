@@ -0,0 +1,236 @@
+use pnet::datalink::{self, Channel, DataLinkReceiver, NetworkInterface};
+use pnet::ipnetwork::IpNetwork;
+use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::Packet;
+use pnet::util::MacAddr;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::log;
+use crate::volley::{self, PingResult, Strays, VolleyConfig, VolleyInfo, VolleyResult};
+
+const ETHERNET_HEADER_SIZE: usize = 14;
+const ARP_SIZE: usize = 28;
+/// How often the receiver checks whether to stop while no frames arrive.
+const RECEIVE_POLL: Duration = Duration::from_millis(100);
+
+/// The interface `target` is on, and the address and MAC address requests are sent from.
+fn local_interface(target: Ipv4Addr) -> Result<(NetworkInterface, Ipv4Addr, MacAddr), String> {
+    for interface in datalink::interfaces() {
+        if !interface.is_up() || interface.is_loopback() {
+            continue;
+        }
+        let mac = match interface.mac {
+            None => continue,
+            Some(mac) => mac,
+        };
+        let source = interface.ips.iter().find_map(|network| match network {
+            IpNetwork::V4(network) if network.contains(target) => Some(network.ip()),
+            _ => None,
+        });
+        if let Some(source) = source {
+            return Ok((interface, source, mac));
+        }
+    }
+    Err(format!("{} isn't on a local IPv4 subnet", target))
+}
+
+/// A broadcast frame asking who has `target`.
+fn request_frame(target: Ipv4Addr, source: Ipv4Addr, mac: MacAddr) -> Vec<u8> {
+    let mut arp = vec![0; ARP_SIZE];
+    let mut arp_packet = MutableArpPacket::new(&mut arp).expect("Failed to create ARP packet");
+    arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp_packet.set_protocol_type(EtherTypes::Ipv4);
+    arp_packet.set_hw_addr_len(6);
+    arp_packet.set_proto_addr_len(4);
+    arp_packet.set_operation(ArpOperations::Request);
+    arp_packet.set_sender_hw_addr(mac);
+    arp_packet.set_sender_proto_addr(source);
+    arp_packet.set_target_hw_addr(MacAddr::zero());
+    arp_packet.set_target_proto_addr(target);
+
+    let mut frame = vec![0; ETHERNET_HEADER_SIZE + ARP_SIZE];
+    let mut ethernet_packet =
+        MutableEthernetPacket::new(&mut frame).expect("Failed to create Ethernet packet");
+    ethernet_packet.set_destination(MacAddr::broadcast());
+    ethernet_packet.set_source(mac);
+    ethernet_packet.set_ethertype(EtherTypes::Arp);
+    ethernet_packet.set_payload(&arp);
+    frame
+}
+
+/// Whether `frame` is a reply from `target` to a request from `source`.
+fn is_reply(frame: &[u8], target: Ipv4Addr, source: Ipv4Addr) -> bool {
+    let ethernet_packet = match EthernetPacket::new(frame) {
+        Some(packet) if packet.get_ethertype() == EtherTypes::Arp => packet,
+        _ => return false,
+    };
+    match ArpPacket::new(ethernet_packet.payload()) {
+        None => false,
+        Some(arp_packet) => {
+            arp_packet.get_operation() == ArpOperations::Reply
+                && arp_packet.get_sender_proto_addr() == target
+                && arp_packet.get_target_proto_addr() == source
+        }
+    }
+}
+
+/// Collects the arrival times of replies until the stop time sent through `stop`.
+fn receive(
+    mut rx: Box<dyn DataLinkReceiver>,
+    target: Ipv4Addr,
+    source: Ipv4Addr,
+    stop: mpsc::Receiver<Instant>,
+) -> Vec<Instant> {
+    let mut replies = Vec::new();
+    let mut stop_at = None;
+    loop {
+        if stop_at.is_none() {
+            stop_at = stop.try_recv().ok();
+        }
+        if stop_at.is_some_and(|stop_at| Instant::now() >= stop_at) {
+            return replies;
+        }
+        match rx.next() {
+            Ok(frame) => {
+                if is_reply(frame, target, source) {
+                    replies.push(Instant::now());
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => {
+                log::verbose!("Failed to receive frame: {}", e);
+                return replies;
+            }
+        }
+    }
+}
+
+/// Measures a volley like `volley::measure_volley`, but sends ARP requests for `target` on its
+/// local subnet, which hosts answer even when they filter ICMP.
+///
+/// ARP replies don't identify the request they answer, so each is matched to the oldest
+/// request still awaiting one. Replies beyond those are counted as duplicates.
+pub fn measure_volley(target: IpAddr, config: &VolleyConfig) -> VolleyResult {
+    let target = match target {
+        IpAddr::V4(target) => target,
+        IpAddr::V6(_) => return VolleyResult::Error("ARP only works with IPv4".to_string()),
+    };
+    let (interface, source, mac) = match local_interface(target) {
+        Err(e) => return VolleyResult::Error(e),
+        Ok(local) => local,
+    };
+    let channel_config = datalink::Config {
+        read_timeout: Some(RECEIVE_POLL),
+        ..Default::default()
+    };
+    let (mut tx, rx) = match datalink::channel(&interface, channel_config) {
+        Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+        Ok(_) => return VolleyResult::Error("Unsupported channel type".to_string()),
+        Err(e) => {
+            return VolleyResult::Error(format!(
+                "Failed to open a channel on {}: {}",
+                interface.name, e
+            ))
+        }
+    };
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let receiver = thread::spawn(move || receive(rx, target, source, stop_rx));
+
+    let frame = request_frame(target, source, mac);
+    let mut send_times: Vec<Instant> = Vec::new();
+    let mut wall_times: Vec<SystemTime> = Vec::new();
+    let mut truncated = false;
+    let mut rng = match config.seed {
+        None => StdRng::from_entropy(),
+        Some(seed) => StdRng::seed_from_u64(seed),
+    };
+    let mut next_send = Instant::now();
+    let deadline = config.deadline.map(|deadline| next_send + deadline);
+    for index in 0..config.count {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            truncated = true;
+            break;
+        }
+        send_times.push(Instant::now());
+        wall_times.push(SystemTime::now());
+        if let Some(Err(e)) = tx.send_to(&frame, None) {
+            log::verbose!("Failed to send ARP request: {}", e);
+        }
+
+        next_send += config.gap(index, &mut rng);
+        let wake = match deadline {
+            Some(deadline) => next_send.min(deadline),
+            None => next_send,
+        };
+        volley::pace(wake, config.precise_pacing);
+    }
+
+    let last_send = send_times.last().copied().unwrap_or_else(Instant::now);
+    let stop_at = match deadline {
+        Some(deadline) if deadline < last_send + config.timeout => {
+            truncated = true;
+            deadline
+        }
+        _ => last_send + config.timeout,
+    };
+    _ = stop_tx.send(stop_at);
+    let replies = match receiver.join() {
+        Err(_) => return VolleyResult::Error("Receiver thread panicked".to_string()),
+        Ok(replies) => replies,
+    };
+
+    let sent = send_times.len();
+    let mut results: Vec<Option<PingResult>> = vec![None; sent];
+    let mut duplicates = 0;
+    let mut next = 0;
+    for reply in replies {
+        while next < sent && reply > send_times[next] + config.timeout {
+            next += 1;
+        }
+        if next < sent && reply >= send_times[next] {
+            results[next] = Some(PingResult {
+                latency: reply - send_times[next],
+                reply_size: ARP_SIZE,
+                interface: Some(interface.index),
+                ip_header: None,
+            });
+            next += 1;
+        } else {
+            duplicates += 1;
+        }
+    }
+
+    let received = results.iter().flatten().count();
+    let (max_in_flight, avg_in_flight) = volley::in_flight(&send_times, &results, config.timeout);
+    VolleyResult::Success(Box::new(VolleyInfo {
+        sent,
+        received,
+        lost: sent - received,
+        corrupted: 0,
+        checksum_errors: 0,
+        rejected_sources: 0,
+        strays: Strays::default(),
+        icmp_errors: Vec::new(),
+        duplicates,
+        lenient_matches: 0,
+        excess_replies: 0,
+        reordered: 0,
+        max_reorder_distance: 0,
+        max_in_flight,
+        avg_in_flight,
+        window_waits: 0,
+        first_index: 0,
+        interface: Some(interface.index),
+        profile: None,
+        truncated,
+        send_times: wall_times,
+        results,
+    }))
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::record;
+use crate::stats::{self, StatsOptions, VolleyStats};
+use crate::volley::VolleyInfo;
+
+/// Summary of a target over a whole baseline run.
+struct BaselineEntry {
+    avg: Duration,
+    percentile99: Duration,
+    /// Percentage of requests lost.
+    loss: f64,
+}
+
+/// Per-target summaries of a previous run to compare volleys against.
+pub struct Baseline {
+    entries: HashMap<String, BaselineEntry>,
+    /// Growth, in percent for latencies and percentage points for loss, counted as a regression.
+    threshold: f64,
+}
+
+/// Difference between a volley and the baseline of its target.
+pub struct Comparison {
+    /// Differences in latency, in milliseconds.
+    pub avg_delta: f64,
+    pub percentile99_delta: f64,
+    /// Difference in loss, in percentage points.
+    pub loss_delta: f64,
+    pub regression: bool,
+}
+
+fn loss_percent(lost: usize, received: usize) -> f64 {
+    match lost + received {
+        0 => 0.0,
+        total => lost as f64 * 100.0 / total as f64,
+    }
+}
+
+impl Baseline {
+    /// Loads a baseline from a file written with `--format ndjson` or `cbor`, pooling every volley
+    /// of a target into one summary.
+    pub fn load(path: &Path, timeout: Duration, threshold: f64) -> io::Result<Baseline> {
+        let mut pooled: HashMap<String, VolleyInfo> = HashMap::new();
+        for record in record::read_records(path)? {
+            let info = record.to_info();
+            match pooled.get_mut(&record.target) {
+                None => {
+                    pooled.insert(record.target, info);
+                }
+                Some(pool) => {
+                    pool.results.extend(info.results);
+                    pool.sent += info.sent;
+                    pool.received += info.received;
+                    pool.lost += info.lost;
+                }
+            }
+        }
+
+        let options = StatsOptions {
+            timeout,
+            ..Default::default()
+        };
+        let entries = pooled
+            .into_iter()
+            .map(|(target, info)| {
+                let stats = stats::summarize(&info, &options);
+                let entry = BaselineEntry {
+                    avg: stats.avg,
+                    percentile99: stats.percentile99,
+                    loss: loss_percent(stats.lost, stats.received),
+                };
+                (target, entry)
+            })
+            .collect();
+
+        Ok(Baseline { entries, threshold })
+    }
+
+    /// Compares a volley with the baseline of `target`, if the baseline has one.
+    pub fn compare(&self, target: &str, stats: &VolleyStats) -> Option<Comparison> {
+        let entry = self.entries.get(target)?;
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let grew = |current: Duration, baseline: Duration| {
+            millis(current) > millis(baseline) * (1.0 + self.threshold / 100.0)
+        };
+        let loss_delta = loss_percent(stats.lost, stats.received) - entry.loss;
+
+        Some(Comparison {
+            avg_delta: millis(stats.avg) - millis(entry.avg),
+            percentile99_delta: millis(stats.percentile99) - millis(entry.percentile99),
+            loss_delta,
+            regression: grew(stats.avg, entry.avg)
+                || grew(stats.percentile99, entry.percentile99)
+                || loss_delta > self.threshold,
+        })
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::stats::VolleyStats;
+
+/// Limits a volley breaches by exceeding them.
+pub struct Thresholds {
+    /// Percentage of requests lost.
+    pub loss: Option<f64>,
+    /// Average and 99th percentile latency in milliseconds.
+    pub avg: Option<f64>,
+    pub p99: Option<f64>,
+}
+
+/// A metric of a volley over its threshold.
+pub struct Breach {
+    pub metric: &'static str,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Lists the metrics of a volley over their thresholds.
+pub fn breaches(stats: &VolleyStats, thresholds: &Thresholds) -> Vec<Breach> {
+    let loss = match stats.received + stats.lost {
+        0 => None,
+        total => Some(stats.lost as f64 * 100.0 / total as f64),
+    };
+    let replied = stats.received > 0;
+    let metrics = [
+        ("loss", loss, thresholds.loss),
+        ("avg", replied.then(|| millis(stats.avg)), thresholds.avg),
+        (
+            "p99",
+            stats.percentiles.then(|| millis(stats.percentile99)),
+            thresholds.p99,
+        ),
+    ];
+    metrics
+        .into_iter()
+        .filter_map(|(metric, value, threshold)| match (value, threshold) {
+            (Some(value), Some(threshold)) if value > threshold => Some(Breach {
+                metric,
+                value,
+                threshold,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Runs a shell command for each breach, at most once per cooldown for a target and metric.
+pub struct OnBreach {
+    command: String,
+    cooldown: Duration,
+    last_run: HashMap<(String, &'static str), Instant>,
+}
+
+impl OnBreach {
+    pub fn new(command: String, cooldown: Duration) -> OnBreach {
+        OnBreach {
+            command,
+            cooldown,
+            last_run: HashMap::new(),
+        }
+    }
+
+    /// Starts the command for `breach` of `label` without waiting for it to finish, unless it
+    /// ran for the same breach within the cooldown.
+    pub fn run(&mut self, label: &str, addr: IpAddr, breach: &Breach) {
+        let now = Instant::now();
+        let key = (label.to_string(), breach.metric);
+        if let Some(last_run) = self.last_run.get(&key) {
+            if now - *last_run < self.cooldown {
+                return;
+            }
+        }
+        self.last_run.insert(key, now);
+
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("EPINGM_TARGET", label)
+            .env("EPINGM_IP", addr.to_string())
+            .env("EPINGM_METRIC", breach.metric)
+            .env("EPINGM_VALUE", format!("{:.2}", breach.value))
+            .env("EPINGM_THRESHOLD", format!("{:.2}", breach.threshold))
+            .spawn();
+        match child {
+            Err(e) => eprintln!("Failed to run --on-breach command: {}", e),
+            // Reap the command when it exits, so it doesn't linger as a zombie.
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+        }
+    }
+}
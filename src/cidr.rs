@@ -0,0 +1,116 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A subnet in CIDR notation, such as 192.0.2.0/24 or 2001:db8::/32.
+#[derive(Clone, Copy, Debug)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Parses a subnet, or a single address if the prefix length is left out.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid subnet '{}', expected an address with an optional prefix length (e.g. 192.0.2.0/24)",
+                s
+            )
+        };
+        let (network, prefix) = match s.split_once('/') {
+            None => (s, None),
+            Some((network, prefix)) => (network, Some(prefix)),
+        };
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let max_prefix = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            None => max_prefix,
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max_prefix => prefix,
+                _ => return Err(invalid()),
+            },
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn addr(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn whole_address_space() {
+        assert!(cidr("0.0.0.0/0").contains(addr("0.0.0.0")));
+        assert!(cidr("0.0.0.0/0").contains(addr("255.255.255.255")));
+        assert!(cidr("::/0").contains(addr("::")));
+        assert!(cidr("::/0").contains(addr("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")));
+    }
+
+    #[test]
+    fn single_address() {
+        assert!(cidr("192.0.2.1/32").contains(addr("192.0.2.1")));
+        assert!(!cidr("192.0.2.1/32").contains(addr("192.0.2.2")));
+        assert!(cidr("2001:db8::1/128").contains(addr("2001:db8::1")));
+        assert!(!cidr("2001:db8::1/128").contains(addr("2001:db8::2")));
+    }
+
+    #[test]
+    fn bare_address() {
+        assert!(cidr("192.0.2.1").contains(addr("192.0.2.1")));
+        assert!(!cidr("192.0.2.1").contains(addr("192.0.2.0")));
+        assert!(cidr("2001:db8::1").contains(addr("2001:db8::1")));
+        assert!(!cidr("2001:db8::1").contains(addr("2001:db8::")));
+    }
+
+    #[test]
+    fn subnet() {
+        assert!(cidr("192.0.2.0/24").contains(addr("192.0.2.255")));
+        assert!(!cidr("192.0.2.0/24").contains(addr("192.0.3.0")));
+        assert!(cidr("2001:db8::/32").contains(addr("2001:db8:ffff::1")));
+        assert!(!cidr("2001:db8::/32").contains(addr("2001:db9::")));
+    }
+
+    #[test]
+    fn other_family() {
+        assert!(!cidr("0.0.0.0/0").contains(addr("::ffff:192.0.2.1")));
+        assert!(!cidr("::/0").contains(addr("192.0.2.1")));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+        assert!("192.0.2.0/".parse::<Cidr>().is_err());
+        assert!("192.0.2.0/-1".parse::<Cidr>().is_err());
+        assert!("192.0.2/24".parse::<Cidr>().is_err());
+    }
+}
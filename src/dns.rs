@@ -0,0 +1,223 @@
+use rand::random;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::log;
+use crate::volley::{self, PingResult, VolleyConfig, VolleyResult};
+
+/// Port of the resolver if --port isn't given.
+pub const DEFAULT_PORT: u16 = 53;
+/// Largest response read. Queries don't advertise EDNS, so responses fit in 512 bytes.
+const MAX_RESPONSE_SIZE: usize = 512;
+const HEADER_SIZE: usize = 12;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+/// Recursion desired, the only flag set in queries.
+const FLAG_RD: u16 = 0x0100;
+const FLAG_QR: u16 = 0x8000;
+const RCODE_NOERROR: u16 = 0;
+const RCODE_NXDOMAIN: u16 = 3;
+
+/// Encodes `name` as a sequence of length-prefixed labels.
+fn encode_name(name: &str) -> Result<Vec<u8>, String> {
+    let mut encoded = Vec::new();
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!(
+                "Invalid name {}: labels must be 1 to 63 bytes",
+                name
+            ));
+        }
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    if encoded.len() > 255 {
+        return Err(format!("Invalid name {}: longer than 255 bytes", name));
+    }
+    Ok(encoded)
+}
+
+/// Result of a response to a query.
+#[derive(Debug, PartialEq)]
+enum Response {
+    /// Not a response to the query with this ID, e.g. a late one to an earlier query.
+    Unrelated,
+    /// An answer, including that the name doesn't exist.
+    Answer,
+    /// A failure such as SERVFAIL or REFUSED, with its response code.
+    Failure(u16),
+}
+
+fn parse_response(response: &[u8], id: u16) -> Response {
+    if response.len() < HEADER_SIZE {
+        return Response::Unrelated;
+    }
+    let response_id = u16::from_be_bytes([response[0], response[1]]);
+    let flags = u16::from_be_bytes([response[2], response[3]]);
+    if response_id != id || flags & FLAG_QR == 0 {
+        return Response::Unrelated;
+    }
+    match flags & 0x000f {
+        RCODE_NOERROR | RCODE_NXDOMAIN => Response::Answer,
+        rcode => Response::Failure(rcode),
+    }
+}
+
+/// Name of the response code `rcode`, for messages.
+fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        rcode => format!("RCODE {}", rcode),
+    }
+}
+
+/// An A query for a name, sent to every target as its resolver.
+pub struct DnsProbe {
+    /// Encoded question, the name followed by its type and class.
+    question: Vec<u8>,
+    port: u16,
+}
+
+impl DnsProbe {
+    pub fn new(qname: &str, port: u16) -> Result<DnsProbe, String> {
+        let mut question = encode_name(qname)?;
+        question.extend_from_slice(&TYPE_A.to_be_bytes());
+        question.extend_from_slice(&CLASS_IN.to_be_bytes());
+        Ok(DnsProbe { question, port })
+    }
+
+    /// Query with the ID `id`.
+    fn query(&self, id: u16) -> Vec<u8> {
+        let mut query = Vec::with_capacity(HEADER_SIZE + self.question.len());
+        query.extend_from_slice(&id.to_be_bytes());
+        query.extend_from_slice(&FLAG_RD.to_be_bytes());
+        // One question, no answer, authority or additional records.
+        query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        query.extend_from_slice(&self.question);
+        query
+    }
+
+    /// Sends a query to `addr` and times the response. `None` if there was no answer within
+    /// `timeout`, or the resolver failed to answer.
+    fn request(&self, addr: SocketAddr, timeout: Duration) -> io::Result<Option<PingResult>> {
+        let local: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0))?;
+        socket.connect(addr)?;
+
+        let id = random();
+        let start = Instant::now();
+        socket.send(&self.query(id))?;
+        let mut buffer = [0; MAX_RESPONSE_SIZE];
+        loop {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let size = match socket.recv(&mut buffer) {
+                Ok(size) => size,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            };
+            match parse_response(&buffer[..size], id) {
+                Response::Unrelated => continue,
+                Response::Answer => {
+                    return Ok(Some(PingResult {
+                        latency: start.elapsed(),
+                        reply_size: size,
+                        interface: None,
+                        ip_header: None,
+                    }))
+                }
+                Response::Failure(rcode) => {
+                    log::verbose!("{} answered {}", addr, rcode_name(rcode));
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// Measures a volley like `volley::measure_volley`, but sends DNS queries to the target as a
+/// resolver and times the responses. Failures such as SERVFAIL count as lost, like timeouts.
+pub fn measure_volley(
+    target: IpAddr,
+    probe: &Arc<DnsProbe>,
+    config: &VolleyConfig,
+) -> VolleyResult {
+    let addr = SocketAddr::new(target, probe.port);
+    let probe = probe.clone();
+    let timeout = config.timeout;
+    volley::measure_blocking(config, move |_| match probe.request(addr, timeout) {
+        Ok(result) => result,
+        Err(e) => {
+            log::verbose!("Failed to query {}: {}", addr, e);
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query() {
+        let probe = DnsProbe::new("example.com.", DEFAULT_PORT).unwrap();
+        assert_eq!(
+            probe.query(0x1234),
+            [
+                &[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0][..],
+                b"\x07example\x03com\x00",
+                &[0, 1, 0, 1],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert!(encode_name("").is_err());
+        assert!(encode_name("a..b").is_err());
+        assert!(encode_name(&"a".repeat(64)).is_err());
+        assert!(encode_name(&vec!["a".repeat(63); 4].join(".")).is_err());
+    }
+
+    #[test]
+    fn responses() {
+        let response = |id: u16, flags: u16| {
+            [
+                id.to_be_bytes(),
+                flags.to_be_bytes(),
+                [0; 2],
+                [0; 2],
+                [0; 2],
+                [0; 2],
+            ]
+            .concat()
+        };
+        assert_eq!(parse_response(&response(7, 0x8180), 7), Response::Answer);
+        assert_eq!(parse_response(&response(7, 0x8183), 7), Response::Answer);
+        assert_eq!(
+            parse_response(&response(7, 0x8182), 7),
+            Response::Failure(2)
+        );
+        assert_eq!(parse_response(&response(8, 0x8180), 7), Response::Unrelated);
+        assert_eq!(parse_response(&response(7, 0x0100), 7), Response::Unrelated);
+        assert_eq!(parse_response(&[0; 4], 7), Response::Unrelated);
+    }
+}
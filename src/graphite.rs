@@ -0,0 +1,88 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::stats::VolleyStats;
+
+/// Longest a connection or write may take, so a slow server doesn't hold up the volleys.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends volley summaries to a Graphite server over TCP in the plaintext protocol.
+pub struct Graphite {
+    server: String,
+    prefix: String,
+    /// Open connection, if the last send didn't fail.
+    stream: Option<TcpStream>,
+}
+
+fn connect(server: &str) -> io::Result<TcpStream> {
+    let addr = match server.to_socket_addrs()?.next() {
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No addresses found for {}", server),
+            ))
+        }
+        Some(addr) => addr,
+    };
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Makes a target usable as one node of a metric path, which dots would split.
+fn node(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+impl Graphite {
+    /// Connects to `server`, a host:port, right away so a wrong address is reported at start.
+    pub fn connect(server: &str, prefix: &str) -> io::Result<Graphite> {
+        Ok(Graphite {
+            server: server.to_string(),
+            prefix: prefix.to_string(),
+            stream: Some(connect(server)?),
+        })
+    }
+
+    /// Sends the summary of a volley of `label` as metrics under `<prefix>.<label>`. After a
+    /// failure the next send reconnects.
+    pub fn send(&mut self, label: &str, stats: &VolleyStats, timestamp: i64) -> io::Result<()> {
+        let path = format!("{}.{}", self.prefix, node(label));
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let mut metrics: Vec<(&str, f64)> = vec![
+            ("sent", stats.sent as f64),
+            ("received", stats.received as f64),
+            ("lost", stats.lost as f64),
+        ];
+        if stats.received > 0 {
+            metrics.push(("avg_ms", millis(stats.avg)));
+            metrics.push(("min_ms", millis(stats.min)));
+            metrics.push(("max_ms", millis(stats.max)));
+        }
+        if stats.percentiles {
+            metrics.push(("p50_ms", millis(stats.percentile50)));
+            metrics.push(("p99_ms", millis(stats.percentile99)));
+        }
+
+        let mut lines = String::new();
+        for (name, value) in metrics {
+            _ = writeln!(lines, "{}.{} {} {}", path, name, value, timestamp);
+        }
+
+        let mut stream = match self.stream.take() {
+            None => connect(&self.server)?,
+            Some(stream) => stream,
+        };
+        stream.write_all(lines.as_bytes())?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
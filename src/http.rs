@@ -0,0 +1,137 @@
+use clap::ValueEnum;
+use rustls::{ClientConfig, StreamOwned};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::volley::{self, PingResult, VolleyConfig, VolleyResult};
+use crate::{log, tls};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Method {
+    Get,
+    Head,
+}
+
+impl Method {
+    fn name(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+        }
+    }
+}
+
+/// A request to time against every target, sent to the target's address with its host name.
+pub struct HttpProbe {
+    method: Method,
+    path: String,
+    port: u16,
+    /// Client configuration for HTTPS, `None` for plain HTTP.
+    tls: Option<Arc<ClientConfig>>,
+}
+
+impl HttpProbe {
+    /// A probe for `path` on `port`, or the default port of the scheme. With `tls`, certificates
+    /// are verified against the bundled Mozilla roots.
+    pub fn new(method: Method, path: &str, port: Option<u16>, tls: bool) -> HttpProbe {
+        let tls = tls.then(|| tls::client_config(true));
+        let default_port = match tls {
+            Some(_) => 443,
+            None => 80,
+        };
+        HttpProbe {
+            method,
+            path: path.to_string(),
+            port: port.unwrap_or(default_port),
+            tls,
+        }
+    }
+
+    /// Value of the Host header for `host`, with the port unless it's the scheme's default.
+    fn host_header(&self, host: &str) -> String {
+        match (&self.tls, self.port) {
+            (None, 80) | (Some(_), 443) => host.to_string(),
+            _ => format!("{}:{}", host, self.port),
+        }
+    }
+
+    /// Time from starting to connect to `addr` until the first byte of the response arrived,
+    /// including the TLS handshake. `None` if there was no response within `timeout`.
+    fn request(
+        &self,
+        addr: SocketAddr,
+        host: &str,
+        timeout: Duration,
+    ) -> io::Result<Option<Duration>> {
+        let start = Instant::now();
+        let stream = match TcpStream::connect_timeout(&addr, timeout) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+            result => result?,
+        };
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        // The handshake and request share what is left of the timeout.
+        stream.set_read_timeout(Some(remaining))?;
+        stream.set_write_timeout(Some(remaining))?;
+
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: epingm/{}\r\nConnection: close\r\n\r\n",
+            self.method.name(),
+            self.path,
+            self.host_header(host),
+            env!("CARGO_PKG_VERSION")
+        );
+        let mut stream: Box<dyn ReadWrite> = match &self.tls {
+            None => Box::new(stream),
+            Some(config) => Box::new(StreamOwned::new(tls::connection(config, host)?, stream)),
+        };
+        let mut first = [0; 1];
+        let result = stream
+            .write_all(request.as_bytes())
+            .and_then(|_| stream.read_exact(&mut first));
+        match result {
+            Ok(()) => Ok(Some(start.elapsed())),
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+trait ReadWrite: Read + Write {}
+
+impl<T: Read + Write> ReadWrite for T {}
+
+/// Measures a volley like `volley::measure_volley`, but times an HTTP request per ping to
+/// first byte of the response, on a new connection each, for application-level latency. `host`
+/// is sent as the host name and used to verify the certificate.
+pub fn measure_volley(
+    target: IpAddr,
+    host: &str,
+    probe: &Arc<HttpProbe>,
+    config: &VolleyConfig,
+) -> VolleyResult {
+    let addr = SocketAddr::new(target, probe.port);
+    let host = host.to_string();
+    let probe = probe.clone();
+    let timeout = config.timeout;
+    volley::measure_blocking(config, move |_| match probe.request(addr, &host, timeout) {
+        Ok(latency) => latency.map(|latency| PingResult {
+            latency,
+            reply_size: 0,
+            interface: None,
+            ip_header: None,
+        }),
+        Err(e) => {
+            log::verbose!("Failed to request {} from {}: {}", probe.path, addr, e);
+            None
+        }
+    })
+}
@@ -0,0 +1,244 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Why a destination was unreachable, in categories shared by ICMPv4 and ICMPv6.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unreachable {
+    Network,
+    Host,
+    Protocol,
+    Port,
+    /// Administratively prohibited or rejected by policy.
+    Prohibited,
+    /// Any other code, as sent.
+    Other(u8),
+}
+
+/// An ICMP error about one of our requests, categorized the same way for both families.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IcmpError {
+    DestinationUnreachable(Unreachable),
+    /// The request didn't fit the next hop. IPv4 reports this as "fragmentation needed".
+    PacketTooBig {
+        mtu: u32,
+    },
+    TimeExceeded,
+    ParameterProblem,
+}
+
+impl fmt::Display for IcmpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IcmpError::DestinationUnreachable(Unreachable::Other(code)) => {
+                write!(f, "destination unreachable (code {})", code)
+            }
+            IcmpError::DestinationUnreachable(reason) => {
+                let reason = match reason {
+                    Unreachable::Network => "network",
+                    Unreachable::Host => "host",
+                    Unreachable::Protocol => "protocol",
+                    Unreachable::Port => "port",
+                    Unreachable::Prohibited => "prohibited",
+                    Unreachable::Other(_) => unreachable!(),
+                };
+                write!(f, "destination unreachable ({})", reason)
+            }
+            IcmpError::PacketTooBig { mtu } => write!(f, "packet too big (mtu {})", mtu),
+            IcmpError::TimeExceeded => write!(f, "time exceeded"),
+            IcmpError::ParameterProblem => write!(f, "parameter problem"),
+        }
+    }
+}
+
+/// An ICMP error together with the echo request it was sent about.
+#[derive(Debug, PartialEq)]
+pub struct ErrorReport {
+    pub error: IcmpError,
+    /// Destination, identifier and sequence number of the original request.
+    pub destination: IpAddr,
+    pub identifier: u16,
+    pub seq: u16,
+}
+
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const IPV6_HEADER_LENGTH: usize = 40;
+const NEXT_HEADER_ICMPV6: u8 = 58;
+
+/// Identifier and sequence number of an echo request.
+fn echo_request(data: &[u8], request_type: u8) -> Option<(u16, u16)> {
+    if data.len() < 8 || data[0] != request_type {
+        return None;
+    }
+    let identifier = u16::from_be_bytes([data[4], data[5]]);
+    let seq = u16::from_be_bytes([data[6], data[7]]);
+    Some((identifier, seq))
+}
+
+/// Parses an ICMPv4 message, without its IP header, as an error about an echo request.
+pub fn parse_ipv4(message: &[u8]) -> Option<ErrorReport> {
+    if message.len() < 8 {
+        return None;
+    }
+    let (message_type, code) = (message[0], message[1]);
+    let error = match (message_type, code) {
+        (3, 0 | 6) => IcmpError::DestinationUnreachable(Unreachable::Network),
+        (3, 1 | 7) => IcmpError::DestinationUnreachable(Unreachable::Host),
+        (3, 2) => IcmpError::DestinationUnreachable(Unreachable::Protocol),
+        (3, 3) => IcmpError::DestinationUnreachable(Unreachable::Port),
+        (3, 4) => IcmpError::PacketTooBig {
+            mtu: u16::from_be_bytes([message[6], message[7]]) as u32,
+        },
+        (3, 9 | 10 | 13) => IcmpError::DestinationUnreachable(Unreachable::Prohibited),
+        (3, code) => IcmpError::DestinationUnreachable(Unreachable::Other(code)),
+        (11, _) => IcmpError::TimeExceeded,
+        (12, _) => IcmpError::ParameterProblem,
+        _ => return None,
+    };
+
+    // The original IP header and at least the first 8 bytes of its payload.
+    let original = &message[8..];
+    if original.len() < 20 || original[0] >> 4 != 4 {
+        return None;
+    }
+    let header_length = (original[0] & 0x0f) as usize * 4;
+    let destination = Ipv4Addr::new(original[16], original[17], original[18], original[19]);
+    let (identifier, seq) = echo_request(original.get(header_length..)?, ICMPV4_ECHO_REQUEST)?;
+    Some(ErrorReport {
+        error,
+        destination: IpAddr::V4(destination),
+        identifier,
+        seq,
+    })
+}
+
+/// Parses an ICMPv6 message as an error about an echo request.
+pub fn parse_ipv6(message: &[u8]) -> Option<ErrorReport> {
+    if message.len() < 8 {
+        return None;
+    }
+    let (message_type, code) = (message[0], message[1]);
+    let error = match (message_type, code) {
+        (1, 0) => IcmpError::DestinationUnreachable(Unreachable::Network),
+        (1, 1 | 5 | 6) => IcmpError::DestinationUnreachable(Unreachable::Prohibited),
+        (1, 3) => IcmpError::DestinationUnreachable(Unreachable::Host),
+        (1, 4) => IcmpError::DestinationUnreachable(Unreachable::Port),
+        (1, code) => IcmpError::DestinationUnreachable(Unreachable::Other(code)),
+        (2, _) => IcmpError::PacketTooBig {
+            mtu: u32::from_be_bytes([message[4], message[5], message[6], message[7]]),
+        },
+        (3, _) => IcmpError::TimeExceeded,
+        (4, _) => IcmpError::ParameterProblem,
+        _ => return None,
+    };
+
+    // As much of the original packet as fits. Requests are sent without extension headers.
+    let original = &message[8..];
+    if original.len() < IPV6_HEADER_LENGTH
+        || original[0] >> 4 != 6
+        || original[6] != NEXT_HEADER_ICMPV6
+    {
+        return None;
+    }
+    let destination: [u8; 16] = original[24..40].try_into().unwrap();
+    let (identifier, seq) = echo_request(&original[IPV6_HEADER_LENGTH..], ICMPV6_ECHO_REQUEST)?;
+    Some(ErrorReport {
+        error,
+        destination: IpAddr::V6(Ipv6Addr::from(destination)),
+        identifier,
+        seq,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    const DESTINATION: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+
+    /// Builds an ICMPv6 error of `message_type` and `code` quoting an echo request.
+    fn icmpv6_error(message_type: u8, code: u8, field: u32, identifier: u16, seq: u16) -> Vec<u8> {
+        let mut message = vec![message_type, code, 0, 0];
+        message.extend_from_slice(&field.to_be_bytes());
+
+        let request = [
+            &[ICMPV6_ECHO_REQUEST, 0, 0, 0][..],
+            &identifier.to_be_bytes(),
+            &seq.to_be_bytes(),
+            &[0xab; 56],
+        ]
+        .concat();
+        // Version 6, payload length, next header ICMPv6, hop limit.
+        message.extend_from_slice(&[0x60, 0, 0, 0]);
+        message.extend_from_slice(&(request.len() as u16).to_be_bytes());
+        message.extend_from_slice(&[NEXT_HEADER_ICMPV6, 64]);
+        message.extend_from_slice(&SOURCE.octets());
+        message.extend_from_slice(&DESTINATION.octets());
+        message.extend_from_slice(&request);
+        message
+    }
+
+    #[test]
+    fn packet_too_big() {
+        let message = icmpv6_error(2, 0, 1280, 0x1234, 42);
+        assert_eq!(
+            parse_ipv6(&message),
+            Some(ErrorReport {
+                error: IcmpError::PacketTooBig { mtu: 1280 },
+                destination: IpAddr::V6(DESTINATION),
+                identifier: 0x1234,
+                seq: 42,
+            })
+        );
+    }
+
+    #[test]
+    fn packet_too_big_with_truncated_quote() {
+        // Only the first 8 bytes of the request are needed.
+        let message = icmpv6_error(2, 0, 1400, 7, 65535);
+        let report = parse_ipv6(&message[..8 + IPV6_HEADER_LENGTH + 8]).unwrap();
+        assert_eq!(report.error, IcmpError::PacketTooBig { mtu: 1400 });
+        assert_eq!((report.identifier, report.seq), (7, 65535));
+
+        assert_eq!(parse_ipv6(&message[..8 + IPV6_HEADER_LENGTH + 7]), None);
+    }
+
+    #[test]
+    fn ipv6_categories_match_ipv4() {
+        let ipv6 = |message_type, code| parse_ipv6(&icmpv6_error(message_type, code, 0, 1, 1));
+        assert_eq!(
+            ipv6(1, 4).unwrap().error,
+            IcmpError::DestinationUnreachable(Unreachable::Port)
+        );
+        assert_eq!(
+            ipv6(1, 1).unwrap().error,
+            IcmpError::DestinationUnreachable(Unreachable::Prohibited)
+        );
+        assert_eq!(ipv6(3, 0).unwrap().error, IcmpError::TimeExceeded);
+        assert_eq!(ipv6(4, 0).unwrap().error, IcmpError::ParameterProblem);
+        // An echo reply isn't an error.
+        assert_eq!(ipv6(129, 0), None);
+    }
+
+    #[test]
+    fn ipv4_fragmentation_needed_is_packet_too_big() {
+        let mut message = vec![3, 4, 0, 0, 0, 0, 0x05, 0xdc];
+        let mut header = [0u8; 20];
+        header[0] = 0x45;
+        header[9] = 1;
+        header[16..20].copy_from_slice(&[192, 0, 2, 1]);
+        message.extend_from_slice(&header);
+        message.extend_from_slice(&[ICMPV4_ECHO_REQUEST, 0, 0, 0, 0x12, 0x34, 0, 42]);
+
+        assert_eq!(
+            parse_ipv4(&message),
+            Some(ErrorReport {
+                error: IcmpError::PacketTooBig { mtu: 1500 },
+                destination: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                identifier: 0x1234,
+                seq: 42,
+            })
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Verbosity of diagnostics on stderr, from least to most verbose.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Warning = 0,
+    Info = 1,
+    Verbose = 2,
+    Debug = 3,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Sets the level from the --quiet and --verbose flags.
+pub fn init(quiet: bool, verbose: u8) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::Warning,
+        (false, 0) => Level::Info,
+        (false, 1) => Level::Verbose,
+        (false, _) => Level::Debug,
+    };
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Prints problems that affect the results, even with --quiet.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if crate::log::enabled(crate::log::Level::Warning) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+pub(crate) use warning;
+
+/// Prints per-packet diagnostics, which would otherwise clutter output piped elsewhere.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if crate::log::enabled(crate::log::Level::Verbose) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+pub(crate) use verbose;
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if crate::log::enabled(crate::log::Level::Debug) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+pub(crate) use debug;
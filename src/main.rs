@@ -1,26 +1,304 @@
-use clap::{Parser, ValueEnum};
+use chrono::{DateTime, Local};
+use clap::{Parser, Subcommand, ValueEnum};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
 use std::net::ToSocketAddrs;
 use std::{
-    io,
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, BufWriter, IsTerminal, Write},
     net::IpAddr,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
     time::{Duration, Instant},
 };
 use textplots::{Chart, Plot, Shape, LabelBuilder, LabelFormat};
-use volley::{measure_volley, VolleyResult};
+use volley::{Ecn, Entropy, IntervalDistribution, Prober, VolleyConfig, VolleyInfo, VolleyResult};
 
-use crate::volley::PingResult;
+use crate::aggregate::TargetAggregate;
+use crate::baseline::Baseline;
+use crate::breach::{OnBreach, Thresholds};
+use crate::cidr::Cidr;
+use crate::dns::DnsProbe;
+use crate::graphite::Graphite;
+use crate::http::HttpProbe;
+use crate::icmp_error::IcmpError;
+use crate::otlp::OtlpExporter;
+use crate::output::Table;
+use crate::parquet_output::ParquetWriter;
+use crate::prometheus::Exporter;
+use crate::record::{
+    ClockStep, ClockStepRecord, FleetRecord, FleetSummary, Metadata, MetadataRecord,
+    TargetMetadata, VolleyRecord, VolleySummary,
+};
+use crate::stats::{HistogramBucket, SpikeThreshold, StatsOptions, VolleyStats};
+use crate::store::{SqliteStore, StoreLocation};
+use crate::syslog::{Facility, Syslog};
+use crate::timestamp::OneWayDelay;
+use crate::tls::TlsProbe;
+use crate::trace::TraceArgs;
+use crate::volley::{PingResult, Profile};
 
+mod aggregate;
+mod arp;
+mod baseline;
+mod breach;
+mod cidr;
+mod dns;
+mod graphite;
+mod http;
+mod icmp_error;
+mod log;
+mod otlp;
+mod output;
+mod parquet_output;
+mod pmtu;
+mod prometheus;
+mod quic;
+mod record;
+mod selftest;
+mod socket;
+mod stats;
+mod store;
+mod stream;
+mod syslog;
+mod tcp;
+mod timestamp;
+mod tls;
+mod trace;
+mod udp;
 mod volley;
 
 #[derive(Clone, Debug, ValueEnum)]
 enum Format {
     Text,
     Csv,
+    /// A CSV row per request with its volley, sequence number, send time, latency and reply
+    /// size, for analysis that needs more than the summaries.
+    CsvRaw,
+    /// Raw per-packet results as newline-delimited JSON, readable by --replay.
+    Ndjson,
+    /// The same records as ndjson as a sequence of CBOR items, readable by --replay.
+    Cbor,
+    /// A summary of each volley with its latencies as a JSON object per line, for scripts.
+    Json,
+    /// The same objects as json without the header and clock step lines, so every line is a
+    /// self-contained volley, for log shippers tailing the output.
+    Jsonl,
+    /// Shell variable assignments such as EPINGM_AVG_MS='12', for use with eval.
+    Env,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+enum Precision {
+    #[value(name = "s")]
+    Seconds,
+    #[value(name = "ms")]
+    Millis,
+    #[value(name = "us")]
+    Micros,
+    #[value(name = "ns")]
+    Nanos,
+}
+
+impl Precision {
+    fn time_format(&self) -> &'static str {
+        match self {
+            Precision::Seconds => "%Y-%m-%d %H:%M:%S",
+            Precision::Millis => "%Y-%m-%d %H:%M:%S%.3f",
+            Precision::Micros => "%Y-%m-%d %H:%M:%S%.6f",
+            Precision::Nanos => "%Y-%m-%d %H:%M:%S%.9f",
+        }
+    }
+}
+
+/// When results are flushed to stdout or output files.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OutputFlush {
+    /// After every line, so that consumers of a pipe see each volley right away.
+    Line,
+    /// When the buffer fills up or the run ends, for high output rates.
+    Buffered,
+}
+
+/// How volley start times of targets are spread over the volley interval.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Stagger {
+    /// Evenly spaced, in the order the targets were given.
+    Even,
+    /// At random offsets, fixed for the whole run.
+    Random,
+}
+
+/// Summary latency drawn as a horizontal line across the --graph scatter.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Band {
+    Min,
+    P50,
+    P99,
+    Max,
+}
+
+impl Band {
+    /// Latency of the band, if the volley has a meaningful one.
+    fn latency(&self, stats: &VolleyStats) -> Option<Duration> {
+        match self {
+            Band::Min => Some(stats.min),
+            Band::P50 => stats.percentiles.then_some(stats.percentile50),
+            Band::P99 => stats.percentiles.then_some(stats.percentile99),
+            Band::Max => Some(stats.max),
+        }
+    }
+}
+
+/// A graph dimension in dots, two per column and four per row of the terminal.
+#[derive(Clone, Copy, Debug)]
+enum GraphSize {
+    /// Fit the terminal at the time the graph is drawn.
+    Auto,
+    Fixed(u32),
+}
+
+impl FromStr for GraphSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(GraphSize::Auto);
+        }
+        match s.parse::<u32>() {
+            Ok(size) => Ok(GraphSize::Fixed(size)),
+            Err(_) => Err(format!(
+                "invalid graph size '{}', expected a number or auto",
+                s
+            )),
+        }
+    }
+}
+
+/// Graph size used for auto when the terminal size is unknown.
+const DEFAULT_GRAPH_WIDTH: u32 = 300;
+const DEFAULT_GRAPH_HEIGHT: u32 = 100;
+/// Width of the bar of the fullest --histogram bucket, in characters.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Columns and rows of the terminal on stdout, if it is one.
+fn terminal_size() -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret != 0 || size.ws_col == 0 || size.ws_row == 0 {
+        return None;
+    }
+    Some((size.ws_col, size.ws_row))
+}
+
+/// Width and height to draw a graph with, fitting auto dimensions to the terminal.
+fn graph_dimensions(args: &ProgramArgs) -> (u32, u32) {
+    let terminal = match (args.graph_width, args.graph_height) {
+        (GraphSize::Fixed(_), GraphSize::Fixed(_)) => None,
+        _ => terminal_size(),
+    };
+    // Leave room for the axis labels beside and below the chart, and the title line above.
+    let width = match (args.graph_width, terminal) {
+        (GraphSize::Fixed(width), _) => width,
+        (GraphSize::Auto, None) => DEFAULT_GRAPH_WIDTH,
+        (GraphSize::Auto, Some((columns, _))) => (columns.saturating_sub(12) as u32 * 2).max(32),
+    };
+    let height = match (args.graph_height, terminal) {
+        (GraphSize::Fixed(height), _) => height,
+        (GraphSize::Auto, None) => DEFAULT_GRAPH_HEIGHT,
+        (GraphSize::Auto, Some((_, rows))) => (rows.saturating_sub(4) as u32 * 4).max(3),
+    };
+    (width, height)
+}
+
+/// How targets are probed.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Probe {
+    /// ICMP echo requests.
+    Icmp,
+    /// TCP connections to --port, timed until the target accepts or refuses them.
+    Tcp,
+    /// UDP datagrams to --port, timed until a UDP echo server echoes them or the target reports
+    /// the port unreachable.
+    Udp,
+    /// HTTP requests to --http-path, timed to the first byte of the response.
+    Http,
+    /// HTTPS requests to --http-path, timed to the first byte of the response including the TLS
+    /// handshake.
+    Https,
+    /// DNS queries for --qname, sent to the target as a resolver.
+    Dns,
+    /// TLS handshakes, timed from the first message to completion on a new connection each.
+    Tls,
+    /// QUIC version negotiation, timing how quickly an HTTP/3 endpoint's QUIC stack answers.
+    Quic,
+    /// ARP requests, for IPv4 targets on a local subnet that filter ICMP.
+    Arp,
+    /// ICMP timestamp requests instead of echo requests, also estimating the one-way delays to
+    /// IPv4 targets that support them.
+    IcmpTimestamp,
+}
+
+/// Metric --compare-targets ranks targets by, lowest first.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum RankBy {
+    Avg,
+    P99,
+    Loss,
+    Jitter,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Trace the path to a target with echo requests of increasing hop limits, printing the
+    /// latency of each hop.
+    Trace(TraceArgs),
 }
 
 #[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true)]
 struct ProgramArgs {
+    /// How to probe targets. tcp and udp probe --port instead of sending echo requests, for
+    /// targets behind firewalls that drop ICMP, and http and https measure application-level
+    /// latency, as does dns of a resolver, and tls and quic of an edge. None of them need
+    /// privileges. arp reaches hosts on the local network that filter ICMP, and like icmp needs
+    /// root or CAP_NET_RAW.
+    #[arg(long, default_value = "icmp", conflicts_with = "steady_rate")]
+    probe: Probe,
+
+    /// Port to probe with --probe tcp or udp. A closed port still measures latency, since the
+    /// target refuses the connection or reports the port unreachable in one round trip. With
+    /// --probe http or https, defaults to the scheme's port, with --probe dns to 53 and with
+    /// --probe tls or quic to 443.
+    #[arg(long, required_if_eq_any([("probe", "tcp"), ("probe", "udp")]))]
+    port: Option<u16>,
+
+    /// Path requested with --probe http or https.
+    #[arg(long, default_value = "/")]
+    http_path: String,
+
+    /// Method of --probe http or https requests.
+    #[arg(long, default_value = "get")]
+    http_method: http::Method,
+
+    /// Name to query with --probe dns, for its A records. Responses with any answer, including
+    /// that the name doesn't exist, are replies; failures such as SERVFAIL count as lost.
+    #[arg(long, required_if_eq("probe", "dns"))]
+    qname: Option<String>,
+
+    /// Make every --probe tls handshake a full one instead of resuming the session of an earlier
+    /// connection, as a first visit to the site would.
+    #[arg(long)]
+    tls_no_resumption: bool,
+
     /// Number of pings to send per volley
     #[arg(short, long, default_value = "1000")]
     count: usize,
@@ -29,229 +307,2508 @@ struct ProgramArgs {
     #[arg(short, long, default_value = "0.01")]
     interval: f32,
 
+    /// Busy-wait for the last moments before each ping instead of only sleeping, so intervals
+    /// below a millisecond are honored. Keeps a CPU core fully busy while sending.
+    #[arg(long)]
+    precise_pacing: bool,
+
+    /// How the time between pings is chosen, with --interval as the mean for poisson.
+    #[arg(long, default_value = "periodic")]
+    interval_distribution: IntervalDistribution,
+
     /// Payload size in bytes.
     #[arg(short, long, default_value = "64")]
     size: usize,
 
+    /// Payload content: all zeros (low) or random (high), to compare links that compress
+    /// traffic.
+    #[arg(long, default_value = "high", conflicts_with_all = ["payload_set", "pattern"])]
+    entropy: Entropy,
+
+    /// Payload content, repeated to --size: `zero`, `random`, bytes in hex like ping -p (e.g.
+    /// `ff00`), or text as `ascii:<text>`. Useful to trigger link errors that depend on the
+    /// data, e.g. long runs of ones or zeros.
+    #[arg(
+        long,
+        value_name = "zero|random|HEX|ascii:TEXT",
+        value_parser = parse_pattern,
+        conflicts_with = "payload_set"
+    )]
+    pattern: Option<Pattern>,
+
+    /// File with the gaps between pings in milliseconds, one per line, used in rotation instead
+    /// of --interval. The list starts over when it runs out before --count pings are sent.
+    #[arg(long, value_name = "FILE", conflicts_with = "steady_rate")]
+    timing_file: Option<PathBuf>,
+
+    /// File with payloads to send in rotation, one per line. Overrides --size.
+    #[arg(long)]
+    payload_set: Option<PathBuf>,
+
+    /// Maximum number of requests awaiting a reply or timeout at once. Sending pauses while the
+    /// window is full.
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    window: Option<usize>,
+
+    /// Most requests to send, and replies to read, per syscall, up to 64. Requests due within
+    /// 1 ms of each other are sent together, which cuts the syscall overhead of intervals of a
+    /// millisecond or less.
+    #[arg(
+        long,
+        default_value = "1",
+        conflicts_with_all = ["window", "steady_rate"],
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=64)
+    )]
+    batch_size: usize,
+
+    /// DSCP values to mark requests with, separated by commas. With several values, requests
+    /// rotate through them and latency is also summarized per value.
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..64))]
+    dscp: Vec<u8>,
+
+    /// Mark requests with this ECN codepoint, ECT(0) if none is given, and report the
+    /// codepoints of IPv4 replies in verbose output to see whether the path preserves, clears or
+    /// marks them. Only meaningful if the target echoes the codepoint back.
+    #[arg(long, num_args = 0..=1, default_missing_value = "ect0")]
+    ecn: Option<Ecn>,
+
+    /// Send requests with this TTL, the hop limit over IPv6, so that they only go that many
+    /// hops. Routers where it runs out answer with time exceeded errors, reported as ICMP errors.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    ttl: Option<u8>,
+
+    /// Set the don't fragment bit on IPv4 requests, and don't fragment IPv6 ones, so that
+    /// requests too big for the path are dropped and reported instead of fragmented. Only
+    /// supported on Linux.
+    #[arg(long)]
+    df: bool,
+
+    /// Send requests from this local address, to choose which address and interface they leave
+    /// from on a host that has several. Targets are only resolved to addresses of its family.
+    #[arg(long, value_name = "IP", conflicts_with = "dual")]
+    source: Option<IpAddr>,
+
+    /// Match replies by sequence number alone when a middlebox rewrites the ICMP identifier.
+    /// Exact matches still take precedence, but replies to other pings may be counted as ours.
+    #[arg(long)]
+    lenient_identifier: bool,
+
+    /// Ping over ICMP datagram sockets, which don't need root or CAP_NET_RAW, instead of raw
+    /// sockets. Used automatically if raw sockets can't be opened. On Linux the group of the
+    /// user must be in the net.ipv4.ping_group_range sysctl. ICMP errors aren't received, so
+    /// trace and --discover-pmtu don't work.
+    #[arg(long)]
+    unprivileged: bool,
+
+    /// Time replies when the receiver reads them instead of by the kernel's receive timestamps,
+    /// which leave out scheduling delays of the event loop.
+    #[arg(long)]
+    userspace_timestamps: bool,
+
+    /// Time requests and replies by hardware timestamps of the NIC sending and receiving them,
+    /// for latencies under 100 µs on local links. Needs a NIC that supports them and
+    /// CAP_NET_ADMIN to configure it; packets it doesn't timestamp are timed as usual.
+    #[arg(long, conflicts_with_all = ["userspace_timestamps", "steady_rate"])]
+    hw_timestamps: bool,
+
+    /// Longest a volley may take in seconds, regardless of --count and --timeout. A volley cut
+    /// short reports the requests sent so far and is marked as truncated.
+    #[arg(long, value_name = "SECS", conflicts_with = "steady_rate")]
+    deadline_per_volley: Option<f32>,
+
+    /// Time the tool's own work in sending and receiving each volley and show it next to the
+    /// measured latency in verbose output. Not available with --steady-rate.
+    #[arg(long)]
+    profile: bool,
+
+    /// Accept replies from any source in this subnet (e.g. 192.0.2.0/24) instead of only from
+    /// the target. Can be repeated. Replies to us from other sources are counted and warned
+    /// about.
+    #[arg(long, value_name = "CIDR")]
+    accept_source_cidr: Vec<Cidr>,
+
+    /// Sequence number of the first request in each volley.
+    #[arg(long, default_value = "0")]
+    seq_start: u16,
+
+    /// Accept replies with an invalid ICMP checksum.
+    ///
+    /// Useful where checksum offload leaves received checksums zeroed, but corrupted replies
+    /// are then counted as valid.
+    #[arg(long)]
+    no_verify_checksum: bool,
+
     /// Maximum number of seconds to wait for a reply.
-    #[arg(long, default_value = "1")]
+    #[arg(long, default_value = "1", global = true)]
     timeout: f32,
 
     /// Seconds between each volley.
     #[arg(long, default_value = "0")]
     volley_interval: f32,
 
+    /// Instead of volleys, send this many requests per second to each target without pauses,
+    /// reporting a summary of the requests sent in each --summary-interval.
+    ///
+    /// Volleys wait out the timeout after their last request before the next volley starts,
+    /// so they leave gaps and each request belongs to one volley. A steady rate has no gaps,
+    /// and a summary is reported once the timeout has passed after its interval ends.
+    #[arg(long, value_name = "PPS", conflicts_with_all = ["stagger", "window"])]
+    steady_rate: Option<f64>,
+
+    /// Seconds covered by each summary with --steady-rate.
+    #[arg(long, default_value = "10")]
+    summary_interval: f32,
+
+    /// Spread the volley start times of targets over the volley interval instead of starting
+    /// each target as soon as the previous one is done.
+    #[arg(long)]
+    stagger: Option<Stagger>,
+
+    /// Measure the volleys of up to this many targets at once instead of one target after
+    /// another, each over its own socket and with its own identifier. Volleys are reported as
+    /// they finish.
+    #[arg(
+        long,
+        value_name = "TARGETS",
+        conflicts_with = "steady_rate",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    parallel: Option<usize>,
+
+    /// Seed for --stagger random and --interval-distribution poisson, for reproducible offsets
+    /// and intervals.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Stop after this many volleys, or --steady-rate summaries, per target and print a summary
+    /// of the run. Runs until interrupted by default.
+    #[arg(long)]
+    volleys: Option<usize>,
+
+    /// After each round of volleys, print the targets ranked by --rank-by with the best one
+    /// marked. Use --volleys 1 for a quick comparison.
+    #[arg(long, conflicts_with = "steady_rate")]
+    compare_targets: bool,
+
+    /// Metric --compare-targets ranks by. Jitter is the mean difference in latency between
+    /// consecutive replies.
+    #[arg(long, default_value = "avg", requires = "compare_targets")]
+    rank_by: RankBy,
+
+    /// After each round of volleys, also report all targets' volleys pooled into one summary
+    /// labelled "fleet". Latencies are pooled as samples, so the percentiles are over every reply
+    /// of the round and targets that answer more requests weigh more. Missing and spike indices
+    /// count through the targets' requests in order.
+    #[arg(long, conflicts_with = "steady_rate")]
+    fleet_summary: bool,
+
+    /// Print a line to stderr every given number of seconds while waiting for the next volley.
+    #[arg(long)]
+    heartbeat: Option<f32>,
+
     /// Output format
     #[arg(short, long, default_value = "text")]
     format: Format,
 
+    /// Include the target in the variable names of --format env, e.g. EPINGM_EXAMPLE_COM_AVG_MS,
+    /// so that several targets can be evaluated at once.
+    #[arg(long)]
+    env_per_target: bool,
+
+    /// Precision of the volley timestamps.
+    #[arg(long, default_value = "ms")]
+    timestamp_precision: Precision,
+
+    /// Don't start output with a header recording the version, arguments, start time and
+    /// resolved targets of the run. The header is never written to a terminal.
+    #[arg(long)]
+    no_header: bool,
+
+    /// When to flush results to stdout or output files.
+    #[arg(long, default_value = "line")]
+    output_buffer_flush: OutputFlush,
+
+    /// Print text output as aligned columns under a header row.
+    #[arg(long)]
+    table: bool,
+
+    /// Show sub-millisecond latencies in microseconds in text output.
+    ///
+    /// CSV, NDJSON and --table output always use milliseconds.
+    #[arg(long)]
+    latency_unit_auto: bool,
+
     /// Targets to ping
-    #[arg(required = true)]
+    #[arg(required_unless_present_any = ["replay", "self_test"])]
     target: Vec<String>,
 
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Re-report volleys from a file written with --format ndjson or cbor instead of pinging.
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// Ping loopback with injected bad and duplicate replies to check that replies are
+    /// counted correctly, then exit.
+    #[arg(long, conflicts_with = "replay")]
+    self_test: bool,
+
+    /// Number of times to retry resolving a target, with exponential backoff.
+    #[arg(long, default_value = "0")]
+    retry_resolve: u32,
+
+    /// Ping both the IPv4 and IPv6 address of targets that have both.
+    #[arg(long)]
+    dual: bool,
+
+    /// Write each target's results to its own file in this directory instead of stdout.
+    #[arg(long, value_name = "DIR")]
+    split_output: Option<PathBuf>,
+
+    /// Also send volley summaries to syslog.
+    #[arg(long)]
+    syslog: bool,
+
+    /// Syslog facility to log with.
+    #[arg(long, default_value = "user")]
+    syslog_facility: Facility,
+
+    /// Send syslog messages over UDP to this host:port instead of the local daemon.
+    #[arg(long, requires = "syslog")]
+    syslog_server: Option<String>,
+
+    /// Also send volley summaries to a Graphite server at this host:port over TCP, in the
+    /// plaintext protocol.
+    #[arg(long, value_name = "HOST:PORT")]
+    graphite: Option<String>,
+
+    /// First nodes of the Graphite metric paths, followed by the target and the metric, e.g.
+    /// epingm.example_com.avg_ms.
+    #[arg(long, default_value = "epingm", requires = "graphite")]
+    graphite_prefix: String,
+
+    /// Also publish volley summaries as OpenTelemetry metrics to this OTLP/HTTP endpoint, e.g.
+    /// http://localhost:4318, with the target and ip as attributes. Only plain HTTP with JSON
+    /// encoding is supported.
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// Also write each volley to a database, e.g. sqlite:results.db, creating it if needed.
+    /// Volley summaries go to the volleys table.
+    #[arg(long, value_name = "sqlite:PATH")]
+    store: Option<StoreLocation>,
+
+    /// Also write the result of every request to the pings table of --store.
+    #[arg(long, requires = "store")]
+    store_pings: bool,
+
+    /// Also write the result of every request as a row of a new Parquet file: target, ip, seq,
+    /// send_time, latency_ns, reply_size and lost. The file is completed when the run ends,
+    /// including on Ctrl-C or SIGTERM, which then stop the run after the current volley.
+    #[arg(long, value_name = "FILE")]
+    output_parquet: Option<PathBuf>,
+
+    /// Count replies slower than this many milliseconds as lost in summaries.
+    ///
+    /// They're left out of the latency statistics, jitter and call quality as well. Unlike
+    /// --timeout this doesn't change how long replies are waited for, and raw output still
+    /// records the replies.
+    #[arg(long, value_name = "MS")]
+    slow_as_loss: Option<f64>,
+
+    /// Fewest replies a volley needs for its percentiles to be reported. With fewer, they're
+    /// shown as "-", since a percentile of a handful of samples is mostly noise.
+    #[arg(long, default_value = "10")]
+    min_samples: usize,
+
+    /// Percentiles of latency to report for each volley, separated by commas
+    /// (e.g. 50,90,99,99.9).
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "50,99",
+        value_parser = parse_percentile
+    )]
+    percentiles: Vec<f64>,
+
+    /// Flag replies slower than this many milliseconds, or this multiple of the median
+    /// latency when suffixed with x (e.g. 3x).
+    #[arg(long)]
+    spike_threshold: Option<SpikeThreshold>,
+
+    /// Flag replies more than this many median absolute deviations slower than the median as
+    /// outliers, and print their sequence numbers and latencies. The deviation is scaled to be
+    /// comparable to a standard deviation, so 3 flags what a 3 sigma rule would.
+    #[arg(long, value_name = "K")]
+    outlier_mads: Option<f64>,
+
+    /// Count replies with a payload smaller than this many bytes as size violations.
+    #[arg(long)]
+    min_reply_size: Option<usize>,
+
+    /// Count replies with a payload larger than this many bytes as size violations.
+    #[arg(long)]
+    max_reply_size: Option<usize>,
+
+    /// Exit with an error after a volley with reply size violations.
+    #[arg(long)]
+    fail_on_size_violation: bool,
+
+    /// Flag volleys where more than this share of replies (0-1) arrive out of order.
+    #[arg(long)]
+    warn_on_reorder_ratio: Option<f64>,
+
+    /// Exit with an error after a volley flagged by --warn-on-reorder-ratio.
+    #[arg(long, requires = "warn_on_reorder_ratio")]
+    fail_on_reorder: bool,
+
+    /// De-jitter buffer sizes in milliseconds to estimate the share of late replies for,
+    /// separated by commas.
+    #[arg(long, value_delimiter = ',')]
+    jitter_buffer: Vec<f64>,
+
+    /// Compare volleys with a previous run captured with --format ndjson or cbor.
+    #[arg(long, value_name = "FILE")]
+    compare_baseline: Option<PathBuf>,
+
+    /// Growth over the baseline counted as a regression: percent for avg and 99th latency,
+    /// percentage points for loss.
+    #[arg(long, default_value = "10")]
+    regression_threshold: f64,
+
+    /// Also report the 99th percentile latency of each target's most recent replies, over this
+    /// many across volleys. It's approximate, within 0.5% of the exact value.
+    #[arg(
+        long,
+        value_name = "PACKETS",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    rolling_p99: Option<usize>,
+
+    /// Also report exponentially weighted moving averages of each target's average latency and
+    /// loss across volleys, giving the newest volley this weight (0-1). Smaller weights smooth
+    /// more.
+    #[arg(long, value_name = "WEIGHT", value_parser = parse_ewma_weight)]
+    ewma: Option<f64>,
+
+    /// Serve metrics of each target for Prometheus at http://ADDR/metrics, e.g. 127.0.0.1:9100:
+    /// request totals, the loss and latencies of the latest volley and the --rolling-p99 latency.
+    #[arg(long, value_name = "ADDR", visible_alias = "metrics-listen")]
+    prometheus_listen: Option<String>,
+
+    /// Shell command to run when a volley exceeds a --breach-* threshold, without waiting for it.
+    /// It gets EPINGM_TARGET, EPINGM_IP, EPINGM_METRIC (loss, avg or p99), EPINGM_VALUE and
+    /// EPINGM_THRESHOLD in its environment.
+    #[arg(long, value_name = "COMMAND")]
+    on_breach: Option<String>,
+
+    /// Run --on-breach when more than this percentage of a volley's requests is lost.
+    #[arg(long, value_name = "PERCENT", requires = "on_breach")]
+    breach_loss: Option<f64>,
+
+    /// Run --on-breach when a volley's average latency exceeds this many milliseconds.
+    #[arg(long, value_name = "MS", requires = "on_breach")]
+    breach_avg: Option<f64>,
+
+    /// Run --on-breach when a volley's 99th percentile latency exceeds this many milliseconds.
+    #[arg(long, value_name = "MS", requires = "on_breach")]
+    breach_p99: Option<f64>,
+
+    /// Seconds after running --on-breach for a target and metric during which further breaches
+    /// of it don't run the command again.
+    #[arg(long, value_name = "SECS", default_value = "300")]
+    breach_cooldown: f32,
+
+    /// Keep the totals of each target in this file, saving them every minute and on exit, and
+    /// resume from it on start. The file is versioned JSON; a file written by an incompatible
+    /// version is rejected instead of being overwritten.
+    #[arg(long, value_name = "FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Before the first volley, find the path MTU to each target by sending echo requests of
+    /// varying sizes with the don't fragment bit set, and print it.
+    #[arg(long)]
+    discover_pmtu: bool,
+
+    /// After each volley, estimate one-way delays to IPv4 targets from ICMP timestamp
+    /// requests. Only meaningful if both clocks are synchronized. With --probe icmp-timestamp,
+    /// the volley's own replies are used instead.
+    #[arg(long)]
+    one_way_delay: bool,
+
+    /// Number of ICMP timestamp requests sent for --one-way-delay.
+    #[arg(long, default_value = "10")]
+    one_way_samples: usize,
+
+    /// Bandwidth of the link in bits per second, with an optional k, M or G suffix. Verbose
+    /// output then includes the bandwidth-delay product.
+    #[arg(long, value_name = "BPS", value_parser = parse_bits_per_second)]
+    link_bandwidth: Option<f64>,
+
     /// Display a graph of the ping results.
     #[arg(long)]
     graph: bool,
 
-    /// Graph width.
+    /// Print a histogram of each volley's latencies, and add the count of each bucket to its
+    /// summary.
+    #[arg(long)]
+    histogram: bool,
+
+    /// Upper bounds of the --histogram buckets in milliseconds, separated by commas. Replies
+    /// slower than all of them fall in a last bucket.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1,2,5,10,20,50,100,200,500,1000",
+        value_parser = parse_bucket_bound
+    )]
+    histogram_buckets: Vec<f64>,
+
+    /// Summary latencies to draw as horizontal lines on the --graph scatter, separated by
+    /// commas.
+    #[arg(long, value_delimiter = ',', requires = "graph")]
+    graph_percentile_bands: Vec<Band>,
+
+    /// Display a graph of the loss ratio of each target over its recent volleys.
+    #[arg(long)]
+    graph_loss: bool,
+
+    /// Number of volleys shown in the --graph-loss graph.
+    #[arg(
+        long,
+        default_value = "60",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    graph_loss_window: usize,
+
+    /// Graph width, or auto to fit the terminal.
     #[arg(long, default_value = "300")]
-    graph_width: u32,
+    graph_width: GraphSize,
 
-    /// Graph height.
+    /// Graph height, or auto to fit the terminal.
     #[arg(long, default_value = "100")]
-    graph_height: u32,
+    graph_height: GraphSize,
 
     /// Graph maximum latency.
     #[arg(long, default_value = "0.1")]
     graph_max_latency: f32,
+
+    /// Print additional diagnostics after each volley. Repeat for debug messages.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Suppress informational messages on stderr.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
 }
 
 fn secs_to_duration(secs: f32) -> Duration {
     Duration::from_nanos((secs * 1e9) as u64)
 }
 
-fn resolve(target: &str) -> io::Result<IpAddr> {
-    match (target.to_string() + ":0").to_socket_addrs() {
-        Err(e) => Err(io::Error::new(
-            e.kind(),
-            format!("Failed to resolve {}: {}", target, e),
+/// Parses a rate in bits per second, with an optional k, M or G suffix.
+fn parse_bits_per_second(s: &str) -> Result<f64, String> {
+    let (value, multiplier) = match s.chars().last() {
+        Some('k' | 'K') => (&s[..s.len() - 1], 1e3),
+        Some('M') => (&s[..s.len() - 1], 1e6),
+        Some('G') => (&s[..s.len() - 1], 1e9),
+        _ => (s, 1.0),
+    };
+    match value.parse::<f64>() {
+        Ok(value) if value > 0.0 => Ok(value * multiplier),
+        _ => Err(format!(
+            "invalid bandwidth '{}', expected bits per second (e.g. 100M)",
+            s
         )),
-        Ok(mut addrs) => match addrs.next() {
-            None => Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                "No addresses found",
-            )),
-            Some(addr) => Ok(addr.ip()),
-        },
     }
 }
 
-fn run(args: ProgramArgs) {
-    let count = args.count;
-    let interval = secs_to_duration(args.interval);
-    let timeout = secs_to_duration(args.timeout);
-    let volley_interval = secs_to_duration(args.volley_interval);
-    let targets = args.target;
-    let format = args.format;
+fn parse_percentile(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+        _ => Err(format!(
+            "invalid percentile '{}', expected a number from 0 to 100",
+            s
+        )),
+    }
+}
 
-    for target in &targets {
-        match resolve(target) {
-            Err(e) => {
-                eprintln!("{}", e);
-                return;
-            }
-            Ok(_) => {}
-        };
+fn parse_ewma_weight(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(weight) if weight > 0.0 && weight <= 1.0 => Ok(weight),
+        _ => Err(format!(
+            "invalid weight '{}', expected a number above 0 and at most 1",
+            s
+        )),
     }
+}
 
-    match format {
-        Format::Text => {}
-        Format::Csv => {
-            println!("time,target,ip,received,sent,lost,avg,min,max,50th,99th,missing");
+fn parse_bucket_bound(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(bound) if bound > 0.0 && bound.is_finite() => Ok(bound),
+        _ => Err(format!(
+            "invalid bucket bound '{}', expected a positive number of milliseconds",
+            s
+        )),
+    }
+}
+
+/// Content of payloads given with --pattern.
+#[derive(Clone, Debug)]
+enum Pattern {
+    Zero,
+    Random,
+    /// Bytes repeated to fill the payload.
+    Bytes(Vec<u8>),
+}
+
+/// Parses `zero`, `random`, `ascii:` followed by text, or hex digits, two per byte, with an
+/// optional 0x prefix.
+fn parse_pattern(s: &str) -> Result<Pattern, String> {
+    match s {
+        "zero" => return Ok(Pattern::Zero),
+        "random" => return Ok(Pattern::Random),
+        _ => {}
+    }
+    if let Some(text) = s.strip_prefix("ascii:") {
+        if text.is_empty() || !text.is_ascii() {
+            return Err(format!(
+                "invalid pattern '{}', expected ASCII text after ascii:",
+                s
+            ));
         }
+        return Ok(Pattern::Bytes(text.as_bytes().to_vec()));
+    }
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    let error = || {
+        format!(
+            "invalid pattern '{}', expected zero, random, pairs of hex digits or ascii:<text>",
+            s
+        )
+    };
+    if digits.is_empty() || !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return Err(error());
     }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| error()))
+        .collect::<Result<Vec<u8>, String>>()
+        .map(Pattern::Bytes)
+}
 
-    let mut next_volley = Instant::now();
-    loop {
-        for target in &targets {
-            let addr = match resolve(target) {
-                Err(e) => {
-                    eprintln!("{}", e);
-                    continue;
-                }
-                Ok(addr) => addr,
-            };
+fn read_payload_set(path: &PathBuf) -> io::Result<Vec<Vec<u8>>> {
+    let contents = match fs::read(path) {
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Failed to read {}: {}", path.display(), e),
+            ))
+        }
+        Ok(contents) => contents,
+    };
 
-            let start = chrono::Local::now();
-            let info = match measure_volley(addr, count, args.size, interval, timeout) {
-                VolleyResult::Error(e) => {
-                    eprintln!("Failed to measure volley: {}", e);
-                    continue;
-                }
-                VolleyResult::Success(info) => info,
-            };
+    let payloads: Vec<Vec<u8>> = contents
+        .split(|b| *b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+        .filter(|line| !line.is_empty())
+        .collect();
+    if payloads.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No payloads found in {}", path.display()),
+        ));
+    }
+    Ok(payloads)
+}
 
-            let mut sum = Duration::ZERO;
-            let mut latencies: Vec<u64> = Vec::new();
-            let mut missing: Vec<usize> = Vec::new();
+fn read_timing_file(path: &PathBuf) -> io::Result<Vec<Duration>> {
+    let contents = match fs::read_to_string(path) {
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Failed to read {}: {}", path.display(), e),
+            ))
+        }
+        Ok(contents) => contents,
+    };
 
-            for (i, result) in info.results.iter().enumerate() {
-                match result {
-                    None => {
-                        missing.push(i);
-                    }
-                    Some(PingResult {
-                        latency,
-                        reply_size: _,
-                    }) => {
-                        latencies.push(latency.as_millis() as u64);
-                        sum += latency.clone();
-                    }
-                }
+    let mut gaps = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.parse::<f64>() {
+            Ok(ms) if ms >= 0.0 && ms.is_finite() => {
+                gaps.push(Duration::from_secs_f64(ms / 1000.0))
             }
-
-            let timeout_millis = timeout.as_millis() as u64;
-            let avg;
-            if info.received > 0 {
-                avg = (sum / info.received as u32).as_millis() as u64;
-            } else {
-                avg = timeout_millis;
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}:{}: invalid gap '{}', expected milliseconds",
+                        path.display(),
+                        i + 1,
+                        line
+                    ),
+                ))
             }
+        }
+    }
+    if gaps.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No gaps found in {}", path.display()),
+        ));
+    }
+    Ok(gaps)
+}
 
-            latencies.sort();
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Family {
+    V4,
+    V6,
+}
 
-            let min;
-            let max;
-            let percentile50;
-            let percentile99;
-            if latencies.len() > 0 {
-                min = latencies.first().unwrap().clone();
-                max = latencies.last().unwrap().clone();
-                percentile50 = latencies[(latencies.len() as f64 * 0.50) as usize];
-                percentile99 = latencies[(latencies.len() as f64 * 0.99) as usize];
-            } else {
-                min = timeout_millis;
-                max = timeout_millis;
-                percentile50 = timeout_millis;
-                percentile99 = timeout_millis;
-            }
-
-            let lost = count - info.received;
-
-            match format {
-                Format::Text => {
-                    println!(
-                        "[{}] {} ({}): received: {}/{}, lost: {}, avg: {} ms, min: {} ms, max: {} ms, 50th: {} ms, 99th: {} ms, missing: {:?}",
-                        start.format("%Y-%m-%d %H:%M:%S"),
-                        target,
-                        addr,
-                        info.received,
-                        info.sent,
-                        lost,
-                        avg,
-                        min,
-                        max,
-                        percentile50,
-                        percentile99,
-                        missing
-                    );
-                }
-                Format::Csv => {
-                    println!(
-                        "{},{},{},{},{},{},{},{},{},{},{},{:?}",
-                        start.format("%Y-%m-%d %H:%M:%S"),
-                        target,
-                        addr,
-                        info.received,
-                        info.sent,
-                        lost,
-                        avg,
-                        min,
-                        max,
-                        percentile50,
-                        percentile99,
-                        missing
-                    );
-                }
-            }
+impl Family {
+    fn of(addr: &IpAddr) -> Family {
+        match addr {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+}
 
-            if args.graph {
-                let mut values: Vec<(f32, f32)> = Vec::new();
-                for (i, result) in info.results.iter().enumerate() {
-                    match result {
-                        None => {}
-                        Some(PingResult {
-                            latency,
-                            reply_size: _,
-                        }) => {
-                            values.push((i as f32, latency.as_nanos() as f32 / 1e6));
-                        }
-                    }
-                }
+struct Target {
+    /// Name shown in the output.
+    label: String,
+    host: String,
+    /// Restricts resolution to one address family.
+    family: Option<Family>,
+}
+
+const MAX_RESOLVE_BACKOFF: Duration = Duration::from_secs(30);
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Smallest difference between wall-clock and monotonic time reported as a clock step.
+const CLOCK_STEP_THRESHOLD: Duration = Duration::from_secs(1);
+/// Target label of --fleet-summary lines.
+const FLEET_LABEL: &str = "fleet";
+
+fn resolve(target: &str, family: Option<Family>) -> io::Result<IpAddr> {
+    match (target, 0).to_socket_addrs() {
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("Failed to resolve {}: {}", target, e),
+        )),
+        Ok(mut addrs) => {
+            match addrs.find(|addr| family.is_none() || family == Some(Family::of(&addr.ip()))) {
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No addresses found",
+                )),
+                Some(addr) => Ok(addr.ip()),
+            }
+        }
+    }
+}
 
-                Chart::new_with_y_range(
-                    args.graph_width,
-                    args.graph_height,
-                    0.0,
-                    (count - 1) as f32,
-                    0.0,
-                    args.graph_max_latency * 1000.0,
-                )
-                .lineplot(&Shape::Points(&values))
-                .x_label_format(LabelFormat::None)
-                .display();
+/// Resolves `target`, retrying up to `retries` times with exponential backoff.
+fn resolve_with_retry(target: &str, family: Option<Family>, retries: u32) -> io::Result<IpAddr> {
+    let mut backoff = Duration::from_millis(500);
+    let mut attempt = 0;
+    loop {
+        match resolve(target, family) {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                log::debug!("{} (retry {}/{} in {:?})", e, attempt, retries, backoff);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_RESOLVE_BACKOFF);
             }
+            result => return result,
         }
+    }
+}
 
-        next_volley += volley_interval;
-        if next_volley > Instant::now() {
-            let sleep_duration = next_volley - Instant::now();
-            thread::sleep(sleep_duration);
+/// Turns the hosts given on the command line into targets, checking that each one resolves.
+///
+/// With `dual`, hosts that have both IPv4 and IPv6 addresses are split into two targets. With
+/// `family`, hosts are only resolved to addresses of that family.
+fn prepare_targets(
+    hosts: &[String],
+    dual: bool,
+    family: Option<Family>,
+    retries: u32,
+) -> io::Result<Vec<Target>> {
+    let mut targets = Vec::new();
+    for host in hosts {
+        resolve_with_retry(host, family, retries)?;
+
+        if dual
+            && resolve(host, Some(Family::V4)).is_ok()
+            && resolve(host, Some(Family::V6)).is_ok()
+        {
+            targets.push(Target {
+                label: format!("{}/ipv4", host),
+                host: host.clone(),
+                family: Some(Family::V4),
+            });
+            targets.push(Target {
+                label: format!("{}/ipv6", host),
+                host: host.clone(),
+                family: Some(Family::V6),
+            });
+        } else {
+            targets.push(Target {
+                label: host.clone(),
+                host: host.clone(),
+                family,
+            });
+        }
+    }
+    Ok(targets)
+}
+
+/// Reports replies that arrived on a different interface than the requests were sent from.
+fn report_interfaces(info: &VolleyInfo) {
+    let sent_via = match info.interface {
+        None => return,
+        Some(index) => index,
+    };
+
+    let mut received_via: Vec<(u32, usize)> = Vec::new();
+    for result in info.results.iter().flatten() {
+        let index = match result.interface {
+            Some(index) if index != sent_via => index,
+            _ => continue,
+        };
+        match received_via.iter_mut().find(|(i, _)| *i == index) {
+            Some((_, count)) => *count += 1,
+            None => received_via.push((index, 1)),
+        }
+    }
+
+    for (index, count) in received_via {
+        eprintln!(
+            "  {} replies received via {}, requests sent via {}",
+            count,
+            socket::interface_name(index),
+            socket::interface_name(sent_via)
+        );
+    }
+}
+
+/// Reports replies that arrived fragmented, or were reassembled from fragments, on the return
+/// path. Only IPv4 replies carry the header details this relies on.
+fn report_fragmentation(info: &VolleyInfo) {
+    let mut fragments: Vec<u16> = Vec::new();
+    let mut reassembled: Vec<u16> = Vec::new();
+    let mut mtus: HashMap<u32, Option<usize>> = HashMap::new();
+    for result in info.results.iter().flatten() {
+        let header = match result.ip_header {
+            None => continue,
+            Some(header) => header,
+        };
+        let mtu = match result.interface.or(info.interface) {
+            None => None,
+            Some(index) => *mtus
+                .entry(index)
+                .or_insert_with(|| socket::interface_mtu(index)),
+        };
+        if header.fragment {
+            fragments.push(header.identification);
+        } else if mtu.is_some_and(|mtu| header.total_length > mtu) {
+            reassembled.push(header.identification);
+        }
+    }
+
+    if !fragments.is_empty() {
+        eprintln!(
+            "  {} replies arrived as fragments, IP IDs: {:?}",
+            fragments.len(),
+            fragments
+        );
+    }
+    if !reassembled.is_empty() {
+        eprintln!(
+            "  {} replies exceeded the interface MTU and were reassembled, IP IDs: {:?}",
+            reassembled.len(),
+            reassembled
+        );
+    }
+}
+
+/// Reports the ECN codepoints replies arrived with, compared to the one requests were sent with.
+fn report_ecn(sent: Ecn, info: &VolleyInfo) {
+    let mut counts = [0usize; 4];
+    for result in info.results.iter().flatten() {
+        if let Some(header) = result.ip_header {
+            counts[header.ecn as usize & 0b11] += 1;
+        }
+    }
+    let total: usize = counts.iter().sum();
+    if total == 0 {
+        return;
+    }
+
+    let sent = sent as usize;
+    let path = if counts[0b11] > 0 && sent != 0b11 {
+        "marked congestion"
+    } else if counts[sent] == total {
+        "preserved"
+    } else if counts[0] == total {
+        "cleared"
+    } else {
+        "changed"
+    };
+    let counts: Vec<String> = (0..4)
+        .filter(|&ecn| counts[ecn] > 0)
+        .map(|ecn| format!("{} {}", volley::ecn_name(ecn as u8), counts[ecn]))
+        .collect();
+    eprintln!(
+        "  ECN sent {}, replies: {} ({})",
+        volley::ecn_name(sent as u8),
+        counts.join(", "),
+        path
+    );
+}
+
+/// Reports how much data must be in flight to fill a link of `bandwidth` bits per second,
+/// taking the minimum latency as the propagation delay.
+fn report_bandwidth_delay_product(
+    bandwidth: f64,
+    addr: IpAddr,
+    info: &VolleyInfo,
+    stats: &VolleyStats,
+) {
+    if info.received == 0 {
+        return;
+    }
+    let bytes = bandwidth / 8.0 * stats.min.as_secs_f64();
+    let ip_header_size = match addr {
+        IpAddr::V4(_) => 20,
+        IpAddr::V6(_) => 40,
+    };
+    let reply_size = info
+        .results
+        .iter()
+        .flatten()
+        .map(|r| r.reply_size)
+        .max()
+        .unwrap_or(0);
+    let packet_size = ip_header_size + 8 + reply_size;
+    eprintln!(
+        "  bandwidth-delay product: {:.0} bytes, {:.1} packets of {} bytes",
+        bytes,
+        bytes / packet_size as f64,
+        packet_size
+    );
+}
+
+/// Destination for formatted results, along with its header state.
+struct Sink {
+    writer: Box<dyn Write>,
+    table: Table,
+    csv_header_printed: bool,
+}
+
+impl Sink {
+    fn new(writer: Box<dyn Write>, table: Table) -> Sink {
+        Sink {
+            writer,
+            table,
+            csv_header_printed: false,
+        }
+    }
+}
+
+/// Terminates a line of text output.
+fn line(text: String) -> Vec<u8> {
+    let mut bytes = text.into_bytes();
+    bytes.push(b'\n');
+    bytes
+}
+
+/// Turns a target label into something safe to use as a file name.
+fn file_name(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// A target's volley in a --compare-targets ranking.
+struct Ranked<'a> {
+    label: &'a str,
+    addr: IpAddr,
+    stats: VolleyStats,
+    jitter: Option<Duration>,
+    /// Value of the --rank-by metric, if the volley has one.
+    value: Option<f64>,
+}
+
+struct Reporter<'a> {
+    args: &'a ProgramArgs,
+    stats_options: StatsOptions,
+    stdout: Sink,
+    /// Per-target sinks used with --split-output.
+    split_sinks: HashMap<String, Sink>,
+    syslog: Option<Syslog>,
+    graphite: Option<Graphite>,
+    otlp: Option<OtlpExporter>,
+    store: Option<SqliteStore>,
+    /// Writer of --output-parquet, until `finish` closes it.
+    parquet: Option<ParquetWriter>,
+    baseline: Option<Baseline>,
+    aggregates: HashMap<String, TargetAggregate>,
+    /// Number of volleys reported so far, by target.
+    volleys: HashMap<String, usize>,
+    /// When the aggregates were last saved to --state-file.
+    state_saved: Instant,
+    prometheus: Option<Exporter>,
+    on_breach: Option<OnBreach>,
+    breach_thresholds: Thresholds,
+    /// Monotonic and wall-clock time of the previous volley, to detect clock steps.
+    clock: Option<(Instant, DateTime<Local>)>,
+    /// Header written at the start of each output, unless --no-header is given.
+    metadata: Option<MetadataRecord>,
+}
+
+/// Formats a record that annotates the output, as `comment` in text formats. JSON Lines output
+/// only has volleys, so annotations are left out.
+fn annotation<T: Serialize>(format: &Format, comment: String, record: &T) -> io::Result<Vec<u8>> {
+    match format {
+        Format::Jsonl => Ok(Vec::new()),
+        Format::Text | Format::Csv | Format::CsvRaw | Format::Env => Ok(line(comment)),
+        Format::Ndjson | Format::Json => Ok(line(serde_json::to_string(record)?)),
+        Format::Cbor => {
+            let mut bytes = Vec::new();
+            if let Err(e) = ciborium::into_writer(record, &mut bytes) {
+                return Err(io::Error::other(e.to_string()));
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+/// Formats the metadata header in the output format.
+fn metadata_header(format: &Format, metadata: &MetadataRecord) -> io::Result<Vec<u8>> {
+    annotation(format, metadata.metadata.comment(), metadata)
+}
+
+impl<'a> Reporter<'a> {
+    fn new(args: &'a ProgramArgs, table: Table, metadata: Metadata) -> io::Result<Reporter<'a>> {
+        if let Some(dir) = &args.split_output {
+            if let Err(e) = fs::create_dir_all(dir) {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("Failed to create {}: {}", dir.display(), e),
+                ));
+            }
+        }
+
+        let syslog = match (args.syslog, &args.syslog_server) {
+            (false, _) => None,
+            (true, None) => Some(Syslog::local(args.syslog_facility)),
+            (true, Some(server)) => Some(Syslog::remote(server, args.syslog_facility)),
+        };
+        let syslog = match syslog {
+            None => None,
+            Some(Err(e)) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("Failed to connect to syslog: {}", e),
+                ))
+            }
+            Some(Ok(syslog)) => Some(syslog),
+        };
+
+        let graphite = match &args.graphite {
+            None => None,
+            Some(server) => match Graphite::connect(server, &args.graphite_prefix) {
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("Failed to connect to Graphite at {}: {}", server, e),
+                    ))
+                }
+                Ok(graphite) => Some(graphite),
+            },
+        };
+
+        let otlp = match &args.otlp_endpoint {
+            None => None,
+            Some(url) => match OtlpExporter::new(url) {
+                Err(e) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid --otlp-endpoint: {}", e),
+                    ))
+                }
+                Ok(otlp) => Some(otlp),
+            },
+        };
+
+        let store = match &args.store {
+            None => None,
+            Some(StoreLocation::Sqlite(path)) => Some(SqliteStore::open(path, args.store_pings)?),
+        };
+
+        let parquet = match &args.output_parquet {
+            None => None,
+            Some(path) => Some(ParquetWriter::create(path)?),
+        };
+
+        let baseline = match &args.compare_baseline {
+            None => None,
+            Some(path) => Some(Baseline::load(
+                path,
+                secs_to_duration(args.timeout),
+                args.regression_threshold,
+            )?),
+        };
+
+        let aggregates = match &args.state_file {
+            None => HashMap::new(),
+            Some(path) => aggregate::load_state(path)?,
+        };
+
+        let prometheus = match &args.prometheus_listen {
+            None => None,
+            Some(addr) => match Exporter::listen(addr) {
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("Failed to listen on {}: {}", addr, e),
+                    ))
+                }
+                Ok(exporter) => Some(exporter),
+            },
+        };
+
+        let metadata = if args.no_header {
+            None
+        } else {
+            Some(MetadataRecord { metadata })
+        };
+        let mut stdout = match args.output_buffer_flush {
+            OutputFlush::Line => Sink::new(Box::new(io::stdout()), table),
+            OutputFlush::Buffered => Sink::new(Box::new(BufWriter::new(io::stdout())), table),
+        };
+        // The header documents archived output, so leave it out on a terminal.
+        if let Some(metadata) = &metadata {
+            if !io::stdout().is_terminal() {
+                stdout
+                    .writer
+                    .write_all(&metadata_header(&args.format, metadata)?)?;
+            }
+        }
+
+        Ok(Reporter {
+            args,
+            stats_options: StatsOptions {
+                timeout: secs_to_duration(args.timeout),
+                spike_threshold: args.spike_threshold,
+                outlier_mads: args.outlier_mads,
+                min_reply_size: args.min_reply_size,
+                max_reply_size: args.max_reply_size,
+                reorder_ratio_threshold: args.warn_on_reorder_ratio,
+                jitter_buffers: args.jitter_buffer.clone(),
+                dscp: args.dscp.clone(),
+                slow_as_loss: args
+                    .slow_as_loss
+                    .map(|ms| Duration::from_secs_f64(ms.max(0.0) / 1000.0)),
+                min_samples: args.min_samples,
+                percentiles: args.percentiles.clone(),
+                histogram_buckets: match args.histogram {
+                    false => Vec::new(),
+                    true => {
+                        let mut bounds = args.histogram_buckets.clone();
+                        bounds.sort_by(f64::total_cmp);
+                        bounds.dedup();
+                        bounds
+                    }
+                },
+            },
+            stdout,
+            split_sinks: HashMap::new(),
+            syslog,
+            graphite,
+            otlp,
+            store,
+            parquet,
+            baseline,
+            aggregates,
+            volleys: HashMap::new(),
+            state_saved: Instant::now(),
+            prometheus,
+            on_breach: args
+                .on_breach
+                .clone()
+                .map(|command| OnBreach::new(command, secs_to_duration(args.breach_cooldown))),
+            breach_thresholds: Thresholds {
+                loss: args.breach_loss,
+                avg: args.breach_avg,
+                p99: args.breach_p99,
+            },
+            clock: None,
+            metadata,
+        })
+    }
+
+    /// Returns the sink results for `label` are written to.
+    fn sink(&mut self, label: &str) -> io::Result<&mut Sink> {
+        let args = self.args;
+        let dir = match &args.split_output {
+            None => return Ok(&mut self.stdout),
+            Some(dir) => dir,
+        };
+
+        if !self.split_sinks.contains_key(label) {
+            let extension = match args.format {
+                Format::Text => "txt",
+                Format::Csv | Format::CsvRaw => "csv",
+                Format::Ndjson => "ndjson",
+                Format::Cbor => "cbor",
+                Format::Json => "json",
+                Format::Jsonl => "jsonl",
+                Format::Env => "env",
+            };
+            let path = dir.join(format!("{}.{}", file_name(label), extension));
+            let file = match OpenOptions::new().create(true).append(true).open(&path) {
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("Failed to open {}: {}", path.display(), e),
+                    ))
+                }
+                Ok(file) => file,
+            };
+            let mut sink = Sink::new(Box::new(BufWriter::new(file)), self.stdout.table.clone());
+            if let Some(metadata) = &self.metadata {
+                sink.writer
+                    .write_all(&metadata_header(&args.format, metadata)?)?;
+            }
+            self.split_sinks.insert(label.to_string(), sink);
+        }
+        Ok(self.split_sinks.get_mut(label).unwrap())
+    }
+
+    /// Saves the aggregates to --state-file, if one is given.
+    fn save_state(&mut self) {
+        if let Some(path) = &self.args.state_file {
+            if let Err(e) = aggregate::save_state(path, &self.aggregates) {
+                eprintln!("Failed to save state to {}: {}", path.display(), e);
+            }
+            self.state_saved = Instant::now();
+        }
+    }
+
+    /// Flushes buffered output and prints a summary of each target over the whole run.
+    fn finish(&mut self) {
+        self.save_state();
+        if let Some(parquet) = self.parquet.take() {
+            if let Err(e) = parquet.close() {
+                eprintln!("Failed to write Parquet output: {}", e);
+            }
+        }
+        for sink in std::iter::once(&mut self.stdout).chain(self.split_sinks.values_mut()) {
+            if let Err(e) = sink.writer.flush() {
+                eprintln!("Failed to write output: {}", e);
+            }
+        }
+
+        let mut labels: Vec<&String> = self.aggregates.keys().collect();
+        labels.sort();
+        for label in labels {
+            let aggregate = &self.aggregates[label];
+            if aggregate.sent > 0 {
+                eprintln!(
+                    "{}: {} of {} requests lost in total ({:.2}%)",
+                    label,
+                    aggregate.lost,
+                    aggregate.sent,
+                    aggregate.lost as f64 * 100.0 / aggregate.sent as f64
+                );
+            }
+            match aggregate.avg_cov() {
+                None => eprintln!("{}: no replies", label),
+                Some(cov) => eprintln!(
+                    "{}: avg latency CoV over {} volleys: {:.3}",
+                    label,
+                    aggregate.volleys(),
+                    cov
+                ),
+            }
+        }
+    }
+
+    /// Annotates every output if the wall clock stepped since the previous call, given the
+    /// current monotonic and wall-clock times.
+    fn check_clock(&mut self, now: Instant, wall: DateTime<Local>) {
+        let previous = self.clock.replace((now, wall));
+        let (previous_now, previous_wall) = match previous {
+            None => return,
+            Some(previous) => previous,
+        };
+        let elapsed = chrono::Duration::from_std(now - previous_now).unwrap_or_default();
+        let expected = previous_wall + elapsed;
+        let step = wall - expected;
+        if step.num_milliseconds().abs() < CLOCK_STEP_THRESHOLD.as_millis() as i64
+            && wall.offset() == previous_wall.offset()
+        {
+            return;
+        }
+
+        let record = ClockStepRecord {
+            clock_step: ClockStep {
+                expected: expected.to_rfc3339(),
+                actual: wall.to_rfc3339(),
+                step_ms: step.num_milliseconds(),
+            },
+        };
+        let bytes = match annotation(&self.args.format, record.clock_step.comment(), &record) {
+            Err(e) => {
+                eprintln!("Failed to serialize clock step: {}", e);
+                return;
+            }
+            Ok(bytes) => bytes,
+        };
+        for sink in std::iter::once(&mut self.stdout).chain(self.split_sinks.values_mut()) {
+            if let Err(e) = sink.writer.write_all(&bytes) {
+                eprintln!("Failed to write output: {}", e);
+            }
+        }
+    }
+
+    /// Prints the targets of a round ranked by --rank-by, best first.
+    fn print_ranking(&self, round: &[(&str, IpAddr, VolleyInfo)]) {
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let mut entries: Vec<Ranked> = round
+            .iter()
+            .map(|(label, addr, info)| {
+                let stats = stats::summarize(info, &self.stats_options);
+                let jitter = stats.mean_deviation;
+                let replied = stats.received > 0;
+                let value = match self.args.rank_by {
+                    RankBy::Avg => replied.then(|| millis(stats.avg)),
+                    RankBy::P99 => stats.percentiles.then(|| millis(stats.percentile99)),
+                    RankBy::Loss => match stats.received + stats.lost {
+                        0 => None,
+                        total => Some(stats.lost as f64 * 100.0 / total as f64),
+                    },
+                    RankBy::Jitter => jitter.map(millis),
+                };
+                Ranked {
+                    label,
+                    addr: *addr,
+                    stats,
+                    jitter,
+                    value,
+                }
+            })
+            .collect();
+        // Targets without a value, e.g. without replies, rank last.
+        entries.sort_by(|a, b| match (a.value, b.value) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        });
+
+        eprintln!("Targets ranked by {:?}:", self.args.rank_by);
+        for (i, entry) in entries.iter().enumerate() {
+            let stats = &entry.stats;
+            let latency = |duration: Duration, shown: bool| match (stats.received, shown) {
+                (0, _) | (_, false) => "-".to_string(),
+                _ => format!("{:.3} ms", millis(duration)),
+            };
+            let jitter = match entry.jitter {
+                None => "-".to_string(),
+                Some(jitter) => format!("{:.1} ms", millis(jitter)),
+            };
+            let winner = i == 0 && entry.value.is_some();
+            let line = format!(
+                "{} {:>2}. {} ({}): avg {}, 99th {}, loss {}/{}, jitter {}",
+                if winner { '*' } else { ' ' },
+                i + 1,
+                entry.label,
+                entry.addr,
+                latency(stats.avg, true),
+                latency(stats.percentile99, stats.percentiles),
+                stats.lost,
+                stats.received + stats.lost,
+                jitter
+            );
+            if winner && io::stderr().is_terminal() {
+                eprintln!("\x1b[1m{}\x1b[0m", line);
+            } else {
+                eprintln!("{}", line);
+            }
+        }
+    }
+
+    /// Writes `fields` to the sink of `label` in the output format, the record built by `record`
+    /// in NDJSON and CBOR, the summary built by `json` in JSON, or the rows built by `raw_csv` in
+    /// raw CSV. Returns false if the sink couldn't be opened.
+    fn write_fields<R: Serialize, J: Serialize>(
+        &mut self,
+        label: &str,
+        fields: &[output::Field],
+        record: impl FnOnce() -> R,
+        json: impl FnOnce() -> J,
+        raw_csv: impl FnOnce() -> String,
+    ) -> bool {
+        let args = self.args;
+        let sink = match self.sink(label) {
+            Err(e) => {
+                eprintln!("{}", e);
+                return false;
+            }
+            Ok(sink) => sink,
+        };
+        let output = match args.format {
+            Format::Text if args.table => line(sink.table.format(fields)),
+            Format::Text => line(output::text_line(fields, args.latency_unit_auto)),
+            Format::Csv => {
+                let mut lines = String::new();
+                if !sink.csv_header_printed {
+                    lines.push_str(&output::csv_header(fields));
+                    lines.push('\n');
+                    sink.csv_header_printed = true;
+                }
+                lines.push_str(&output::csv_row(fields));
+                line(lines)
+            }
+            Format::CsvRaw => {
+                let mut lines = String::new();
+                if !sink.csv_header_printed {
+                    lines.push_str(output::RAW_CSV_HEADER);
+                    lines.push('\n');
+                    sink.csv_header_printed = true;
+                }
+                lines.push_str(&raw_csv());
+                lines.into_bytes()
+            }
+            Format::Ndjson => match serde_json::to_string(&record()) {
+                Err(e) => {
+                    eprintln!("Failed to serialize record: {}", e);
+                    line(String::new())
+                }
+                Ok(json) => line(json),
+            },
+            Format::Json | Format::Jsonl => match serde_json::to_string(&json()) {
+                Err(e) => {
+                    eprintln!("Failed to serialize summary: {}", e);
+                    line(String::new())
+                }
+                Ok(json) => line(json),
+            },
+            Format::Env => {
+                let target = if args.env_per_target {
+                    Some(label)
+                } else {
+                    None
+                };
+                line(output::env_lines(fields, target))
+            }
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                if let Err(e) = ciborium::into_writer(&record(), &mut bytes) {
+                    eprintln!("Failed to serialize record: {}", e);
+                    bytes.clear();
+                }
+                bytes
+            }
+        };
+        let mut written = sink.writer.write_all(&output);
+        if args.output_buffer_flush == OutputFlush::Line {
+            written = written.and_then(|_| sink.writer.flush());
+        }
+        if let Err(e) = written {
+            eprintln!("Failed to write output: {}", e);
+        }
+        true
+    }
+
+    /// Reports the volleys of a round pooled across targets as one summary labelled "fleet".
+    fn report_fleet(&mut self, time: String, round: &[(&str, IpAddr, VolleyInfo)]) -> bool {
+        let args = self.args;
+        let info = VolleyInfo::pool(round.iter().map(|(_, _, info)| info));
+        let stats = stats::summarize(&info, &self.stats_options);
+
+        // The same columns as the targets' lines, so CSV and tables line up.
+        let mut fields = output::summary_fields(time.clone(), FLEET_LABEL, "-", &stats);
+        if self.baseline.is_some() {
+            output::insert_fields(&mut fields, output::comparison_fields(None));
+        }
+        if args.deadline_per_volley.is_some() {
+            output::insert_fields(&mut fields, output::truncated_fields(info.truncated));
+        }
+        if args.rolling_p99.is_some() {
+            output::insert_fields(&mut fields, output::rolling_fields(None));
+        }
+        if args.ewma.is_some() {
+            output::insert_fields(&mut fields, output::ewma_fields(None, None));
+        }
+        let summary = output::text_summary(&fields, args.latency_unit_auto);
+
+        let targets: Vec<String> = round
+            .iter()
+            .map(|(label, _, _)| label.to_string())
+            .collect();
+        let record = || FleetRecord {
+            fleet: FleetSummary::new(time.clone(), targets.clone(), &stats),
+        };
+        // The same shape as the targets' summaries, so JSON consumers read one kind of line.
+        let json = || {
+            let mut summary = VolleySummary::new(time.clone(), FLEET_LABEL, None, &info, &stats);
+            summary.targets = Some(targets.clone());
+            summary
+        };
+        // Requests are in the targets' own rows of raw CSV.
+        if !self.write_fields(FLEET_LABEL, &fields, record, json, String::new) {
+            return false;
+        }
+
+        if let Some(syslog) = &self.syslog {
+            if let Err(e) = syslog.send(&summary) {
+                eprintln!("Failed to send to syslog: {}", e);
+            }
+        }
+        if let Some(graphite) = &mut self.graphite {
+            if let Err(e) = graphite.send(FLEET_LABEL, &stats, Local::now().timestamp()) {
+                eprintln!("Failed to send to Graphite: {}", e);
+            }
+        }
+        if let Some(otlp) = &mut self.otlp {
+            otlp.export(FLEET_LABEL, None, &stats);
+        }
+        true
+    }
+
+    /// Reports a volley, returning false if it should fail the run.
+    fn report(&mut self, time: String, label: &str, addr: IpAddr, info: &VolleyInfo) -> bool {
+        let args = self.args;
+        let stats = stats::summarize(info, &self.stats_options);
+        let aggregate = self.aggregates.entry(label.to_string()).or_default();
+        aggregate.add(
+            info,
+            &stats,
+            args.graph_loss_window,
+            args.rolling_p99,
+            args.ewma,
+        );
+        let rolling_p99 = aggregate.rolling_p99();
+        let (ewma_avg, ewma_loss) = (aggregate.ewma_avg(), aggregate.ewma_loss());
+        if let Some(prometheus) = &self.prometheus {
+            prometheus.update(&self.aggregates);
+        }
+        if let Some(on_breach) = &mut self.on_breach {
+            for breach in breach::breaches(&stats, &self.breach_thresholds) {
+                on_breach.run(label, addr, &breach);
+            }
+        }
+
+        let mut fields = output::summary_fields(time.clone(), label, addr, &stats);
+        let comparison = self.baseline.as_ref().map(|b| b.compare(label, &stats));
+        if let Some(comparison) = &comparison {
+            output::insert_fields(&mut fields, output::comparison_fields(comparison.as_ref()));
+        }
+        if args.deadline_per_volley.is_some() {
+            output::insert_fields(&mut fields, output::truncated_fields(info.truncated));
+        }
+        if args.rolling_p99.is_some() {
+            output::insert_fields(&mut fields, output::rolling_fields(rolling_p99));
+        }
+        if args.ewma.is_some() {
+            output::insert_fields(&mut fields, output::ewma_fields(ewma_avg, ewma_loss));
+        }
+        let summary = output::text_summary(&fields, args.latency_unit_auto);
+
+        let volley = self.volleys.entry(label.to_string()).or_default();
+        let index = *volley;
+        *volley += 1;
+        let record = || VolleyRecord::new(time.clone(), label, addr, info);
+        let json = || {
+            let mut summary = VolleySummary::new(time.clone(), label, Some(addr), info, &stats);
+            summary.ewma_avg_ms = ewma_avg.map(|avg| avg.as_secs_f64() * 1000.0);
+            summary.ewma_loss = ewma_loss.map(|loss| loss * 100.0);
+            summary
+        };
+        let raw_csv = || output::raw_csv_rows(&time, label, addr, index, info);
+        if !self.write_fields(label, &fields, record, json, raw_csv) {
+            return false;
+        }
+
+        if let Some(syslog) = &self.syslog {
+            if let Err(e) = syslog.send(&summary) {
+                eprintln!("Failed to send to syslog: {}", e);
+            }
+        }
+        if let Some(graphite) = &mut self.graphite {
+            if let Err(e) = graphite.send(label, &stats, Local::now().timestamp()) {
+                eprintln!("Failed to send to Graphite: {}", e);
+            }
+        }
+        if let Some(otlp) = &mut self.otlp {
+            otlp.export(label, Some(addr), &stats);
+        }
+        if let Some(store) = &mut self.store {
+            if let Err(e) = store.write(&time, label, addr, info, &stats) {
+                eprintln!("Failed to write to store: {}", e);
+            }
+        }
+        if let Some(parquet) = &mut self.parquet {
+            if let Err(e) = parquet.add(label, addr, info) {
+                eprintln!("Failed to write Parquet output: {}", e);
+            }
+        }
+
+        if args.verbose > 0 {
+            report_interfaces(info);
+            report_fragmentation(info);
+            if let Some(ecn) = args.ecn {
+                report_ecn(ecn, info);
+            }
+            if info.checksum_errors > 0 {
+                eprintln!("  {} replies with invalid checksum", info.checksum_errors);
+            }
+            if !stats.percentiles && stats.received > 0 {
+                eprintln!(
+                    "  percentiles omitted: {} replies, fewer than --min-samples {}",
+                    stats.received, args.min_samples
+                );
+            }
+            if !stats.loss_bursts.is_empty() {
+                let lengths: Vec<String> = stats::burst_lengths(&stats.loss_bursts)
+                    .iter()
+                    .map(|(length, count)| format!("{} x {}", count, length))
+                    .collect();
+                eprintln!("  loss bursts: {}", lengths.join(", "));
+            }
+            report_icmp_errors(info);
+            if info.duplicates > 0 {
+                eprintln!("  {} duplicate replies", info.duplicates);
+            }
+            if info.lenient_matches > 0 {
+                eprintln!(
+                    "  {} replies matched by sequence number only",
+                    info.lenient_matches
+                );
+            }
+            if info.strays.total() > 0 {
+                eprintln!("  stray ICMP: {}", info.strays);
+            }
+            if info.max_in_flight > 0 {
+                eprintln!(
+                    "  requests in flight: max {}, avg {:.1}",
+                    info.max_in_flight, info.avg_in_flight
+                );
+            }
+            if info.window_waits > 0 {
+                eprintln!(
+                    "  {} requests held back by the send window",
+                    info.window_waits
+                );
+            }
+            if let Some(profile) = &info.profile {
+                report_profile(profile, info, &stats);
+            }
+            if let Some(bandwidth) = args.link_bandwidth {
+                report_bandwidth_delay_product(bandwidth, addr, info, &stats);
+            }
+            if let Some(spikes) = &stats.spikes {
+                if !spikes.is_empty() {
+                    eprintln!("  latency spikes at sequence numbers: {:?}", spikes);
+                }
+            }
+        }
+
+        if args.graph {
+            let mut values: Vec<(f32, f32)> = Vec::new();
+            for (i, result) in info.results.iter().enumerate() {
+                match result {
+                    None => {}
+                    Some(PingResult { latency, .. }) => {
+                        values.push((i as f32, latency.as_nanos() as f32 / 1e6));
+                    }
+                }
+            }
+
+            let x_max = (info.results.len().max(1) - 1) as f32;
+            // Without replies the summary latencies are just the timeout.
+            let bands: Vec<[(f32, f32); 2]> = match stats.received {
+                0 => Vec::new(),
+                _ => args
+                    .graph_percentile_bands
+                    .iter()
+                    .filter_map(|band| {
+                        let y = band.latency(&stats)?.as_nanos() as f32 / 1e6;
+                        Some([(0.0, y), (x_max, y)])
+                    })
+                    .collect(),
+            };
+            let bands: Vec<Shape> = bands.iter().map(|band| Shape::Lines(band)).collect();
+
+            let (width, height) = graph_dimensions(args);
+            let mut chart = Chart::new_with_y_range(
+                width,
+                height,
+                0.0,
+                x_max,
+                0.0,
+                args.graph_max_latency * 1000.0,
+            );
+            let points = Shape::Points(&values);
+            let mut chart = chart.lineplot(&points);
+            for band in &bands {
+                chart = chart.lineplot(band);
+            }
+            chart.x_label_format(LabelFormat::None).display();
+        }
+
+        if !stats.by_dscp.is_empty() {
+            eprintln!("  dscp  received  avg");
+            for group in &stats.by_dscp {
+                let avg = match group.avg {
+                    None => "-".to_string(),
+                    Some(avg) => format!("{:.3} ms", avg.as_secs_f64() * 1000.0),
+                };
+                eprintln!(
+                    "  {:>4}  {:>8}  {}",
+                    group.dscp,
+                    format!("{}/{}", group.received, group.sent),
+                    avg
+                );
+            }
+        }
+
+        let aggregate = &self.aggregates[label];
+        if args.graph_loss {
+            let values: Vec<(f32, f32)> = aggregate
+                .loss_history
+                .iter()
+                .enumerate()
+                .map(|(i, loss)| (i as f32, *loss as f32 * 100.0))
+                .collect();
+
+            println!(
+                "{} ({}): loss % over the last {} volleys",
+                label,
+                addr,
+                values.len()
+            );
+            let (width, height) = graph_dimensions(args);
+            Chart::new_with_y_range(
+                width,
+                height,
+                0.0,
+                (args.graph_loss_window.max(2) - 1) as f32,
+                0.0,
+                100.0,
+            )
+            .lineplot(&Shape::Lines(&values))
+            .x_label_format(LabelFormat::None)
+            .display();
+        }
+
+        if self.state_saved.elapsed() >= STATE_SAVE_INTERVAL {
+            self.save_state();
+        }
+
+        if let Some(outliers) = &stats.outliers {
+            if !outliers.is_empty() && !args.quiet {
+                let outliers: Vec<String> = outliers
+                    .iter()
+                    .map(|(seq, latency)| {
+                        format!("{} ({:.3} ms)", seq, latency.as_secs_f64() * 1000.0)
+                    })
+                    .collect();
+                eprintln!("  outliers: {}", outliers.join(", "));
+            }
+        }
+
+        if !stats.histogram.is_empty() {
+            report_histogram(&stats.histogram);
+        }
+
+        if !stats.late_by_jitter_buffer.is_empty() {
+            eprintln!("  jitter buffer  late");
+            for (buffer, late) in &stats.late_by_jitter_buffer {
+                eprintln!("  {:>10} ms  {:>5.2}%", buffer, late * 100.0);
+            }
+        }
+
+        if let Some(Some(comparison)) = &comparison {
+            if comparison.regression && !args.quiet {
+                eprintln!(
+                    "WARNING: {} ({}): regression against baseline: avg {:+.3} ms, 99th {:+.3} ms, loss {:+.2} pp",
+                    label,
+                    addr,
+                    comparison.avg_delta,
+                    comparison.percentile99_delta,
+                    comparison.loss_delta
+                );
+            }
+        }
+
+        if info.excess_replies > 0 && !args.quiet {
+            eprintln!(
+                "WARNING: {} ({}): {} replies matched {} requests, possible reflection or amplification",
+                label,
+                addr,
+                info.received + info.duplicates,
+                info.sent
+            );
+        }
+
+        if info.corrupted > 0 && !args.quiet {
+            eprintln!(
+                "WARNING: {} ({}): {} of {} replies came back with a corrupted payload",
+                label, addr, info.corrupted, info.received
+            );
+        }
+
+        let too_big: Vec<u32> = info
+            .icmp_errors
+            .iter()
+            .filter_map(|(_, error)| match error {
+                IcmpError::PacketTooBig { mtu } => Some(*mtu),
+                _ => None,
+            })
+            .collect();
+        if !too_big.is_empty() && !args.quiet {
+            // Routers predating RFC 1191 report an MTU of 0.
+            let mtu = match too_big.iter().copied().filter(|&mtu| mtu > 0).min() {
+                Some(mtu) => format!(", MTU {}", mtu),
+                None => String::new(),
+            };
+            eprintln!(
+                "WARNING: {} ({}): {} requests too big for the path{}",
+                label,
+                addr,
+                too_big.len(),
+                mtu
+            );
+        }
+
+        if info.rejected_sources > 0 && !args.quiet {
+            eprintln!(
+                "WARNING: {} ({}): {} replies from sources outside --accept-source-cidr",
+                label, addr, info.rejected_sources
+            );
+        }
+
+        if stats.reorder_warning && !args.quiet {
+            eprintln!(
+                "WARNING: {} ({}): {} of {} replies arrived out of order",
+                label, addr, info.reordered, info.received
+            );
+        }
+
+        !(args.fail_on_size_violation && stats.size_violations.unwrap_or(0) > 0
+            || args.fail_on_reorder && stats.reorder_warning)
+    }
+}
+
+/// Re-reports volleys captured with `--format ndjson` or `cbor` without sending any packets.
+fn replay(args: &ProgramArgs, path: &Path) -> ExitCode {
+    let records = match record::read_records(path) {
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        Ok(records) => records,
+    };
+
+    let time_width = records.iter().map(|r| r.time.len()).max().unwrap_or(0);
+    let target_width = records.iter().map(|r| r.target.len()).max().unwrap_or(0);
+    let ip_width = records
+        .iter()
+        .map(|r| r.ip.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let count = records.iter().map(|r| r.results.len()).max().unwrap_or(0);
+    let table = Table::new(
+        time_width,
+        target_width,
+        ip_width,
+        count,
+        secs_to_duration(args.timeout).as_millis() as u64,
+    );
+    let mut targets: Vec<TargetMetadata> = Vec::new();
+    for record in &records {
+        if !targets
+            .iter()
+            .any(|t| t.target == record.target && t.ip == record.ip)
+        {
+            targets.push(TargetMetadata {
+                target: record.target.clone(),
+                ip: record.ip,
+            });
+        }
+    }
+    let mut reporter = match Reporter::new(args, table, Metadata::new(targets, None)) {
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        Ok(reporter) => reporter,
+    };
+
+    let mut success = true;
+    for record in &records {
+        let info = record.to_info();
+        success &= reporter.report(record.time.clone(), &record.target, record.ip, &info);
+    }
+    reporter.finish();
+
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn run(args: ProgramArgs) -> ExitCode {
+    if let Some(path) = &args.replay {
+        return replay(&args, path);
+    }
+
+    let count = args.count;
+    let interval = secs_to_duration(args.interval);
+    let timeout = secs_to_duration(args.timeout);
+    let volley_interval = secs_to_duration(args.volley_interval);
+    let time_format = args.timestamp_precision.time_format();
+    let heartbeat = match args.heartbeat {
+        Some(secs) if secs > 0.0 && !args.quiet => Some(secs_to_duration(secs)),
+        _ => None,
+    };
+
+    // Zero and random patterns are the generated payloads of either entropy.
+    let entropy = match args.pattern {
+        Some(Pattern::Zero) => Entropy::Low,
+        Some(Pattern::Random) => Entropy::High,
+        _ => args.entropy,
+    };
+    let payloads = match (&args.payload_set, &args.pattern) {
+        (None, Some(Pattern::Bytes(bytes))) => {
+            vec![bytes.iter().copied().cycle().take(args.size).collect()]
+        }
+        (None, _) => Vec::new(),
+        (Some(path), _) => match read_payload_set(path) {
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+            Ok(payloads) => payloads,
+        },
+    };
+    let gaps = match &args.timing_file {
+        None => Vec::new(),
+        Some(path) => match read_timing_file(path) {
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+            Ok(gaps) => gaps,
+        },
+    };
+    let unprivileged = args.unprivileged || !socket::raw_icmp_permitted();
+    if unprivileged && !args.unprivileged {
+        log::verbose!("No permission to open raw sockets, using ICMP datagram sockets");
+    }
+    let config = VolleyConfig {
+        count,
+        size: args.size,
+        entropy,
+        interval,
+        interval_distribution: args.interval_distribution,
+        gaps: Arc::new(gaps),
+        seed: args.seed,
+        timeout,
+        payloads: Arc::new(payloads),
+        verify_checksum: !args.no_verify_checksum,
+        seq_start: args.seq_start,
+        inject_faults: false,
+        window: args.window,
+        lenient_identifier: args.lenient_identifier,
+        dscp: Arc::new(args.dscp.clone()),
+        ecn: args.ecn,
+        ttl: args.ttl,
+        dont_fragment: args.df,
+        source: args.source,
+        precise_pacing: args.precise_pacing,
+        accept_sources: Arc::new(args.accept_source_cidr.clone()),
+        profile: args.profile,
+        deadline: args.deadline_per_volley.map(secs_to_duration),
+        unprivileged,
+        kernel_timestamps: !args.userspace_timestamps,
+        hardware_timestamps: args.hw_timestamps,
+        batch_size: args.batch_size,
+    };
+    let http_probe = Arc::new(HttpProbe::new(
+        args.http_method,
+        &args.http_path,
+        args.port,
+        args.probe == Probe::Https,
+    ));
+    let tls_probe = Arc::new(TlsProbe::new(
+        args.port.unwrap_or(tls::DEFAULT_PORT),
+        !args.tls_no_resumption,
+    ));
+    let dns_probe = match &args.qname {
+        None => None,
+        Some(qname) => match DnsProbe::new(qname, args.port.unwrap_or(dns::DEFAULT_PORT)) {
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+            Ok(probe) => Some(Arc::new(probe)),
+        },
+    };
+
+    if args.self_test {
+        return selftest::run(&config);
+    }
+    if let Some(Command::Trace(trace_args)) = &args.command {
+        let addr = match resolve_with_retry(&trace_args.target, None, args.retry_resolve) {
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+            Ok(addr) => addr,
+        };
+        return trace::run(trace_args, addr, &config);
+    }
+
+    let targets = match prepare_targets(
+        &args.target,
+        args.dual,
+        args.source.as_ref().map(Family::of),
+        args.retry_resolve,
+    ) {
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        Ok(targets) => targets,
+    };
+
+    let mut ip_width = 0;
+    let mut resolved: Vec<TargetMetadata> = Vec::new();
+    for target in &targets {
+        if let Ok(addr) = resolve(&target.host, target.family) {
+            ip_width = ip_width.max(addr.to_string().len());
+            resolved.push(TargetMetadata {
+                target: target.label.clone(),
+                ip: addr,
+            });
+        }
+    }
+
+    if args.discover_pmtu {
+        for metadata in &resolved {
+            report_path_mtu(&metadata.target, metadata.ip, &config);
+        }
+    }
+
+    let time_width = chrono::Local::now().format(time_format).to_string().len();
+    let mut target_width = targets.iter().map(|t| t.label.len()).max().unwrap_or(0);
+    let mut table_count = count;
+    if args.fleet_summary {
+        target_width = target_width.max(FLEET_LABEL.len());
+        table_count = count * targets.len();
+    }
+    let table = Table::new(
+        time_width,
+        target_width,
+        ip_width,
+        table_count,
+        timeout.as_millis() as u64,
+    );
+    let entropy = config.payloads.is_empty().then_some(config.entropy);
+    let mut reporter = match Reporter::new(&args, table, Metadata::new(resolved, entropy)) {
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        Ok(reporter) => reporter,
+    };
+
+    if args.output_parquet.is_some() {
+        finish_on_interrupt();
+    }
+
+    if let Some(rate) = args.steady_rate {
+        return run_steady(&args, &config, &targets, rate, &mut reporter);
+    }
+
+    let offsets = stagger_offsets(args.stagger, args.seed, targets.len(), volley_interval);
+
+    // Measures a volley of a target with the probe selected by the arguments, also returning
+    // the one-way delays estimated by --probe icmp-timestamp.
+    let measure = |target: &Target, addr: IpAddr, prober: &mut Option<Prober>| match (
+        args.probe, args.port, &dns_probe,
+    ) {
+        (Probe::Tcp, Some(port), _) => (tcp::measure_volley(addr, port, &config), None),
+        (Probe::Udp, Some(port), _) => (udp::measure_volley(addr, port, &config), None),
+        (Probe::Http | Probe::Https, _, _) => (
+            http::measure_volley(addr, &target.host, &http_probe, &config),
+            None,
+        ),
+        (Probe::Dns, _, Some(probe)) => (dns::measure_volley(addr, probe, &config), None),
+        (Probe::Tls, _, _) => (
+            tls::measure_volley(addr, &target.host, &tls_probe, &config),
+            None,
+        ),
+        (Probe::Arp, _, _) => (arp::measure_volley(addr, &config), None),
+        (Probe::IcmpTimestamp, _, _) => {
+            let (result, delay) = timestamp::measure_volley(addr, &config);
+            (result, Some(delay))
+        }
+        (Probe::Quic, port, _) => (
+            quic::measure_volley(addr, port.unwrap_or(quic::DEFAULT_PORT), &config),
+            None,
+        ),
+        _ => match prober_for(prober, addr, &config) {
+            Err(e) => (VolleyResult::Error(e), None),
+            Ok(prober) => (prober.measure_volley(&config), None),
+        },
+    };
+
+    // ICMP sockets of the targets, kept open across volleys.
+    let mut probers: Vec<Option<Prober>> = targets.iter().map(|_| None).collect();
+    let workers = args.parallel.unwrap_or(1);
+    // Parallel ICMP volleys run together on one event loop rather than a thread each.
+    let event_loop = args.parallel.is_some() && matches!(args.probe, Probe::Icmp);
+    let mut next_volley = Instant::now();
+    let mut volleys = 0;
+    loop {
+        let mut success = true;
+        let mut round_by_target: Vec<Option<(&str, IpAddr, VolleyInfo)>> =
+            targets.iter().map(|_| None).collect();
+        let round_start = chrono::Local::now();
+        // Targets not yet taken by a worker, with their offsets and probers.
+        let jobs = Mutex::new(targets.iter().zip(&offsets).zip(&mut probers).enumerate());
+        thread::scope(|scope| {
+            let (volleys_tx, volleys_rx) = mpsc::channel();
+            if event_loop {
+                let jobs = &jobs;
+                let config = &config;
+                let volleys_tx = volleys_tx.clone();
+                scope.spawn(move || {
+                    let mut started = Vec::new();
+                    let mut pending = Vec::new();
+                    for (i, ((target, offset), prober)) in jobs.lock().unwrap().by_ref() {
+                        let addr = match resolve_with_retry(
+                            &target.host,
+                            target.family,
+                            args.retry_resolve,
+                        ) {
+                            Err(e) => {
+                                eprintln!("{}", e);
+                                continue;
+                            }
+                            Ok(addr) => addr,
+                        };
+                        match prober_for(prober, addr, config) {
+                            Err(e) => {
+                                let result = VolleyResult::Error(e);
+                                _ = volleys_tx.send((i, addr, chrono::Local::now(), result, None));
+                            }
+                            Ok(prober) => {
+                                started.push((i, addr));
+                                pending.push((prober, next_volley + *offset));
+                            }
+                        }
+                    }
+                    volley::measure_volleys(pending, config, workers, interrupted, |j, result| {
+                        let (i, addr) = started[j];
+                        let start = match &result {
+                            VolleyResult::Success(info) => info.send_times.first().copied(),
+                            VolleyResult::Error(_) => None,
+                        };
+                        let start = start.map_or_else(chrono::Local::now, DateTime::from);
+                        _ = volleys_tx.send((i, addr, start, result, None));
+                    });
+                });
+            } else {
+                for worker in 0..workers {
+                    // Only one worker prints heartbeats while waiting, so they aren't repeated.
+                    let heartbeat = heartbeat.filter(|_| worker == 0);
+                    let jobs = &jobs;
+                    let measure = &measure;
+                    let volleys_tx = volleys_tx.clone();
+                    scope.spawn(move || loop {
+                        let next = jobs.lock().unwrap().next();
+                        let (i, ((target, offset), prober)) = match next {
+                            None => return,
+                            Some(job) => job,
+                        };
+                        wait_until(next_volley + *offset, heartbeat);
+
+                        let addr = match resolve_with_retry(
+                            &target.host,
+                            target.family,
+                            args.retry_resolve,
+                        ) {
+                            Err(e) => {
+                                eprintln!("{}", e);
+                                continue;
+                            }
+                            Ok(addr) => addr,
+                        };
+                        if interrupted() {
+                            return;
+                        }
+                        let start = chrono::Local::now();
+                        let (result, one_way) = measure(target, addr, prober);
+                        let one_way = match (&result, one_way) {
+                            (VolleyResult::Error(_), _) => None,
+                            (_, Some(delay)) => Some(Ok(delay)),
+                            (_, None) if args.one_way_delay => {
+                                Some(measure_one_way_delay(addr, args.one_way_samples, timeout))
+                            }
+                            (_, None) => None,
+                        };
+                        if volleys_tx.send((i, addr, start, result, one_way)).is_err() {
+                            return;
+                        }
+                    });
+                }
+            }
+            drop(volleys_tx);
+
+            // Volleys are reported as they finish, which is in turn unless --parallel.
+            for (i, addr, start, result, one_way) in volleys_rx {
+                let info = match result {
+                    VolleyResult::Error(e) => {
+                        eprintln!("Failed to measure volley: {}", e);
+                        continue;
+                    }
+                    VolleyResult::Success(info) => *info,
+                };
+                let one_way = match one_way {
+                    None if event_loop && args.one_way_delay => {
+                        Some(measure_one_way_delay(addr, args.one_way_samples, timeout))
+                    }
+                    one_way => one_way,
+                };
+
+                reporter.check_clock(Instant::now(), chrono::Local::now());
+                let time = start.format(time_format).to_string();
+                let label = &targets[i].label;
+                success &= reporter.report(time, label, addr, &info);
+                if args.compare_targets || args.fleet_summary {
+                    round_by_target[i] = Some((label, addr, info));
+                }
+                if let Some(delay) = one_way {
+                    print_one_way_delay(delay);
+                }
+            }
+        });
+        // In the order of the targets, however the volleys finished.
+        let round: Vec<(&str, IpAddr, VolleyInfo)> =
+            round_by_target.into_iter().flatten().collect();
+
+        if args.fleet_summary && !round.is_empty() {
+            let time = round_start.format(time_format).to_string();
+            success &= reporter.report_fleet(time, &round);
+        }
+        if args.compare_targets {
+            reporter.print_ranking(&round);
+        }
+
+        if !success {
+            reporter.finish();
+            return ExitCode::FAILURE;
+        }
+        if interrupted() {
+            reporter.finish();
+            return ExitCode::SUCCESS;
+        }
+
+        volleys += 1;
+        if args.volleys.is_some_and(|limit| volleys >= limit) {
+            reporter.finish();
+            return ExitCode::SUCCESS;
+        }
+
+        next_volley += volley_interval;
+        wait_until(next_volley, heartbeat);
+    }
+}
+
+/// Prints the replies in each latency bucket with a bar scaled to the fullest bucket.
+fn report_histogram(histogram: &[HistogramBucket]) {
+    let most = histogram.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+    eprintln!("  latency           replies");
+    for bucket in histogram {
+        let range = match bucket.upper {
+            Some(upper) => format!("<= {} ms", upper),
+            None => format!(" > {} ms", bucket.lower),
+        };
+        let bar = "#".repeat((bucket.count * HISTOGRAM_WIDTH).div_ceil(most));
+        eprintln!("  {:>12}  {:>8}  {}", range, bucket.count, bar);
+    }
+}
+
+/// Prints the ICMP errors of a volley with the indices of the requests each was about.
+fn report_icmp_errors(info: &VolleyInfo) {
+    let mut errors: Vec<(IcmpError, Vec<usize>)> = Vec::new();
+    for (index, error) in &info.icmp_errors {
+        match errors.iter_mut().find(|(e, _)| e == error) {
+            Some((_, indices)) => indices.push(*index),
+            None => errors.push((*error, vec![*index])),
+        }
+    }
+    for (error, indices) in errors {
+        eprintln!("  {} for requests {:?}", error, indices);
+    }
+}
+
+/// Prints the tool's own time per request and reply next to the measured latency.
+fn report_profile(profile: &Profile, info: &VolleyInfo, stats: &VolleyStats) {
+    let per = |total: Duration, n: usize| match n {
+        0 => Duration::ZERO,
+        n => total / n as u32,
+    };
+    eprintln!(
+        "  overhead: send {:.1} us/request ({} sent), receive {:.1} us/datagram ({} read), avg latency {:.1} us",
+        per(profile.send, info.sent).as_secs_f64() * 1e6,
+        info.sent,
+        per(profile.receive, profile.datagrams).as_secs_f64() * 1e6,
+        profile.datagrams,
+        stats.avg.as_secs_f64() * 1e6
+    );
+}
+
+/// Discovers and prints the path MTU to `addr`.
+fn report_path_mtu(label: &str, addr: IpAddr, config: &VolleyConfig) {
+    match pmtu::discover(addr, config) {
+        Err(e) => eprintln!("path MTU to {} ({}): {}", label, addr, e),
+        Ok(path) if path.reported => {
+            eprintln!("path MTU to {} ({}): {} bytes", label, addr, path.mtu)
+        }
+        Ok(path) => eprintln!(
+            "path MTU to {} ({}): {} bytes, larger packets were dropped without an error",
+            label, addr, path.mtu
+        ),
+    }
+}
+
+/// Estimates the one-way delays to `addr` from ICMP timestamp replies.
+fn measure_one_way_delay(
+    addr: IpAddr,
+    samples: usize,
+    timeout: Duration,
+) -> io::Result<Option<OneWayDelay>> {
+    match addr {
+        IpAddr::V4(target) => timestamp::measure_one_way(target, samples, timeout),
+        IpAddr::V6(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "ICMP timestamps aren't available over IPv6",
+        )),
+    }
+}
+
+fn print_one_way_delay(delay: io::Result<Option<OneWayDelay>>) {
+    match delay {
+        Err(e) => eprintln!("  one-way delay: {}", e),
+        Ok(None) => eprintln!("  one-way delay: no usable timestamp replies"),
+        Ok(Some(delay)) => eprintln!(
+            "  one-way delay: forward {} ms, reverse {} ms, asymmetry {:+} ms \
+             (clock offset if symmetric: {:+} ms, {} samples, assumes synchronized clocks)",
+            delay.forward,
+            delay.reverse,
+            delay.forward - delay.reverse,
+            delay.clock_offset,
+            delay.samples
+        ),
+    }
+}
+
+/// Returns the offset of each target's volley start from the start of the volley interval.
+fn stagger_offsets(
+    stagger: Option<Stagger>,
+    seed: Option<u64>,
+    targets: usize,
+    volley_interval: Duration,
+) -> Vec<Duration> {
+    match stagger {
+        None => vec![Duration::ZERO; targets],
+        Some(Stagger::Even) => (0..targets)
+            .map(|i| volley_interval.mul_f64(i as f64 / targets as f64))
+            .collect(),
+        Some(Stagger::Random) => {
+            let mut rng = match seed {
+                None => StdRng::from_entropy(),
+                Some(seed) => StdRng::seed_from_u64(seed),
+            };
+            // Targets are pinged in order, so the offsets must be too.
+            let mut offsets: Vec<Duration> = (0..targets)
+                .map(|_| volley_interval.mul_f64(rng.gen::<f64>()))
+                .collect();
+            offsets.sort();
+            offsets
+        }
+    }
+}
+
+/// Pings every target continuously at `rate` requests per second, reporting a summary of each
+/// --summary-interval.
+fn run_steady(
+    args: &ProgramArgs,
+    config: &VolleyConfig,
+    targets: &[Target],
+    rate: f64,
+    reporter: &mut Reporter,
+) -> ExitCode {
+    if !(rate > 0.0 && rate.is_finite()) {
+        eprintln!("--steady-rate must be a positive number of requests per second");
+        return ExitCode::FAILURE;
+    }
+    let summary_interval = secs_to_duration(args.summary_interval);
+    let time_format = args.timestamp_precision.time_format();
+
+    let (windows_tx, windows_rx) = mpsc::channel();
+    for (i, target) in targets.iter().enumerate() {
+        let addr = match resolve_with_retry(&target.host, target.family, args.retry_resolve) {
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+            Ok(addr) => addr,
+        };
+        let windows = windows_tx.clone();
+        let config = config.clone();
+        thread::spawn(move || {
+            let result =
+                stream::measure_stream(addr, &config, rate, summary_interval, |start, info| {
+                    windows.send((i, addr, start, info)).is_ok()
+                });
+            if let Err(e) = result {
+                eprintln!("Failed to measure {}: {}", addr, e);
+            }
+        });
+    }
+    drop(windows_tx);
+
+    for (windows, (i, addr, start, info)) in windows_rx.into_iter().enumerate() {
+        // Window start times are projected from the first one, so read the clock afresh.
+        reporter.check_clock(Instant::now(), chrono::Local::now());
+        let time = start.format(time_format).to_string();
+        if !reporter.report(time, &targets[i].label, addr, &info) {
+            reporter.finish();
+            return ExitCode::FAILURE;
+        }
+        if interrupted() {
+            reporter.finish();
+            return ExitCode::SUCCESS;
+        }
+
+        if args
+            .volleys
+            .is_some_and(|limit| windows + 1 >= limit * targets.len())
+        {
+            reporter.finish();
+            return ExitCode::SUCCESS;
+        }
+    }
+
+    // Every stream stopped with an error.
+    reporter.finish();
+    ExitCode::FAILURE
+}
+
+/// Set by SIGINT or SIGTERM once `finish_on_interrupt` has installed its handler.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Longest an interruptible sleep goes without checking for an interrupt.
+const INTERRUPT_POLL: Duration = Duration::from_millis(100);
+
+extern "C" fn on_interrupt(signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    // A second signal kills the process as usual, e.g. if the current volley hangs.
+    unsafe { libc::signal(signal, libc::SIG_DFL) };
+}
+
+/// Makes SIGINT and SIGTERM end the run after the current volley instead of killing the
+/// process, so output that must be completed, like --output-parquet, is.
+fn finish_on_interrupt() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let handler = on_interrupt as extern "C" fn(libc::c_int);
+        unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+    }
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Sleeps for `duration`, or until interrupted.
+fn sleep_interruptibly(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !interrupted() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+        thread::sleep(remaining.min(INTERRUPT_POLL));
+    }
+}
+
+/// The prober of a target, opened anew if there's none yet or the target's address changed.
+fn prober_for<'a>(
+    prober: &'a mut Option<Prober>,
+    addr: IpAddr,
+    config: &VolleyConfig,
+) -> Result<&'a mut Prober, String> {
+    if prober.as_ref().map(Prober::target) != Some(addr) {
+        *prober = Some(Prober::new(addr, config)?);
+    }
+    Ok(prober.as_mut().unwrap())
+}
+
+/// Sleeps until `deadline`, or until interrupted, printing a line to stderr every `heartbeat`
+/// while waiting.
+fn wait_until(deadline: Instant, heartbeat: Option<Duration>) {
+    loop {
+        let now = Instant::now();
+        if now >= deadline || interrupted() {
+            return;
+        }
+        let remaining = deadline - now;
+
+        match heartbeat {
+            Some(heartbeat) if heartbeat < remaining => {
+                sleep_interruptibly(heartbeat);
+                if interrupted() {
+                    return;
+                }
+                let next = chrono::Local::now()
+                    + chrono::Duration::from_std(remaining - heartbeat).unwrap_or_default();
+                eprintln!(
+                    "[{}] still running, next volley at {}",
+                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    next.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+            _ => {
+                sleep_interruptibly(remaining);
+                return;
+            }
         }
     }
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = ProgramArgs::parse();
-    run(args);
+    log::init(args.quiet, args.verbose);
+    run(args)
 }
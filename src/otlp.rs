@@ -0,0 +1,319 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::stats::VolleyStats;
+
+/// Port of OTLP over HTTP if the endpoint doesn't name one.
+const DEFAULT_PORT: u16 = 4318;
+/// Longest a connection or request may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Exports waiting for the sender thread, beyond which new ones are dropped.
+const QUEUE_LENGTH: usize = 64;
+/// Aggregation temporality of sums that count since the previous export.
+const TEMPORALITY_DELTA: u8 = 1;
+
+/// An OTLP/HTTP endpoint, e.g. http://collector:4318.
+struct Endpoint {
+    host: String,
+    port: u16,
+    /// Path metrics are posted to, the endpoint's path followed by /v1/metrics.
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Endpoint, String> {
+        let rest = match url.strip_prefix("http://") {
+            None if url.starts_with("https://") => {
+                return Err("https isn't supported, use http:// or a local collector".to_string())
+            }
+            None => return Err(format!("{} isn't an http:// URL", url)),
+            Some(rest) => rest,
+        };
+        let (authority, path) = match rest.find('/') {
+            None => (rest, ""),
+            Some(i) => (&rest[..i], &rest[i..]),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            // A bracketed IPv6 address without a port has colons but no port after them.
+            Some((host, port)) if !port.ends_with(']') => match port.parse() {
+                Err(_) => return Err(format!("Invalid port in {}", url)),
+                Ok(port) => (host, port),
+            },
+            _ => (authority, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err(format!("No host in {}", url));
+        }
+        Ok(Endpoint {
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            path: format!("{}/v1/metrics", path.trim_end_matches('/')),
+        })
+    }
+
+    /// Posts a JSON-encoded export request and checks that it was accepted.
+    fn post(&self, body: &str) -> io::Result<()> {
+        let addr = match (self.host.as_str(), self.port).to_socket_addrs()?.next() {
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No addresses found for {}", self.host),
+                ))
+            }
+            Some(addr) => addr,
+        };
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            self.port,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes())?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "Rejected by collector: {}",
+                status.trim_end()
+            ))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportRequest {
+    resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceMetrics {
+    resource: Resource,
+    scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Serialize)]
+struct Resource {
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Serialize)]
+struct KeyValue {
+    key: &'static str,
+    value: AnyValue,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnyValue {
+    string_value: String,
+}
+
+#[derive(Serialize)]
+struct ScopeMetrics {
+    scope: Scope,
+    metrics: Vec<Metric>,
+}
+
+#[derive(Serialize)]
+struct Scope {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+struct Metric {
+    name: &'static str,
+    description: &'static str,
+    unit: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sum: Option<Sum>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gauge: Option<Gauge>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Sum {
+    data_points: Vec<DataPoint>,
+    aggregation_temporality: u8,
+    is_monotonic: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Gauge {
+    data_points: Vec<DataPoint>,
+}
+
+/// A data point. 64-bit integers are strings in the JSON encoding of OTLP.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DataPoint {
+    attributes: Vec<KeyValue>,
+    start_time_unix_nano: String,
+    time_unix_nano: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    as_int: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    as_double: Option<f64>,
+}
+
+fn attribute(key: &'static str, value: impl ToString) -> KeyValue {
+    KeyValue {
+        key,
+        value: AnyValue {
+            string_value: value.to_string(),
+        },
+    }
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// Publishes volley summaries as OpenTelemetry metrics over OTLP/HTTP with JSON encoding.
+///
+/// Requests are posted from a background thread so a slow collector doesn't hold up volleys.
+pub struct OtlpExporter {
+    queue: SyncSender<String>,
+    /// When each target was last exported, the start of the interval its counts cover.
+    exported: HashMap<String, u128>,
+}
+
+impl OtlpExporter {
+    pub fn new(url: &str) -> Result<OtlpExporter, String> {
+        let endpoint = Endpoint::parse(url)?;
+        let (queue, requests) = mpsc::sync_channel::<String>(QUEUE_LENGTH);
+        thread::spawn(move || {
+            for body in requests {
+                if let Err(e) = endpoint.post(&body) {
+                    eprintln!("Failed to export OTLP metrics: {}", e);
+                }
+            }
+        });
+        Ok(OtlpExporter {
+            queue,
+            exported: HashMap::new(),
+        })
+    }
+
+    /// Exports the summary of a volley of `label`, with the counts as deltas since the previous
+    /// export of the target and the latencies as gauges.
+    pub fn export(&mut self, label: &str, addr: Option<IpAddr>, stats: &VolleyStats) {
+        let now = unix_nanos();
+        let start = self.exported.insert(label.to_string(), now).unwrap_or(now);
+
+        let attributes = || {
+            let mut attributes = vec![attribute("target", label)];
+            if let Some(addr) = addr {
+                attributes.push(attribute("ip", addr));
+            }
+            attributes
+        };
+        let point = |as_int: Option<u64>, as_double: Option<f64>| DataPoint {
+            attributes: attributes(),
+            start_time_unix_nano: start.to_string(),
+            time_unix_nano: now.to_string(),
+            as_int: as_int.map(|value| value.to_string()),
+            as_double,
+        };
+        let mut metrics = Vec::new();
+        let counts = [
+            ("epingm.requests.sent", "Requests sent.", stats.sent),
+            (
+                "epingm.requests.received",
+                "Requests with a valid reply within the timeout.",
+                stats.received,
+            ),
+            (
+                "epingm.requests.lost",
+                "Requests without a reply within the timeout.",
+                stats.lost,
+            ),
+        ];
+        for (name, description, count) in counts {
+            metrics.push(Metric {
+                name,
+                description,
+                unit: "{request}",
+                sum: Some(Sum {
+                    data_points: vec![point(Some(count as u64), None)],
+                    aggregation_temporality: TEMPORALITY_DELTA,
+                    is_monotonic: true,
+                }),
+                gauge: None,
+            });
+        }
+
+        let mut latencies = Vec::new();
+        if stats.received > 0 {
+            latencies.push(("epingm.latency.avg", "Average latency.", stats.avg));
+            latencies.push(("epingm.latency.min", "Lowest latency.", stats.min));
+            latencies.push(("epingm.latency.max", "Highest latency.", stats.max));
+        }
+        if stats.percentiles {
+            latencies.push(("epingm.latency.p50", "Median latency.", stats.percentile50));
+            latencies.push((
+                "epingm.latency.p99",
+                "99th percentile latency.",
+                stats.percentile99,
+            ));
+        }
+        for (name, description, latency) in latencies {
+            metrics.push(Metric {
+                name,
+                description,
+                unit: "s",
+                sum: None,
+                gauge: Some(Gauge {
+                    data_points: vec![point(None, Some(latency.as_secs_f64()))],
+                }),
+            });
+        }
+
+        let request = ExportRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Resource {
+                    attributes: vec![attribute("service.name", "epingm")],
+                },
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Scope {
+                        name: "epingm",
+                        version: env!("CARGO_PKG_VERSION"),
+                    },
+                    metrics,
+                }],
+            }],
+        };
+        let body = match serde_json::to_string(&request) {
+            Err(e) => {
+                eprintln!("Failed to serialize OTLP metrics: {}", e);
+                return;
+            }
+            Ok(body) => body,
+        };
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(body) {
+            eprintln!("OTLP collector is falling behind, dropped the metrics of a volley");
+        }
+    }
+}
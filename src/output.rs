@@ -0,0 +1,420 @@
+use chrono::{DateTime, Local, SecondsFormat};
+use std::borrow::Cow;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::baseline::Comparison;
+use crate::stats::{HistogramBucket, VolleyStats};
+use crate::volley::VolleyInfo;
+
+/// A named value in a volley summary.
+pub struct Field {
+    pub name: Cow<'static, str>,
+    pub value: String,
+    /// Unit printed after the value in text output.
+    pub unit: Option<&'static str>,
+    /// Exact latency behind a value in milliseconds, for text output in other units.
+    pub duration: Option<Duration>,
+}
+
+impl Field {
+    fn new(name: impl Into<Cow<'static, str>>, value: impl ToString) -> Field {
+        Field {
+            name: name.into(),
+            value: value.to_string(),
+            unit: None,
+            duration: None,
+        }
+    }
+
+    /// A latency left out, still sized like one in tables.
+    fn no_latency(name: impl Into<Cow<'static, str>>) -> Field {
+        Field {
+            name: name.into(),
+            value: "-".to_string(),
+            unit: Some("ms"),
+            duration: None,
+        }
+    }
+
+    fn latency(name: impl Into<Cow<'static, str>>, value: Duration) -> Field {
+        Field {
+            name: name.into(),
+            value: format!("{:.3}", value.as_secs_f64() * 1000.0),
+            unit: Some("ms"),
+            duration: Some(value),
+        }
+    }
+
+    /// Value and unit for text output, switching sub-millisecond latencies to microseconds
+    /// with `auto_unit`.
+    fn text_value(&self, auto_unit: bool) -> (String, Option<&'static str>) {
+        match self.duration {
+            Some(duration) if auto_unit && duration < Duration::from_millis(1) => {
+                (duration.as_micros().to_string(), Some("us"))
+            }
+            None if self.value == "-" => (self.value.clone(), None),
+            _ => (self.value.clone(), self.unit),
+        }
+    }
+}
+
+/// Number of leading fields that hold text rather than numbers.
+const TEXT_FIELDS: usize = 3;
+
+/// Lists the fields of a volley summary in output order.
+///
+/// The first five are always time, target, ip, received and sent, and the last is always missing.
+pub fn summary_fields(
+    time: String,
+    label: &str,
+    ip: impl ToString,
+    stats: &VolleyStats,
+) -> Vec<Field> {
+    let mut fields = vec![
+        Field::new("time", time),
+        Field::new("target", label),
+        Field::new("ip", ip),
+        Field::new("received", stats.received),
+        Field::new("sent", stats.sent),
+        Field::new("lost", stats.lost),
+        Field::latency("avg", stats.avg),
+        Field::latency("min", stats.min),
+        Field::latency("max", stats.max),
+        Field::latency("stddev", stats.stddev),
+    ];
+    for &(p, latency) in &stats.by_percentile {
+        let name = percentile_name(p);
+        fields.push(match stats.percentiles {
+            true => Field::latency(name, latency),
+            false => Field::no_latency(name),
+        });
+    }
+    for (name, value) in [
+        ("jitter", stats.jitter),
+        ("mean_deviation", stats.mean_deviation),
+    ] {
+        fields.push(match value {
+            None => Field::no_latency(name),
+            Some(value) => Field::latency(name, value),
+        });
+    }
+    if let Some(spikes) = &stats.spikes {
+        fields.push(Field::new("spikes", spikes.len()));
+    }
+    if let Some(outliers) = &stats.outliers {
+        fields.push(Field::new("outliers", outliers.len()));
+    }
+    if let Some(size_violations) = stats.size_violations {
+        fields.push(Field::new("size_violations", size_violations));
+    }
+    fields.push(Field::new("reordered", stats.reordered));
+    fields.push(Field::new(
+        "max_reorder_distance",
+        stats.max_reorder_distance,
+    ));
+    if let Some(reorder_ratio) = stats.reorder_ratio {
+        fields.push(Field::new("reorder_ratio", format!("{:.4}", reorder_ratio)));
+        fields.push(Field::new("reorder_warning", stats.reorder_warning));
+    }
+    match stats.call_quality {
+        None => {
+            fields.push(Field::new("r_factor", "-"));
+            fields.push(Field::new("mos", "-"));
+        }
+        Some(quality) => {
+            fields.push(Field::new("r_factor", format!("{:.1}", quality.r_factor)));
+            fields.push(Field::new("mos", format!("{:.2}", quality.mos)));
+        }
+    }
+    fields.push(Field::new("loss_bursts", stats.loss_bursts.len()));
+    fields.push(Field::new(
+        "max_loss_burst",
+        stats.loss_bursts.iter().max().copied().unwrap_or(0),
+    ));
+    for bucket in &stats.histogram {
+        fields.push(Field::new(histogram_name(bucket), bucket.count));
+    }
+    fields.push(Field::new("missing", format!("{:?}", stats.missing)));
+    fields
+}
+
+/// Name of the field with the count of a histogram bucket, e.g. le_10ms, or gt_1000ms for the
+/// last.
+fn histogram_name(bucket: &HistogramBucket) -> String {
+    match bucket.upper {
+        Some(upper) => format!("le_{}ms", upper),
+        None => format!("gt_{}ms", bucket.lower),
+    }
+}
+
+/// Name of the field with the `p`th percentile, e.g. 99th or 99.9th.
+fn percentile_name(p: f64) -> String {
+    let number = p.to_string();
+    let suffix = match (number.contains('.'), p as u64 % 100, number.chars().last()) {
+        (false, 11..=13, _) => "th",
+        (_, _, Some('1')) => "st",
+        (_, _, Some('2')) => "nd",
+        (_, _, Some('3')) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", number, suffix)
+}
+
+/// Adds fields to a summary, keeping missing last.
+pub fn insert_fields(fields: &mut Vec<Field>, extra: Vec<Field>) {
+    let at = fields.len() - 1;
+    fields.splice(at..at, extra);
+}
+
+/// Lists the fields comparing a volley with its baseline, with placeholders for targets that
+/// have no baseline.
+pub fn comparison_fields(comparison: Option<&Comparison>) -> Vec<Field> {
+    match comparison {
+        None => vec![
+            Field::new("avg_delta", "-"),
+            Field::new("99th_delta", "-"),
+            Field::new("loss_delta", "-"),
+            Field::new("regression", "-"),
+        ],
+        Some(comparison) => vec![
+            Field {
+                name: "avg_delta".into(),
+                value: format!("{:+.3}", comparison.avg_delta),
+                unit: Some("ms"),
+                duration: None,
+            },
+            Field {
+                name: "99th_delta".into(),
+                value: format!("{:+.3}", comparison.percentile99_delta),
+                unit: Some("ms"),
+                duration: None,
+            },
+            Field {
+                name: "loss_delta".into(),
+                value: format!("{:+.2}", comparison.loss_delta),
+                unit: Some("pp"),
+                duration: None,
+            },
+            Field::new("regression", comparison.regression),
+        ],
+    }
+}
+
+/// Lists the field telling whether --deadline-per-volley cut a volley short.
+pub fn truncated_fields(truncated: bool) -> Vec<Field> {
+    vec![Field::new("truncated", truncated)]
+}
+
+/// Lists the field with the 99th percentile latency of the rolling window, a placeholder if it
+/// has no replies yet.
+pub fn rolling_fields(rolling_p99: Option<Duration>) -> Vec<Field> {
+    match rolling_p99 {
+        None => vec![Field::no_latency("rolling_99th")],
+        Some(p99) => vec![Field::latency("rolling_99th", p99)],
+    }
+}
+
+/// Lists the fields with the moving averages of latency and loss in percent, placeholders
+/// before they have a value.
+pub fn ewma_fields(avg: Option<Duration>, loss: Option<f64>) -> Vec<Field> {
+    let avg = match avg {
+        None => Field::no_latency("ewma_avg"),
+        Some(avg) => Field::latency("ewma_avg", avg),
+    };
+    let loss = match loss {
+        None => Field::new("ewma_loss", "-"),
+        Some(loss) => Field::new("ewma_loss", format!("{:.2}", loss * 100.0)),
+    };
+    vec![avg, loss]
+}
+
+/// Formats a summary as prose, without the timestamp.
+///
+/// With `auto_unit`, sub-millisecond latencies are shown in microseconds.
+pub fn text_summary(fields: &[Field], auto_unit: bool) -> String {
+    let mut line = format!(
+        "{} ({}): received: {}/{}",
+        fields[1].value, fields[2].value, fields[3].value, fields[4].value
+    );
+    for field in &fields[5..] {
+        let (value, unit) = field.text_value(auto_unit);
+        line.push_str(&format!(", {}: {}", field.name, value));
+        if let Some(unit) = unit {
+            line.push_str(&format!(" {}", unit));
+        }
+    }
+    line
+}
+
+pub fn text_line(fields: &[Field], auto_unit: bool) -> String {
+    format!("[{}] {}", fields[0].value, text_summary(fields, auto_unit))
+}
+
+pub fn csv_header(fields: &[Field]) -> String {
+    let names: Vec<&str> = fields.iter().map(|f| f.name.as_ref()).collect();
+    names.join(",")
+}
+
+pub fn csv_row(fields: &[Field]) -> String {
+    let values: Vec<&str> = fields.iter().map(|f| f.value.as_str()).collect();
+    values.join(",")
+}
+
+/// Columns of `--format csv-raw`, one row per request.
+pub const RAW_CSV_HEADER: &str = "time,target,ip,volley,seq,send_time,latency_ms,reply_size";
+
+/// Formats a row per request of a volley, each ending in a newline. `volley` counts the volleys
+/// of the target from 0. Lost requests have latency `timeout` and no reply size, and the send
+/// time is empty if it isn't known.
+pub fn raw_csv_rows(
+    time: &str,
+    label: &str,
+    ip: IpAddr,
+    volley: usize,
+    info: &VolleyInfo,
+) -> String {
+    let mut rows = String::new();
+    for (seq, result) in info.results.iter().enumerate() {
+        let send_time = match info.send_times.get(seq) {
+            None => String::new(),
+            Some(send_time) => {
+                DateTime::<Local>::from(*send_time).to_rfc3339_opts(SecondsFormat::Micros, false)
+            }
+        };
+        let (latency, reply_size) = match result {
+            None => ("timeout".to_string(), String::new()),
+            Some(result) => (
+                format!("{:.3}", result.latency.as_secs_f64() * 1000.0),
+                result.reply_size.to_string(),
+            ),
+        };
+        rows.push_str(&format!(
+            "{},{},{},{},{},{},{},{}\n",
+            time, label, ip, volley, seq, send_time, latency, reply_size
+        ));
+    }
+    rows
+}
+
+/// Turns text into the upper case letters, digits and underscores of a shell variable name.
+fn env_name(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Formats a summary as shell variable assignments, one per line, e.g. `EPINGM_AVG_MS='12'`.
+///
+/// Names are `EPINGM_`, then `target` if given, then the field name, then the unit if any.
+pub fn env_lines(fields: &[Field], target: Option<&str>) -> String {
+    let prefix = match target {
+        None => "EPINGM_".to_string(),
+        Some(target) => format!("EPINGM_{}_", env_name(target)),
+    };
+    let lines: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            let mut name = format!("{}{}", prefix, env_name(&field.name));
+            if let Some(unit) = field.unit {
+                name.push('_');
+                name.push_str(&env_name(unit));
+            }
+            format!("{}='{}'", name, field.value.replace('\'', "'\\''"))
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn digits(value: u64) -> usize {
+    value.to_string().len()
+}
+
+/// Formats summaries as aligned columns under a header row.
+#[derive(Clone)]
+pub struct Table {
+    time_width: usize,
+    target_width: usize,
+    ip_width: usize,
+    count_width: usize,
+    latency_width: usize,
+    /// Column widths, fixed when the header is printed.
+    widths: Option<Vec<usize>>,
+}
+
+impl Table {
+    /// Sizes columns to fit any value the run can produce.
+    pub fn new(
+        time_width: usize,
+        target_width: usize,
+        ip_width: usize,
+        count: usize,
+        timeout_millis: u64,
+    ) -> Table {
+        Table {
+            time_width,
+            target_width,
+            ip_width,
+            count_width: digits(count as u64),
+            // Whole milliseconds, the point and three decimals.
+            latency_width: digits(timeout_millis) + 4,
+            widths: None,
+        }
+    }
+
+    fn column_width(&self, field: &Field) -> usize {
+        let value_width = match field.name.as_ref() {
+            "time" => self.time_width,
+            "target" => self.target_width,
+            "ip" => self.ip_width,
+            "missing" => 0,
+            // Scores like 4.41.
+            "mos" => 4,
+            _ if field.unit == Some("ms") => self.latency_width,
+            _ => self.count_width,
+        };
+        value_width.max(field.name.len())
+    }
+
+    fn format_row<'a>(widths: &[usize], values: impl ExactSizeIterator<Item = &'a str>) -> String {
+        let last = values.len() - 1;
+        let mut line = String::new();
+        for (i, value) in values.enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            let width = widths.get(i).copied().unwrap_or(0);
+            if i == last {
+                line.push_str(value);
+            } else if i < TEXT_FIELDS {
+                line.push_str(&format!("{:<width$}", value));
+            } else {
+                line.push_str(&format!("{:>width$}", value));
+            }
+        }
+        line
+    }
+
+    /// Formats a row, preceded by the header row on the first call.
+    pub fn format(&mut self, fields: &[Field]) -> String {
+        let mut lines = String::new();
+        if self.widths.is_none() {
+            let widths: Vec<usize> = fields.iter().map(|f| self.column_width(f)).collect();
+            lines.push_str(&Self::format_row(
+                &widths,
+                fields.iter().map(|f| f.name.as_ref()),
+            ));
+            lines.push('\n');
+            self.widths = Some(widths);
+        }
+        let widths = self.widths.as_ref().unwrap();
+        lines.push_str(&Self::format_row(
+            widths,
+            fields.iter().map(|f| f.value.as_str()),
+        ));
+        lines
+    }
+}
@@ -0,0 +1,178 @@
+use parquet::basic::Compression;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::fs::File;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use crate::volley::VolleyInfo;
+
+const SCHEMA: &str = "
+message ping {
+    REQUIRED BYTE_ARRAY target (UTF8);
+    REQUIRED BYTE_ARRAY ip (UTF8);
+    REQUIRED INT64 seq;
+    OPTIONAL INT64 send_time (TIMESTAMP(MICROS,true));
+    OPTIONAL INT64 latency_ns;
+    OPTIONAL INT64 reply_size;
+    REQUIRED BOOLEAN lost;
+}
+";
+
+/// Rows buffered before they're written as a row group, bounding memory on long runs.
+const ROW_GROUP_ROWS: usize = 65536;
+
+fn parquet_error(e: ParquetError) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Buffered columns of the rows not yet written.
+#[derive(Default)]
+struct Columns {
+    target: Vec<String>,
+    ip: Vec<String>,
+    seq: Vec<i64>,
+    send_time: Vec<Option<i64>>,
+    latency_ns: Vec<Option<i64>>,
+    reply_size: Vec<Option<i64>>,
+    lost: Vec<bool>,
+}
+
+/// Values and definition levels of an optional column, the form Parquet writes nulls in.
+fn optional(values: &[Option<i64>]) -> (Vec<i64>, Vec<i16>) {
+    let present = values.iter().flatten().copied().collect();
+    let levels = values.iter().map(|v| v.is_some() as i16).collect();
+    (present, levels)
+}
+
+/// Writes the result of every request as a row of a Parquet file.
+///
+/// The file is only readable once `close` writes its footer, so rows are written in large row
+/// groups and the rest when the run ends.
+pub struct ParquetWriter {
+    writer: SerializedFileWriter<File>,
+    columns: Columns,
+}
+
+impl ParquetWriter {
+    /// Creates the file at `path`, refusing to replace an existing one.
+    pub fn create(path: &Path) -> io::Result<ParquetWriter> {
+        let file = match File::options().write(true).create_new(true).open(path) {
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("Failed to create {}: {}", path.display(), e),
+                ))
+            }
+            Ok(file) => file,
+        };
+        let schema = Arc::new(parse_message_type(SCHEMA).map_err(parquet_error)?);
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_created_by(format!("epingm {}", env!("CARGO_PKG_VERSION")))
+            .build();
+        let writer =
+            SerializedFileWriter::new(file, schema, Arc::new(properties)).map_err(parquet_error)?;
+        Ok(ParquetWriter {
+            writer,
+            columns: Columns::default(),
+        })
+    }
+
+    /// Adds a row for each request of a volley, writing a row group once enough are buffered.
+    pub fn add(&mut self, label: &str, addr: IpAddr, info: &VolleyInfo) -> io::Result<()> {
+        let columns = &mut self.columns;
+        for (seq, result) in info.results.iter().enumerate() {
+            let send_time = info.send_times.get(seq).and_then(|time| {
+                time.duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|since| since.as_micros() as i64)
+            });
+            columns.target.push(label.to_string());
+            columns.ip.push(addr.to_string());
+            columns.seq.push(seq as i64);
+            columns.send_time.push(send_time);
+            columns
+                .latency_ns
+                .push(result.as_ref().map(|r| r.latency.as_nanos() as i64));
+            columns
+                .reply_size
+                .push(result.as_ref().map(|r| r.reply_size as i64));
+            columns.lost.push(result.is_none());
+        }
+        if self.columns.seq.len() >= ROW_GROUP_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered rows as a row group.
+    fn flush(&mut self) -> io::Result<()> {
+        let columns = std::mem::take(&mut self.columns);
+        if columns.seq.is_empty() {
+            return Ok(());
+        }
+        let text = |values: Vec<String>| -> Vec<ByteArray> {
+            values
+                .into_iter()
+                .map(|s| ByteArray::from(s.into_bytes()))
+                .collect()
+        };
+        let target = text(columns.target);
+        let ip = text(columns.ip);
+        let send_time = optional(&columns.send_time);
+        let latency_ns = optional(&columns.latency_ns);
+        let reply_size = optional(&columns.reply_size);
+
+        let mut row_group = self.writer.next_row_group().map_err(parquet_error)?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column().map_err(parquet_error)? {
+            // In schema order.
+            let written = match index {
+                0 => column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&target, None, None),
+                1 => column.typed::<ByteArrayType>().write_batch(&ip, None, None),
+                2 => column
+                    .typed::<Int64Type>()
+                    .write_batch(&columns.seq, None, None),
+                3 => {
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&send_time.0, Some(&send_time.1), None)
+                }
+                4 => column.typed::<Int64Type>().write_batch(
+                    &latency_ns.0,
+                    Some(&latency_ns.1),
+                    None,
+                ),
+                5 => column.typed::<Int64Type>().write_batch(
+                    &reply_size.0,
+                    Some(&reply_size.1),
+                    None,
+                ),
+                _ => column
+                    .typed::<BoolType>()
+                    .write_batch(&columns.lost, None, None),
+            };
+            written.map_err(parquet_error)?;
+            column.close().map_err(parquet_error)?;
+            index += 1;
+        }
+        row_group.close().map_err(parquet_error)?;
+        Ok(())
+    }
+
+    /// Writes the remaining rows and the footer.
+    pub fn close(mut self) -> io::Result<()> {
+        self.flush()?;
+        self.writer.close().map_err(parquet_error)?;
+        Ok(())
+    }
+}
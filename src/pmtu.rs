@@ -0,0 +1,137 @@
+use std::io;
+use std::net::IpAddr;
+use std::time::Instant;
+
+use crate::icmp_error::IcmpError;
+use crate::socket;
+use crate::volley::{self, IcmpReceiver, IcmpSender, Parsed, VolleyConfig, MAX_IP_LENGTH};
+
+/// Attempts at a size before concluding that packets of it don't get through.
+const ATTEMPTS: usize = 2;
+
+/// Path MTU found by `discover`.
+pub struct PathMtu {
+    /// Largest packet, including the IP header, that reached the target and got a reply.
+    pub mtu: usize,
+    /// Whether the limit is that of the local interface or was reported by a router. If not,
+    /// larger packets were silently dropped, a sign of a black hole that breaks path MTU
+    /// discovery of TCP.
+    pub reported: bool,
+}
+
+enum Outcome {
+    Fits,
+    /// Too big for the path, with the MTU reported by a router if one did.
+    TooBig(Option<u32>),
+    Lost,
+}
+
+/// Size of the IP and ICMP headers of an echo request to `target`.
+fn header_size(target: IpAddr) -> usize {
+    match target {
+        IpAddr::V4(_) => 20 + 8,
+        IpAddr::V6(_) => 40 + 8,
+    }
+}
+
+/// Sends an echo request of `size` bytes including headers and waits for its answer.
+fn probe(
+    tx: &mut IcmpSender,
+    rx: &IcmpReceiver,
+    target: IpAddr,
+    config: &VolleyConfig,
+    identifier: u16,
+    seq: u16,
+    size: usize,
+) -> io::Result<Outcome> {
+    let payload = vec![0; size - header_size(target)];
+    let start = Instant::now();
+    match volley::send_echo_request(tx, target, &payload, identifier, seq) {
+        // The interface itself is too small.
+        Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => return Ok(Outcome::TooBig(None)),
+        result => result?,
+    }
+
+    let mut buffer = vec![0; 65536];
+    loop {
+        let remaining = config.timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Ok(Outcome::Lost);
+        }
+        let info = match socket::recv_with_timeout(rx.fd(), &mut buffer, remaining)? {
+            None => return Ok(Outcome::Lost),
+            Some(info) => info,
+        };
+        match volley::parse_reply(&buffer, &info, config, target, identifier) {
+            Parsed::Reply(reply) if reply.seq == seq => return Ok(Outcome::Fits),
+            Parsed::Error {
+                seq: error_seq,
+                error: IcmpError::PacketTooBig { mtu },
+            } if error_seq == seq => return Ok(Outcome::TooBig(Some(mtu))),
+            _ => {}
+        }
+    }
+}
+
+/// Finds the path MTU to `target` by binary search over the sizes of echo requests sent with
+/// the don't fragment bit, narrowed by the MTUs routers report in their errors.
+pub fn discover(target: IpAddr, config: &VolleyConfig) -> Result<PathMtu, String> {
+    if config.unprivileged {
+        return Err("Path MTU discovery needs the ICMP errors of raw sockets".to_string());
+    }
+    let (mut tx, rx) = volley::open_channel(target, config)?;
+    if let Err(e) = socket::set_dont_fragment(tx.fd(), target) {
+        return Err(format!("Failed to set the don't fragment bit: {}", e));
+    }
+    let identifier = rand::random::<u16>();
+    let mut seq = config.seq_start;
+    let mut reported = true;
+    let mut attempt = |size: usize| -> Result<Outcome, String> {
+        for _ in 0..ATTEMPTS {
+            let outcome = probe(&mut tx, &rx, target, config, identifier, seq, size)
+                .map_err(|e| format!("Failed to probe {} bytes: {}", size, e))?;
+            seq = seq.wrapping_add(1);
+            match outcome {
+                Outcome::Lost => continue,
+                outcome => return Ok(outcome),
+            }
+        }
+        Ok(Outcome::Lost)
+    };
+
+    // Every link must carry packets of the minimum MTU.
+    let mut fits = match target {
+        IpAddr::V4(_) => 68,
+        IpAddr::V6(_) => 1280,
+    };
+    if let Outcome::Lost = attempt(fits)? {
+        return Err(format!("No replies from {} even at {} bytes", target, fits));
+    }
+    let mut too_big =
+        match socket::outgoing_interface(target, config.source).and_then(socket::interface_mtu) {
+            Some(mtu) => mtu.min(MAX_IP_LENGTH) + 1,
+            None => MAX_IP_LENGTH + 1,
+        };
+    while too_big - fits > 1 {
+        let size = fits + (too_big - fits) / 2;
+        match attempt(size)? {
+            Outcome::Fits => fits = size,
+            Outcome::TooBig(mtu) => {
+                reported = true;
+                too_big = match mtu {
+                    // A reported MTU narrows the search to it, unless it's implausible.
+                    Some(mtu) if (mtu as usize) > fits && (mtu as usize) < size => mtu as usize + 1,
+                    _ => size,
+                };
+            }
+            Outcome::Lost => {
+                reported = false;
+                too_big = size;
+            }
+        }
+    }
+    Ok(PathMtu {
+        mtu: fits,
+        reported,
+    })
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::aggregate::TargetAggregate;
+
+/// Longest a scrape may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the latest metrics over HTTP in the Prometheus text format.
+pub struct Exporter {
+    body: Arc<Mutex<String>>,
+}
+
+impl Exporter {
+    /// Starts serving on `addr`, e.g. 127.0.0.1:9100, from a background thread.
+    pub fn listen(addr: &str) -> io::Result<Exporter> {
+        let listener = TcpListener::bind(addr)?;
+        let body = Arc::new(Mutex::new(String::new()));
+        let served = body.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| respond(stream, &served));
+                if let Err(e) = result {
+                    eprintln!("Failed to serve metrics: {}", e);
+                }
+            }
+        });
+        Ok(Exporter { body })
+    }
+
+    /// Replaces the metrics served with those of `aggregates`.
+    pub fn update(&self, aggregates: &HashMap<String, TargetAggregate>) {
+        *self.body.lock().unwrap() = render(aggregates);
+    }
+}
+
+fn respond(mut stream: TcpStream, body: &Mutex<String>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    // The request line is all that matters, but read the headers so the client isn't reset.
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        match stream.read(&mut buffer)? {
+            0 => break,
+            n => request.extend_from_slice(&buffer[..n]),
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split(' ').nth(1).unwrap_or("");
+    let response = if request.starts_with("GET ") && path == "/metrics" {
+        let body = body.lock().unwrap().clone();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes())
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A metric with one sample per target.
+struct Metric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    /// Value for a target, if it has one.
+    value: fn(&TargetAggregate) -> Option<f64>,
+}
+
+const METRICS: [Metric; 8] = [
+    Metric {
+        name: "epingm_requests_sent_total",
+        kind: "counter",
+        help: "Requests sent.",
+        value: |a| Some(a.sent as f64),
+    },
+    Metric {
+        name: "epingm_requests_received_total",
+        kind: "counter",
+        help: "Requests with a valid reply within the timeout.",
+        value: |a| Some(a.received as f64),
+    },
+    Metric {
+        name: "epingm_requests_lost_total",
+        kind: "counter",
+        help: "Requests without a reply within the timeout.",
+        value: |a| Some(a.lost as f64),
+    },
+    Metric {
+        name: "epingm_loss_ratio",
+        kind: "gauge",
+        help: "Share of requests lost in the most recent volley.",
+        value: |a| a.latest.map(|l| l.loss),
+    },
+    Metric {
+        name: "epingm_latency_avg_seconds",
+        kind: "gauge",
+        help: "Average latency of the most recent volley.",
+        value: |a| a.latest.and_then(|l| l.avg).map(|d| d.as_secs_f64()),
+    },
+    Metric {
+        name: "epingm_latency_p50_seconds",
+        kind: "gauge",
+        help: "Median latency of the most recent volley.",
+        value: |a| {
+            a.latest
+                .and_then(|l| l.percentile50)
+                .map(|d| d.as_secs_f64())
+        },
+    },
+    Metric {
+        name: "epingm_latency_p99_seconds",
+        kind: "gauge",
+        help: "99th percentile latency of the most recent volley.",
+        value: |a| {
+            a.latest
+                .and_then(|l| l.percentile99)
+                .map(|d| d.as_secs_f64())
+        },
+    },
+    Metric {
+        name: "epingm_rolling_p99_seconds",
+        kind: "gauge",
+        help: "Approximate 99th percentile latency of the most recent replies.",
+        value: |a| a.rolling_p99().map(|p99| p99.as_secs_f64()),
+    },
+];
+
+fn render(aggregates: &HashMap<String, TargetAggregate>) -> String {
+    let mut labels: Vec<&String> = aggregates.keys().collect();
+    labels.sort();
+
+    let mut body = String::new();
+    for metric in &METRICS {
+        _ = writeln!(body, "# HELP {} {}", metric.name, metric.help);
+        _ = writeln!(body, "# TYPE {} {}", metric.name, metric.kind);
+        for label in &labels {
+            if let Some(value) = (metric.value)(&aggregates[*label]) {
+                _ = writeln!(
+                    body,
+                    "{}{{target=\"{}\"}} {}",
+                    metric.name,
+                    escape(label),
+                    value
+                );
+            }
+        }
+    }
+    body
+}
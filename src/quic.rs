@@ -0,0 +1,188 @@
+use rand::{thread_rng, RngCore};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::log;
+use crate::volley::{self, PingResult, VolleyConfig, VolleyResult};
+
+/// Port of --probe quic if --port isn't given, that of HTTP/3.
+pub const DEFAULT_PORT: u16 = 443;
+/// Smallest datagram a server must answer, the minimum size of a client's first one.
+const MIN_DATAGRAM_SIZE: usize = 1200;
+/// A reserved version, which servers never support and so answer with a version negotiation.
+const RESERVED_VERSION: u32 = 0x1a2a_3a4a;
+const CONNECTION_ID_SIZE: usize = 8;
+/// Long header form and fixed bits.
+const LONG_HEADER: u8 = 0xc0;
+const MAX_RESPONSE_SIZE: usize = 1500;
+
+/// A packet with a long header and a version the server won't support, padded to the size
+/// servers must answer.
+fn probe_packet(destination: &[u8], source: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(MIN_DATAGRAM_SIZE);
+    packet.push(LONG_HEADER);
+    packet.extend_from_slice(&RESERVED_VERSION.to_be_bytes());
+    packet.push(destination.len() as u8);
+    packet.extend_from_slice(destination);
+    packet.push(source.len() as u8);
+    packet.extend_from_slice(source);
+    packet.resize(MIN_DATAGRAM_SIZE, 0);
+    packet
+}
+
+/// Versions offered by `packet` if it's a version negotiation answering the probe sent with
+/// the connection IDs `destination` and `source`, which it echoes swapped.
+fn parse_version_negotiation(packet: &[u8], destination: &[u8], source: &[u8]) -> Option<Vec<u32>> {
+    let (&first, rest) = packet.split_first()?;
+    if first & 0x80 == 0 || rest.len() < 4 || rest[..4] != [0; 4] {
+        return None;
+    }
+    let rest = &rest[4..];
+    let (echoed_destination, rest) = connection_id(rest)?;
+    let (echoed_source, rest) = connection_id(rest)?;
+    if echoed_destination != source || echoed_source != destination {
+        return None;
+    }
+    Some(
+        rest.chunks_exact(4)
+            .map(|version| u32::from_be_bytes([version[0], version[1], version[2], version[3]]))
+            .collect(),
+    )
+}
+
+/// Splits a length-prefixed connection ID off the start of `bytes`.
+fn connection_id(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&length, rest) = bytes.split_first()?;
+    let length = length as usize;
+    if rest.len() < length {
+        return None;
+    }
+    Some(rest.split_at(length))
+}
+
+/// Sends `addr` a probe packet and times the version negotiation it answers with. `None` if
+/// there was no answer within `timeout`.
+fn probe(addr: SocketAddr, timeout: Duration) -> io::Result<Option<PingResult>> {
+    let local: IpAddr = match addr {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local, 0))?;
+    socket.connect(addr)?;
+
+    let mut destination = [0; CONNECTION_ID_SIZE];
+    let mut source = [0; CONNECTION_ID_SIZE];
+    thread_rng().fill_bytes(&mut destination);
+    thread_rng().fill_bytes(&mut source);
+    let start = Instant::now();
+    socket.send(&probe_packet(&destination, &source))?;
+    let mut buffer = [0; MAX_RESPONSE_SIZE];
+    loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let size = match socket.recv(&mut buffer) {
+            Ok(size) => size,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        let versions = match parse_version_negotiation(&buffer[..size], &destination, &source) {
+            None => continue,
+            Some(versions) => versions,
+        };
+        let latency = start.elapsed();
+        log::debug!(
+            "{} offered QUIC versions {}",
+            addr,
+            versions
+                .iter()
+                .map(|version| format!("{:#010x}", version))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        return Ok(Some(PingResult {
+            latency,
+            reply_size: size,
+            interface: None,
+            ip_header: None,
+        }));
+    }
+}
+
+/// Measures a volley like `volley::measure_volley`, but times QUIC version negotiation with the
+/// target's QUIC stack on `port`. Each request is a packet with a version no server supports,
+/// which any QUIC server answers right away with the versions it does, in one round trip and
+/// without a handshake.
+pub fn measure_volley(target: IpAddr, port: u16, config: &VolleyConfig) -> VolleyResult {
+    let addr = SocketAddr::new(target, port);
+    let timeout = config.timeout;
+    volley::measure_blocking(config, move |_| match probe(addr, timeout) {
+        Ok(result) => result,
+        Err(e) => {
+            log::verbose!("Failed to probe {}: {}", addr, e);
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESTINATION: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+    const SOURCE: [u8; 8] = [9, 10, 11, 12, 13, 14, 15, 16];
+
+    /// A version negotiation echoing `destination` and `source` as the server would.
+    fn version_negotiation(destination: &[u8], source: &[u8], versions: &[u32]) -> Vec<u8> {
+        let mut packet = vec![0x80, 0, 0, 0, 0];
+        packet.push(source.len() as u8);
+        packet.extend_from_slice(source);
+        packet.push(destination.len() as u8);
+        packet.extend_from_slice(destination);
+        for version in versions {
+            packet.extend_from_slice(&version.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn packet() {
+        let packet = probe_packet(&DESTINATION, &SOURCE);
+        assert_eq!(packet.len(), MIN_DATAGRAM_SIZE);
+        assert_eq!(packet[..7], [0xc0, 0x1a, 0x2a, 0x3a, 0x4a, 8, 1]);
+        assert_eq!(packet[14..24], [8, 9, 10, 11, 12, 13, 14, 15, 16, 0]);
+    }
+
+    #[test]
+    fn version_negotiations() {
+        let packet = version_negotiation(&DESTINATION, &SOURCE, &[1, 0x6b33_43cf]);
+        assert_eq!(
+            parse_version_negotiation(&packet, &DESTINATION, &SOURCE),
+            Some(vec![1, 0x6b33_43cf])
+        );
+        // Connection IDs of another probe.
+        assert_eq!(
+            parse_version_negotiation(&packet, &SOURCE, &DESTINATION),
+            None
+        );
+        // Truncated.
+        assert_eq!(
+            parse_version_negotiation(&packet[..10], &DESTINATION, &SOURCE),
+            None
+        );
+        // Another version than the negotiation's 0.
+        let mut handshake = packet.clone();
+        handshake[4] = 1;
+        assert_eq!(
+            parse_version_negotiation(&handshake, &DESTINATION, &SOURCE),
+            None
+        );
+    }
+}
@@ -0,0 +1,474 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::stats::{burst_lengths, VolleyStats};
+use crate::volley::{Entropy, PingResult, Strays, VolleyInfo};
+
+/// Raw results of a volley, as written by `--format ndjson` or `cbor` and read back by
+/// `--replay`.
+#[derive(Serialize, Deserialize)]
+pub struct VolleyRecord {
+    pub time: String,
+    pub target: String,
+    pub ip: IpAddr,
+    pub sent: usize,
+    /// Results by sequence number, null for requests without a valid reply.
+    pub results: Vec<Option<PacketRecord>>,
+    /// Whether --deadline-per-volley cut the volley short. Missing in older files.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// Summary of a volley with its latencies, as written by `--format json` and `jsonl` for
+/// scripts. A fleet summary has the same shape, over the pooled volleys of a round.
+#[derive(Serialize)]
+pub struct VolleySummary {
+    pub time: String,
+    pub target: String,
+    /// Null for a fleet summary.
+    pub ip: Option<IpAddr>,
+    /// Targets whose volleys a fleet summary pooled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub targets: Option<Vec<String>>,
+    pub sent: usize,
+    pub received: usize,
+    pub lost: usize,
+    /// Percentage of requests lost.
+    pub loss: f64,
+    pub avg_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub stddev_ms: f64,
+    /// Latency at each of --percentiles, named like p99.9_ms, null with fewer than
+    /// --min-samples replies.
+    #[serde(flatten)]
+    pub percentiles_ms: BTreeMap<String, Option<f64>>,
+    /// RFC 3550 interarrival jitter and mean absolute difference between consecutive replies,
+    /// null with fewer than two replies.
+    pub jitter_ms: Option<f64>,
+    pub mean_deviation_ms: Option<f64>,
+    /// Call quality estimated with the E-model, null without replies.
+    pub r_factor: Option<f64>,
+    pub mos: Option<f64>,
+    /// Number of runs of consecutive lost requests, the longest, and how many there were of
+    /// each length.
+    pub loss_bursts: usize,
+    pub max_loss_burst: usize,
+    pub loss_burst_lengths: BTreeMap<usize, usize>,
+    /// Reply counts by latency bucket with --histogram.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<Vec<HistogramCount>>,
+    /// Replies that arrived after one to a later request, and the most requests one was behind.
+    pub reordered: usize,
+    pub max_reorder_distance: usize,
+    /// Sequence numbers and latencies of outlying replies with --outlier-mads.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outliers: Option<Vec<Outlier>>,
+    /// Moving averages of the target's volley averages and loss in percent with --ewma.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ewma_avg_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ewma_loss: Option<f64>,
+    /// Sequence numbers of requests without a valid reply. A fleet summary numbers the requests
+    /// of its targets one after another, in the order of `targets`.
+    pub missing: Vec<usize>,
+    /// Latencies by sequence number, null for requests without a valid reply.
+    pub latencies_ms: Vec<Option<f64>>,
+}
+
+/// A reply flagged by --outlier-mads.
+#[derive(Serialize)]
+pub struct Outlier {
+    pub seq: usize,
+    pub latency_ms: f64,
+}
+
+/// Replies in a --histogram bucket of a volley summary.
+#[derive(Serialize)]
+pub struct HistogramCount {
+    /// Upper bound of the bucket, null for the last one of replies slower than every bound.
+    pub le_ms: Option<f64>,
+    pub count: usize,
+}
+
+/// Parameters of a run, written at the start of output unless `--no-header` is given.
+#[derive(Serialize, Deserialize)]
+pub struct Metadata {
+    pub version: String,
+    pub args: Vec<String>,
+    pub start: String,
+    pub targets: Vec<TargetMetadata>,
+    /// Entropy of the generated payloads, unless payloads came from a file or were replayed.
+    pub entropy: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TargetMetadata {
+    pub target: String,
+    pub ip: IpAddr,
+}
+
+/// How metadata is written in NDJSON and CBOR, to tell it apart from volley records.
+#[derive(Serialize, Deserialize)]
+pub struct MetadataRecord {
+    pub metadata: Metadata,
+}
+
+/// A jump of the wall clock between volleys that monotonic time didn't see, e.g. an NTP step
+/// or a change of UTC offset. Latencies aren't affected, only the volley timestamps.
+#[derive(Serialize, Deserialize)]
+pub struct ClockStep {
+    /// Wall-clock times in RFC 3339, projected from the previous volley and read now.
+    pub expected: String,
+    pub actual: String,
+    /// How far the clock moved beyond the monotonic elapsed time, in milliseconds.
+    pub step_ms: i64,
+}
+
+/// How a clock step is written in NDJSON and CBOR, to tell it apart from volley records.
+#[derive(Serialize, Deserialize)]
+pub struct ClockStepRecord {
+    pub clock_step: ClockStep,
+}
+
+impl ClockStep {
+    /// Formats the step as a comment line for text and CSV output.
+    pub fn comment(&self) -> String {
+        format!(
+            "# clock step: {:+} ms, expected {}, now {}",
+            self.step_ms, self.expected, self.actual
+        )
+    }
+}
+
+/// Latencies of a round of volleys pooled across targets, written by `--fleet-summary`.
+#[derive(Serialize, Deserialize)]
+pub struct FleetSummary {
+    pub time: String,
+    /// Targets whose volleys were pooled.
+    pub targets: Vec<String>,
+    pub sent: usize,
+    pub received: usize,
+    pub lost: usize,
+    pub avg_ns: u64,
+    pub min_ns: u64,
+    pub max_ns: u64,
+    /// Percentiles of the pooled replies, null with fewer than --min-samples of them.
+    pub p50_ns: Option<u64>,
+    pub p99_ns: Option<u64>,
+}
+
+/// How a fleet summary is written in NDJSON and CBOR, to tell it apart from volley records.
+#[derive(Serialize, Deserialize)]
+pub struct FleetRecord {
+    pub fleet: FleetSummary,
+}
+
+impl FleetSummary {
+    pub fn new(time: String, targets: Vec<String>, stats: &VolleyStats) -> FleetSummary {
+        let nanos = |duration: Duration| duration.as_nanos() as u64;
+        let percentile = |duration: Duration| stats.percentiles.then(|| nanos(duration));
+        FleetSummary {
+            time,
+            targets,
+            sent: stats.sent,
+            received: stats.received,
+            lost: stats.lost,
+            avg_ns: nanos(stats.avg),
+            min_ns: nanos(stats.min),
+            max_ns: nanos(stats.max),
+            p50_ns: percentile(stats.percentile50),
+            p99_ns: percentile(stats.percentile99),
+        }
+    }
+}
+
+impl Metadata {
+    pub fn new(targets: Vec<TargetMetadata>, entropy: Option<Entropy>) -> Metadata {
+        Metadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            args: std::env::args().collect(),
+            start: chrono::Local::now().to_rfc3339(),
+            targets,
+            entropy: entropy.map(|e| e.name().to_string()),
+        }
+    }
+
+    /// Formats the metadata as a comment line for text and CSV output.
+    pub fn comment(&self) -> String {
+        let targets: Vec<String> = self
+            .targets
+            .iter()
+            .map(|t| format!("{} ({})", t.target, t.ip))
+            .collect();
+        let mut comment = format!(
+            "# epingm {}, started {}, args: {}, targets: {}",
+            self.version,
+            self.start,
+            self.args.join(" "),
+            targets.join(", ")
+        );
+        if let Some(entropy) = &self.entropy {
+            comment += &format!(", payload entropy: {}", entropy);
+        }
+        comment
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PacketRecord {
+    pub latency_ns: u64,
+    pub reply_size: usize,
+}
+
+impl VolleySummary {
+    pub fn new(
+        time: String,
+        target: &str,
+        ip: Option<IpAddr>,
+        info: &VolleyInfo,
+        stats: &VolleyStats,
+    ) -> VolleySummary {
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let percentile = |duration: Duration| stats.percentiles.then(|| millis(duration));
+        let loss = match stats.lost + stats.received {
+            0 => 0.0,
+            total => stats.lost as f64 * 100.0 / total as f64,
+        };
+        VolleySummary {
+            time,
+            target: target.to_string(),
+            ip,
+            targets: None,
+            sent: stats.sent,
+            received: stats.received,
+            lost: stats.lost,
+            loss,
+            avg_ms: millis(stats.avg),
+            min_ms: millis(stats.min),
+            max_ms: millis(stats.max),
+            stddev_ms: millis(stats.stddev),
+            percentiles_ms: stats
+                .by_percentile
+                .iter()
+                .map(|&(p, latency)| (format!("p{}_ms", p), percentile(latency)))
+                .collect(),
+            jitter_ms: stats.jitter.map(millis),
+            mean_deviation_ms: stats.mean_deviation.map(millis),
+            r_factor: stats.call_quality.map(|quality| quality.r_factor),
+            mos: stats.call_quality.map(|quality| quality.mos),
+            loss_bursts: stats.loss_bursts.len(),
+            max_loss_burst: stats.loss_bursts.iter().max().copied().unwrap_or(0),
+            loss_burst_lengths: burst_lengths(&stats.loss_bursts),
+            histogram: (!stats.histogram.is_empty()).then(|| {
+                stats
+                    .histogram
+                    .iter()
+                    .map(|bucket| HistogramCount {
+                        le_ms: bucket.upper,
+                        count: bucket.count,
+                    })
+                    .collect()
+            }),
+            reordered: stats.reordered,
+            max_reorder_distance: stats.max_reorder_distance,
+            outliers: stats.outliers.as_ref().map(|outliers| {
+                outliers
+                    .iter()
+                    .map(|&(seq, latency)| Outlier {
+                        seq,
+                        latency_ms: millis(latency),
+                    })
+                    .collect()
+            }),
+            ewma_avg_ms: None,
+            ewma_loss: None,
+            missing: stats.missing.clone(),
+            latencies_ms: info
+                .results
+                .iter()
+                .map(|result| result.as_ref().map(|r| millis(r.latency)))
+                .collect(),
+        }
+    }
+}
+
+impl VolleyRecord {
+    pub fn new(time: String, target: &str, ip: IpAddr, info: &VolleyInfo) -> VolleyRecord {
+        VolleyRecord {
+            time,
+            target: target.to_string(),
+            ip,
+            sent: info.sent,
+            results: info
+                .results
+                .iter()
+                .map(|result| {
+                    result.as_ref().map(|r| PacketRecord {
+                        latency_ns: r.latency.as_nanos() as u64,
+                        reply_size: r.reply_size,
+                    })
+                })
+                .collect(),
+            truncated: info.truncated,
+        }
+    }
+
+    /// Reconstructs the volley the record was written from.
+    pub fn to_info(&self) -> VolleyInfo {
+        let results: Vec<Option<PingResult>> = self
+            .results
+            .iter()
+            .map(|result| {
+                result.as_ref().map(|r| PingResult {
+                    latency: Duration::from_nanos(r.latency_ns),
+                    reply_size: r.reply_size,
+                    interface: None,
+                    ip_header: None,
+                })
+            })
+            .collect();
+        let received = results.iter().flatten().count();
+
+        VolleyInfo {
+            sent: self.sent,
+            received,
+            lost: results.len() - received,
+            corrupted: 0,
+            checksum_errors: 0,
+            rejected_sources: 0,
+            strays: Strays::default(),
+            icmp_errors: Vec::new(),
+            duplicates: 0,
+            excess_replies: 0,
+            lenient_matches: 0,
+            reordered: 0,
+            max_reorder_distance: 0,
+            max_in_flight: 0,
+            avg_in_flight: 0.0,
+            window_waits: 0,
+            first_index: 0,
+            interface: None,
+            profile: None,
+            truncated: self.truncated,
+            send_times: Vec::new(),
+            results,
+        }
+    }
+}
+
+fn check_record(record: &VolleyRecord) -> Result<(), String> {
+    if record.sent > record.results.len() {
+        return Err(format!(
+            "sent {} requests but has {} results",
+            record.sent,
+            record.results.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Reads all records from an NDJSON or CBOR file, failing on the first malformed record.
+pub fn read_records(path: &Path) -> io::Result<Vec<VolleyRecord>> {
+    let file = match File::open(path) {
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Failed to open {}: {}", path.display(), e),
+            ))
+        }
+        Ok(file) => file,
+    };
+
+    let mut reader = BufReader::new(file);
+    // NDJSON records are objects, while a CBOR record starts with a map header.
+    let json = match reader.fill_buf()?.first() {
+        None => return Ok(Vec::new()),
+        Some(byte) => byte.is_ascii_whitespace() || *byte == b'{',
+    };
+    if json {
+        read_ndjson(path, reader)
+    } else {
+        read_cbor(path, reader)
+    }
+}
+
+/// Whether a CBOR item is a metadata header, clock step or fleet summary rather than a volley
+/// record.
+fn is_annotation(value: &ciborium::Value) -> bool {
+    match value.as_map() {
+        None => false,
+        Some(entries) => entries
+            .iter()
+            .any(|(key, _)| matches!(key.as_text(), Some("metadata" | "clock_step" | "fleet"))),
+    }
+}
+
+fn read_cbor(path: &Path, mut reader: BufReader<File>) -> io::Result<Vec<VolleyRecord>> {
+    let mut records = Vec::new();
+    let mut item = 0;
+    while !reader.fill_buf()?.is_empty() {
+        item += 1;
+        let invalid = |message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: record {}: invalid volley record: {}",
+                    path.display(),
+                    item,
+                    message
+                ),
+            )
+        };
+        let value: ciborium::Value = match ciborium::from_reader(&mut reader) {
+            Err(e) => return Err(invalid(e.to_string())),
+            Ok(value) => value,
+        };
+        if is_annotation(&value) {
+            continue;
+        }
+        let record: VolleyRecord = match value.deserialized() {
+            Err(e) => return Err(invalid(e.to_string())),
+            Ok(record) => record,
+        };
+        check_record(&record).map_err(invalid)?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn read_ndjson(path: &Path, reader: BufReader<File>) -> io::Result<Vec<VolleyRecord>> {
+    let mut records = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: VolleyRecord = match serde_json::from_str(&line) {
+            Err(_) if serde_json::from_str::<MetadataRecord>(&line).is_ok() => continue,
+            Err(_) if serde_json::from_str::<ClockStepRecord>(&line).is_ok() => continue,
+            Err(_) if serde_json::from_str::<FleetRecord>(&line).is_ok() => continue,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: invalid volley record: {}", path.display(), i + 1, e),
+                ))
+            }
+            Ok(record) => record,
+        };
+        if let Err(e) = check_record(&record) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: invalid volley record: {}", path.display(), i + 1, e),
+            ));
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
@@ -0,0 +1,48 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::process::ExitCode;
+
+use crate::volley::{measure_volley, VolleyConfig, VolleyResult};
+
+/// Pings loopback while injecting a reply with a bad checksum and a duplicate reply, and checks
+/// that the receive path counts each of them.
+pub fn run(config: &VolleyConfig) -> ExitCode {
+    let config = VolleyConfig {
+        count: config.count.max(1),
+        verify_checksum: true,
+        inject_faults: true,
+        // Datagram sockets can't send the injected replies.
+        unprivileged: false,
+        ..config.clone()
+    };
+    let target = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    let info = match measure_volley(target, &config) {
+        VolleyResult::Error(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        VolleyResult::Success(info) => *info,
+    };
+
+    let checks = [
+        ("received", config.count, info.received),
+        ("checksum_errors", 1, info.checksum_errors),
+        ("duplicates", 1, info.duplicates),
+        ("corrupted", 0, info.corrupted),
+    ];
+    let mut success = true;
+    for (name, expected, actual) in checks {
+        if actual == expected {
+            println!("PASS: {} = {}", name, actual);
+        } else {
+            println!("FAIL: {} = {}, expected {}", name, actual, expected);
+            success = false;
+        }
+    }
+
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
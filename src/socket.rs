@@ -0,0 +1,690 @@
+use pnet::datalink;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+use std::time::{Duration, Instant, SystemTime};
+use std::{io, mem, ptr};
+
+/// Metadata of a datagram read with `recv_with_timeout`.
+pub struct RecvInfo {
+    pub size: usize,
+    pub source: IpAddr,
+    /// Index of the interface the datagram arrived on, if the platform reports it.
+    pub interface: Option<u32>,
+    /// When the datagram arrived: when the kernel received it if timestamps are enabled, when
+    /// it was read otherwise.
+    pub time: Instant,
+    /// When the NIC received or sent the datagram, on the NIC's own clock, if hardware
+    /// timestamps are enabled and the NIC took one.
+    pub hardware_time: Option<Duration>,
+    /// The datagram is a copy of one sent on the socket, whole down to the link layer header,
+    /// which the kernel returns on the error queue to report its hardware transmit timestamp.
+    pub sent: bool,
+}
+
+fn set_option(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sets the traffic class byte, DSCP in the upper six bits and ECN in the lower two, of packets
+/// sent on `fd`.
+pub fn set_traffic_class(fd: RawFd, target: IpAddr, traffic_class: u8) -> io::Result<()> {
+    let value = traffic_class as libc::c_int;
+    match target {
+        IpAddr::V4(_) => set_option(fd, libc::IPPROTO_IP, libc::IP_TOS, value),
+        IpAddr::V6(_) => set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, value),
+    }
+}
+
+/// Sets the hop limit, the TTL over IPv4, of packets sent on `fd`.
+pub fn set_hop_limit(fd: RawFd, target: IpAddr, hops: u8) -> io::Result<()> {
+    let value = hops as libc::c_int;
+    match target {
+        IpAddr::V4(_) => set_option(fd, libc::IPPROTO_IP, libc::IP_TTL, value),
+        IpAddr::V6(_) => set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, value),
+    }
+}
+
+/// Sets the don't fragment bit on packets sent on `fd`, without limiting them to the path MTU
+/// the kernel has cached, so that larger packets are still sent to probe the path.
+pub fn set_dont_fragment(fd: RawFd, target: IpAddr) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        match target {
+            IpAddr::V4(_) => set_option(
+                fd,
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            ),
+            IpAddr::V6(_) => set_option(
+                fd,
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MTU_DISCOVER,
+                libc::IPV6_PMTUDISC_PROBE,
+            ),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        _ = (fd, target);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Setting the don't fragment bit is only supported on Linux",
+        ))
+    }
+}
+
+/// Asks the kernel to attach the receiving interface to every datagram read from `fd`.
+pub fn enable_packet_info(fd: RawFd, target: IpAddr) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        match target {
+            IpAddr::V4(_) => set_option(fd, libc::IPPROTO_IP, libc::IP_PKTINFO, 1),
+            IpAddr::V6(_) => set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, 1),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        _ = (fd, target);
+        Ok(())
+    }
+}
+
+/// Opens an ICMP datagram socket for pinging `target`, which unlike a raw socket doesn't need
+/// root or CAP_NET_RAW. On Linux the group of the process must be in the
+/// `net.ipv4.ping_group_range` sysctl.
+pub fn open_icmp_datagram(target: IpAddr) -> io::Result<OwnedFd> {
+    let (domain, protocol) = match target {
+        IpAddr::V4(_) => (libc::AF_INET, libc::IPPROTO_ICMP),
+        IpAddr::V6(_) => (libc::AF_INET6, libc::IPPROTO_ICMPV6),
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_DGRAM, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Whether the process may open raw ICMP sockets, which takes root or CAP_NET_RAW.
+pub fn raw_icmp_permitted() -> bool {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_ICMP) };
+    if fd < 0 {
+        return false;
+    }
+    unsafe { libc::close(fd) };
+    true
+}
+
+fn ip_to_sockaddr(target: IpAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let addr_len = match target {
+        IpAddr::V4(ip) => {
+            let addr = unsafe { &mut *(&mut addr as *mut _ as *mut libc::sockaddr_in) };
+            addr.sin_family = libc::AF_INET as libc::sa_family_t;
+            addr.sin_addr.s_addr = u32::from(ip).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        IpAddr::V6(ip) => {
+            let addr = unsafe { &mut *(&mut addr as *mut _ as *mut libc::sockaddr_in6) };
+            addr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            addr.sin6_addr.s6_addr = ip.octets();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (addr, addr_len as libc::socklen_t)
+}
+
+/// Binds `fd` to the local address `source`, which packets sent on it then come from.
+pub fn bind(fd: RawFd, source: IpAddr) -> io::Result<()> {
+    let (addr, addr_len) = ip_to_sockaddr(source);
+    let ret = unsafe { libc::bind(fd, &addr as *const _ as *const libc::sockaddr, addr_len) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sends `packet` to `target` on `fd`, a socket that isn't connected.
+pub fn send_to(fd: RawFd, packet: &[u8], target: IpAddr) -> io::Result<usize> {
+    let (addr, addr_len) = ip_to_sockaddr(target);
+    let size = unsafe {
+        libc::sendto(
+            fd,
+            packet.as_ptr() as *const libc::c_void,
+            packet.len(),
+            0,
+            &addr as *const _ as *const libc::sockaddr,
+            addr_len,
+        )
+    };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+/// Sends `packets` to `target` on `fd` in as few syscalls as the platform allows. Packets the
+/// kernel refuses are skipped and the rest still sent, as separate sends would. Returns how many
+/// were sent, and the last error if any weren't.
+pub fn send_batch(fd: RawFd, packets: &[Vec<u8>], target: IpAddr) -> (usize, Option<io::Error>) {
+    #[cfg(target_os = "linux")]
+    {
+        let (mut addr, addr_len) = ip_to_sockaddr(target);
+        let mut iovs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|packet| libc::iovec {
+                iov_base: packet.as_ptr() as *mut libc::c_void,
+                iov_len: packet.len(),
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = iovs
+            .iter_mut()
+            .map(|iov| {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+                msg.msg_hdr.msg_namelen = addr_len;
+                msg.msg_hdr.msg_iov = iov;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+        let mut sent = 0;
+        let mut error = None;
+        let mut next = 0;
+        // sendmmsg stops at the first packet that fails and reports those before it as sent, so
+        // send the rest again until every packet was accepted or refused.
+        while next < msgs.len() {
+            let remaining = &mut msgs[next..];
+            let count =
+                unsafe { libc::sendmmsg(fd, remaining.as_mut_ptr(), remaining.len() as _, 0) };
+            if count < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                // An error is only returned for the first packet, as later ones would have been
+                // counted as sent.
+                error = Some(e);
+                next += 1;
+            } else {
+                sent += count as usize;
+                next += count as usize;
+            }
+        }
+        (sent, error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let mut sent = 0;
+        let mut error = None;
+        for packet in packets {
+            match send_to(fd, packet, target) {
+                Ok(_) => sent += 1,
+                Err(e) => error = Some(e),
+            }
+        }
+        (sent, error)
+    }
+}
+
+/// Asks the kernel to timestamp every datagram read from `fd` when it's received, so that
+/// latency doesn't include the time until the receiver got to read it.
+pub fn enable_timestamps(fd: RawFd) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        set_option(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, 1)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        _ = fd;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Kernel timestamps are only supported on Linux",
+        ))
+    }
+}
+
+/// Asks the NIC of `interface` to timestamp every packet it sends and receives, and the kernel
+/// to attach those timestamps to datagrams read from `fd` and return copies of datagrams sent on
+/// it with theirs. The timestamps are on the NIC's clock, so they can only be compared with
+/// each other. Configuring the NIC needs CAP_NET_ADMIN.
+pub fn enable_hardware_timestamps(fd: RawFd, interface: &str) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut config = libc::hwtstamp_config {
+            flags: 0,
+            tx_type: libc::HWTSTAMP_TX_ON as libc::c_int,
+            rx_filter: libc::HWTSTAMP_FILTER_ALL as libc::c_int,
+        };
+        let mut request: libc::ifreq = unsafe { mem::zeroed() };
+        if interface.len() >= request.ifr_name.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Interface name {} is too long", interface),
+            ));
+        }
+        for (c, byte) in request.ifr_name.iter_mut().zip(interface.bytes()) {
+            *c = byte as libc::c_char;
+        }
+        request.ifr_ifru.ifru_data = &mut config as *mut _ as *mut libc::c_char;
+        let ret = unsafe { libc::ioctl(fd, libc::SIOCSHWTSTAMP as _, &mut request) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let flags = libc::SOF_TIMESTAMPING_TX_HARDWARE
+            | libc::SOF_TIMESTAMPING_RX_HARDWARE
+            | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+        set_option(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            flags as libc::c_int,
+        )
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        _ = (fd, interface);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Hardware timestamps are only supported on Linux",
+        ))
+    }
+}
+
+/// Waits up to `timeout` for `fd` to become readable, returning the events that occurred, or 0
+/// if none did.
+fn wait_readable(fd: RawFd, timeout: Duration) -> io::Result<libc::c_short> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // Round up so that sub-millisecond timeouts don't turn into a busy loop.
+    let millis = timeout
+        .as_micros()
+        .div_ceil(1000)
+        .min(libc::c_int::MAX as u128) as libc::c_int;
+
+    loop {
+        let ret = unsafe { libc::poll(&mut pollfd, 1, millis) };
+        if ret < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        return Ok(if ret > 0 { pollfd.revents } else { 0 });
+    }
+}
+
+fn sockaddr_to_ip(addr: &libc::sockaddr_storage) -> io::Result<IpAddr> {
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+            Ok(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                addr.sin_addr.s_addr,
+            ))))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+            Ok(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+        }
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected address family: {}", family),
+        )),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn packet_interface(msg: &libc::msghdr) -> Option<u32> {
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            let header = &*cmsg;
+            if header.cmsg_level == libc::IPPROTO_IP && header.cmsg_type == libc::IP_PKTINFO {
+                let info = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo);
+                return Some(info.ipi_ifindex as u32);
+            }
+            if header.cmsg_level == libc::IPPROTO_IPV6 && header.cmsg_type == libc::IPV6_PKTINFO {
+                let info = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in6_pktinfo);
+                return Some(info.ipi6_ifindex);
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn packet_interface(_msg: &libc::msghdr) -> Option<u32> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn packet_timestamp(msg: &libc::msghdr) -> Option<SystemTime> {
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            let header = &*cmsg;
+            if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_TIMESTAMPNS {
+                let time = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::timespec);
+                let since_epoch = Duration::new(time.tv_sec as u64, time.tv_nsec as u32);
+                return SystemTime::UNIX_EPOCH.checked_add(since_epoch);
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn packet_timestamp(_msg: &libc::msghdr) -> Option<SystemTime> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn packet_hardware_time(msg: &libc::msghdr) -> Option<Duration> {
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            let header = &*cmsg;
+            if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_TIMESTAMPING {
+                // Software, legacy and raw hardware timestamps, of which only the last is
+                // enabled.
+                let times =
+                    ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const [libc::timespec; 3]);
+                let time = Duration::new(times[2].tv_sec as u64, times[2].tv_nsec as u32);
+                return (!time.is_zero()).then_some(time);
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn packet_hardware_time(_msg: &libc::msghdr) -> Option<Duration> {
+    None
+}
+
+/// Reads a datagram from `fd` into `buffer` with the recvmsg `flags`, from the error queue if
+/// `sent`.
+fn recv_message(
+    fd: RawFd,
+    buffer: &mut [u8],
+    flags: libc::c_int,
+    sent: bool,
+) -> io::Result<RecvInfo> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    // u64 elements keep the buffer aligned for cmsghdr.
+    let mut control = [0u64; 64];
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of_val(&addr) as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let size = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    recv_info(&msg, &addr, size as usize, sent)
+}
+
+/// Metadata of a datagram of `size` bytes read with `msg`, whose name is `addr`.
+fn recv_info(
+    msg: &libc::msghdr,
+    addr: &libc::sockaddr_storage,
+    size: usize,
+    sent: bool,
+) -> io::Result<RecvInfo> {
+    let now = Instant::now();
+    // Kernel timestamps are on the wall clock, so go back by how long ago that was.
+    let time = match packet_timestamp(msg) {
+        Some(timestamp) => SystemTime::now()
+            .duration_since(timestamp)
+            .ok()
+            .and_then(|ago| now.checked_sub(ago))
+            .unwrap_or(now),
+        None => now,
+    };
+    let source = match sockaddr_to_ip(addr) {
+        // Copies of sent datagrams come without an address.
+        Err(_) if sent => Ipv4Addr::UNSPECIFIED.into(),
+        source => source?,
+    };
+
+    Ok(RecvInfo {
+        size,
+        source,
+        interface: packet_interface(msg),
+        time,
+        hardware_time: packet_hardware_time(msg),
+        sent,
+    })
+}
+
+/// Waits up to `timeout` for any of `fds` to become readable, returning the events that
+/// occurred on each. Returns early with no events if a signal arrives, so the caller can check
+/// for an interrupt.
+pub fn poll_readable(fds: &[RawFd], timeout: Duration) -> io::Result<Vec<libc::c_short>> {
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+
+    // ppoll keeps the sub-millisecond timeouts that pace requests.
+    #[cfg(target_os = "linux")]
+    let ret = {
+        let timeout = libc::timespec {
+            tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        unsafe {
+            libc::ppoll(
+                pollfds.as_mut_ptr(),
+                pollfds.len() as libc::nfds_t,
+                &timeout,
+                ptr::null(),
+            )
+        }
+    };
+    #[cfg(not(target_os = "linux"))]
+    let ret = {
+        let millis = timeout
+            .as_micros()
+            .div_ceil(1000)
+            .min(libc::c_int::MAX as u128) as libc::c_int;
+        unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, millis) }
+    };
+
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(pollfds
+        .iter()
+        .map(|pollfd| if ret > 0 { pollfd.revents } else { 0 })
+        .collect())
+}
+
+/// Waits up to `timeout` for a datagram on `fd` and reads it into `buffer`. Copies of sent
+/// datagrams on the error queue are read first.
+///
+/// Returns `Ok(None)` if nothing arrived in time.
+pub fn recv_with_timeout(
+    fd: RawFd,
+    buffer: &mut [u8],
+    timeout: Duration,
+) -> io::Result<Option<RecvInfo>> {
+    let events = wait_readable(fd, timeout)?;
+    if events == 0 {
+        return Ok(None);
+    }
+    // An error is also reported when the socket has a pending error and nothing queued, which
+    // the plain read below returns.
+    if events & libc::POLLERR != 0 {
+        match recv_message(fd, buffer, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT, true) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            result => return result.map(Some),
+        }
+    }
+    recv_message(fd, buffer, 0, false).map(Some)
+}
+
+/// Waits up to `timeout` for datagrams on `fd` and reads those queued, one into each of
+/// `buffers` at most, in one syscall where the platform allows. Copies of sent datagrams are
+/// read one at a time.
+///
+/// Returns no datagrams if nothing arrived in time.
+pub fn recv_batch_with_timeout(
+    fd: RawFd,
+    buffers: &mut [Vec<u8>],
+    timeout: Duration,
+) -> io::Result<Vec<RecvInfo>> {
+    #[cfg(target_os = "linux")]
+    {
+        let events = wait_readable(fd, timeout)?;
+        if events == 0 {
+            return Ok(Vec::new());
+        }
+        if events & libc::POLLERR != 0 {
+            let info = recv_with_timeout(fd, &mut buffers[0], Duration::ZERO)?;
+            return Ok(info.into_iter().collect());
+        }
+
+        let count = buffers.len();
+        let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; count];
+        // u64 elements keep the buffers aligned for cmsghdr.
+        let mut controls = vec![[0u64; 64]; count];
+        let mut iovs: Vec<libc::iovec> = buffers
+            .iter_mut()
+            .map(|buffer| libc::iovec {
+                iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.len(),
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = Vec::with_capacity(count);
+        for ((addr, control), iov) in addrs.iter_mut().zip(&mut controls).zip(&mut iovs) {
+            let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+            msg.msg_hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+            msg.msg_hdr.msg_namelen = mem::size_of_val(addr) as libc::socklen_t;
+            msg.msg_hdr.msg_iov = iov;
+            msg.msg_hdr.msg_iovlen = 1;
+            msg.msg_hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_hdr.msg_controllen = mem::size_of_val(control) as _;
+            msgs.push(msg);
+        }
+
+        let received = unsafe {
+            libc::recvmmsg(
+                fd,
+                msgs.as_mut_ptr(),
+                count as _,
+                libc::MSG_DONTWAIT as _,
+                ptr::null_mut(),
+            )
+        };
+        if received < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::WouldBlock {
+                return Ok(Vec::new());
+            }
+            return Err(e);
+        }
+        msgs.iter()
+            .zip(&addrs)
+            .take(received as usize)
+            .map(|(msg, addr)| recv_info(&msg.msg_hdr, addr, msg.msg_len as usize, false))
+            .collect()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let info = recv_with_timeout(fd, &mut buffers[0], timeout)?;
+        Ok(info.into_iter().collect())
+    }
+}
+
+/// Returns the index of the interface packets to `target` are sent from: the one with the
+/// address `source` if the socket is bound to it, otherwise the one the kernel would route them
+/// through.
+pub fn outgoing_interface(target: IpAddr, source: Option<IpAddr>) -> Option<u32> {
+    let local = match source {
+        Some(source) => source,
+        None => {
+            let bind_addr = match target {
+                IpAddr::V4(_) => "0.0.0.0:0",
+                IpAddr::V6(_) => "[::]:0",
+            };
+            // Connecting a UDP socket performs the route lookup without sending anything.
+            let socket = UdpSocket::bind(bind_addr).ok()?;
+            socket.connect(SocketAddr::new(target, 9)).ok()?;
+            socket.local_addr().ok()?.ip()
+        }
+    };
+
+    datalink::interfaces()
+        .into_iter()
+        .find(|interface| interface.ips.iter().any(|network| network.ip() == local))
+        .map(|interface| interface.index)
+}
+
+/// Returns the MTU of the interface with the given index, where the platform makes it available.
+pub fn interface_mtu(index: u32) -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        let name = datalink::interfaces()
+            .into_iter()
+            .find(|i| i.index == index)?
+            .name;
+        let mtu = std::fs::read_to_string(format!("/sys/class/net/{}/mtu", name)).ok()?;
+        mtu.trim().parse().ok()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        _ = index;
+        None
+    }
+}
+
+pub fn interface_name(index: u32) -> String {
+    match datalink::interfaces()
+        .into_iter()
+        .find(|i| i.index == index)
+    {
+        Some(interface) => interface.name,
+        None => format!("#{}", index),
+    }
+}
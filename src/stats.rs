@@ -0,0 +1,716 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::volley::{self, PingResult, VolleyInfo};
+
+/// Ratio of the standard deviation of a normal distribution to its median absolute deviation.
+const MAD_SCALE: f64 = 1.4826;
+/// Smallest deviation in milliseconds outliers are measured in, below timer resolution.
+const MIN_DEVIATION: f64 = 0.01;
+
+/// Latency above which a reply counts as a spike.
+#[derive(Clone, Copy, Debug)]
+pub enum SpikeThreshold {
+    Millis(f64),
+    /// Multiple of the volley's median latency.
+    MedianMultiple(f64),
+}
+
+impl FromStr for SpikeThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, threshold): (&str, fn(f64) -> SpikeThreshold) = match s.strip_suffix('x') {
+            Some(factor) => (factor, SpikeThreshold::MedianMultiple),
+            None => (s.strip_suffix("ms").unwrap_or(s), SpikeThreshold::Millis),
+        };
+        match value.trim().parse::<f64>() {
+            Ok(value) if value > 0.0 => Ok(threshold(value)),
+            _ => Err(format!(
+                "invalid spike threshold '{}', expected milliseconds (e.g. 50) or a multiple of the median (e.g. 3x)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StatsOptions {
+    pub timeout: Duration,
+    pub spike_threshold: Option<SpikeThreshold>,
+    /// Median absolute deviations above the median beyond which a reply is an outlier.
+    pub outlier_mads: Option<f64>,
+    /// Bounds on the reply payload size in bytes.
+    pub min_reply_size: Option<usize>,
+    pub max_reply_size: Option<usize>,
+    /// Share of reordered replies above which a volley is flagged.
+    pub reorder_ratio_threshold: Option<f64>,
+    /// De-jitter buffer sizes in milliseconds to estimate late packets for.
+    pub jitter_buffers: Vec<f64>,
+    /// DSCP values the requests rotated through.
+    pub dscp: Vec<u8>,
+    /// Latency above which a reply counts as lost, leaving it out of latency statistics too.
+    pub slow_as_loss: Option<Duration>,
+    /// Fewest replies the percentiles are reported for.
+    pub min_samples: usize,
+    /// Percentiles of latency to report.
+    pub percentiles: Vec<f64>,
+    /// Ascending upper bounds in milliseconds of the latency histogram buckets, empty for no
+    /// histogram.
+    pub histogram_buckets: Vec<f64>,
+}
+
+/// Estimate of the quality of a voice call over the path, from the simplified E-model of
+/// ITU-T G.107.
+#[derive(Clone, Copy)]
+pub struct CallQuality {
+    /// Transmission rating from 0 to 100, where 80 and up is what users are satisfied with.
+    pub r_factor: f64,
+    /// Mean opinion score from 1 (bad) to 4.5 (excellent) the R-factor maps to.
+    pub mos: f64,
+}
+
+/// Replies of a volley in a latency range.
+pub struct HistogramBucket {
+    /// Lower bound in milliseconds, exclusive except for the first bucket's 0.
+    pub lower: f64,
+    /// Upper bound in milliseconds, inclusive, or `None` for the bucket of replies slower than
+    /// every bound.
+    pub upper: Option<f64>,
+    pub count: usize,
+}
+
+/// Summary of the requests of a volley sent with one DSCP value.
+pub struct DscpStats {
+    pub dscp: u8,
+    pub received: usize,
+    pub sent: usize,
+    /// Average latency, if any replies were received.
+    pub avg: Option<Duration>,
+}
+
+/// Summary of a volley. Latencies fall back to the timeout if nothing was received.
+pub struct VolleyStats {
+    pub received: usize,
+    pub sent: usize,
+    pub lost: usize,
+    pub avg: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// Population standard deviation of the latencies of replies, zero without any.
+    pub stddev: Duration,
+    pub percentile50: Duration,
+    pub percentile99: Duration,
+    /// Whether there were at least `min_samples` replies, so the percentiles mean something.
+    pub percentiles: bool,
+    /// Latency at each percentile in `StatsOptions::percentiles`.
+    pub by_percentile: Vec<(f64, Duration)>,
+    /// Interarrival jitter as RFC 3550 estimates it from consecutive replies, if there are at
+    /// least two.
+    pub jitter: Option<Duration>,
+    /// Mean absolute difference between the latencies of consecutive replies, if there are at
+    /// least two.
+    pub mean_deviation: Option<Duration>,
+    /// Call quality estimated with the E-model, if there were replies.
+    pub call_quality: Option<CallQuality>,
+    pub missing: Vec<usize>,
+    /// Lengths of the runs of consecutive requests in `missing`, in order.
+    pub loss_bursts: Vec<usize>,
+    /// Sequence numbers of replies slower than the spike threshold, if one is configured.
+    pub spikes: Option<Vec<usize>>,
+    /// Sequence numbers and latencies of outlying replies, if outlier detection is configured.
+    pub outliers: Option<Vec<(usize, Duration)>>,
+    /// Number of replies outside the reply size bounds, if any are configured.
+    pub size_violations: Option<usize>,
+    /// Replies that arrived after one to a later request, and the most requests one was behind.
+    pub reordered: usize,
+    pub max_reorder_distance: usize,
+    /// Share of replies that arrived out of order, if a threshold is configured.
+    pub reorder_ratio: Option<f64>,
+    /// Whether the reorder ratio exceeded the threshold.
+    pub reorder_warning: bool,
+    /// Share of replies that would miss their playout deadline, by jitter buffer size.
+    pub late_by_jitter_buffer: Vec<(f64, f64)>,
+    /// Summaries by DSCP value, if the requests rotated through more than one.
+    pub by_dscp: Vec<DscpStats>,
+    /// Distribution of latencies, if histogram buckets are configured.
+    pub histogram: Vec<HistogramBucket>,
+}
+
+fn millis(duration: &Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+pub fn summarize(info: &VolleyInfo, options: &StatsOptions) -> VolleyStats {
+    // Slow replies are lost for every statistic, not only the counts.
+    let without_slow;
+    let info = match options.slow_as_loss {
+        None => info,
+        Some(limit) => {
+            without_slow = drop_slow(info, limit);
+            &without_slow
+        }
+    };
+
+    let mut sum = Duration::ZERO;
+    let mut latencies: Vec<Duration> = Vec::new();
+    let mut missing: Vec<usize> = Vec::new();
+
+    for (i, result) in info.results.iter().enumerate() {
+        match result {
+            None => {
+                missing.push(i);
+            }
+            Some(PingResult { latency, .. }) => {
+                latencies.push(*latency);
+                sum += *latency;
+            }
+        }
+    }
+
+    let avg = if info.received > 0 {
+        sum / info.received as u32
+    } else {
+        options.timeout
+    };
+
+    let stddev = stddev(&latencies);
+    latencies.sort();
+
+    let min;
+    let max;
+    if !latencies.is_empty() {
+        min = latencies[0];
+        max = latencies[latencies.len() - 1];
+    } else {
+        min = options.timeout;
+        max = options.timeout;
+    }
+    let latency_at = |p: f64| percentile(&latencies, p).unwrap_or(options.timeout);
+
+    let reorder_ratio = options.reorder_ratio_threshold.map(|_| {
+        if info.received > 0 {
+            info.reordered as f64 / info.received as f64
+        } else {
+            0.0
+        }
+    });
+    let reorder_warning = match (reorder_ratio, options.reorder_ratio_threshold) {
+        (Some(ratio), Some(threshold)) => ratio > threshold,
+        _ => false,
+    };
+
+    let jitter = jitter(info);
+    let call_quality = (info.received > 0).then(|| {
+        let loss = info.lost as f64 * 100.0 / (info.received + info.lost) as f64;
+        call_quality(avg, jitter.unwrap_or(Duration::ZERO), loss)
+    });
+
+    VolleyStats {
+        received: info.received,
+        sent: info.sent,
+        lost: info.lost,
+        avg,
+        min,
+        max,
+        stddev,
+        percentile50: latency_at(50.0),
+        percentile99: latency_at(99.0),
+        percentiles: latencies.len() >= options.min_samples,
+        by_percentile: options
+            .percentiles
+            .iter()
+            .map(|&p| (p, latency_at(p)))
+            .collect(),
+        jitter,
+        mean_deviation: mean_deviation(info),
+        call_quality,
+        loss_bursts: loss_bursts(&missing),
+        missing,
+        spikes: options.spike_threshold.map(|t| find_spikes(info, t)),
+        outliers: options.outlier_mads.map(|k| find_outliers(info, k)),
+        size_violations: count_size_violations(info, options),
+        reordered: info.reordered,
+        max_reorder_distance: info.max_reorder_distance,
+        reorder_ratio,
+        reorder_warning,
+        late_by_jitter_buffer: late_fractions(info, &options.jitter_buffers),
+        by_dscp: group_by_dscp(info, &options.dscp),
+        histogram: histogram(info, &options.histogram_buckets),
+    }
+}
+
+/// Copy of `info` with replies slower than `limit` counted as lost.
+fn drop_slow(info: &VolleyInfo, limit: Duration) -> VolleyInfo {
+    let mut info = info.clone();
+    for result in &mut info.results {
+        if result.as_ref().is_some_and(|r| r.latency > limit) {
+            *result = None;
+            info.received -= 1;
+            info.lost += 1;
+        }
+    }
+    info
+}
+
+/// Estimates call quality from the round-trip latency, jitter and loss in percent the way
+/// network monitors commonly simplify the E-model: jitter counts double, as a de-jitter buffer
+/// adds it to the delay, and 10 ms are added for codec delay.
+fn call_quality(latency: Duration, jitter: Duration, loss: f64) -> CallQuality {
+    let effective = millis(&latency) + 2.0 * millis(&jitter) + 10.0;
+    let delay_impairment = if effective < 160.0 {
+        effective / 40.0
+    } else {
+        (effective - 120.0) / 10.0
+    };
+    let r_factor = (93.2 - delay_impairment - 2.5 * loss).clamp(0.0, 100.0);
+    let mos = 1.0 + 0.035 * r_factor + 0.000007 * r_factor * (r_factor - 60.0) * (100.0 - r_factor);
+    CallQuality {
+        r_factor,
+        mos: mos.clamp(1.0, 4.5),
+    }
+}
+
+/// Lengths of the runs of consecutive sequence numbers in the ascending `missing`.
+fn loss_bursts(missing: &[usize]) -> Vec<usize> {
+    let mut bursts: Vec<usize> = Vec::new();
+    for (i, &seq) in missing.iter().enumerate() {
+        match bursts.last_mut() {
+            Some(length) if i > 0 && missing[i - 1] + 1 == seq => *length += 1,
+            _ => bursts.push(1),
+        }
+    }
+    bursts
+}
+
+/// Lengths of bursts and how many of each there were, shortest first.
+pub fn burst_lengths(bursts: &[usize]) -> BTreeMap<usize, usize> {
+    let mut lengths = BTreeMap::new();
+    for &length in bursts {
+        *lengths.entry(length).or_insert(0) += 1;
+    }
+    lengths
+}
+
+/// The latency `p` percent of `sorted` are faster than, `None` if it's empty.
+fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let index = (sorted.len() as f64 * p / 100.0) as usize;
+    Some(sorted[index.min(sorted.len() - 1)])
+}
+
+/// Population standard deviation of `latencies`, zero if there are none.
+fn stddev(latencies: &[Duration]) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let count = latencies.len() as f64;
+    let mean = latencies.iter().map(Duration::as_secs_f64).sum::<f64>() / count;
+    let variance = latencies
+        .iter()
+        .map(|latency| (latency.as_secs_f64() - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    Duration::from_secs_f64(variance.sqrt())
+}
+
+/// Interarrival jitter of RFC 3550, a running average of the difference between the latencies
+/// of consecutive replies that moves a sixteenth of the way to each new difference, so it
+/// follows recent variation while smoothing out single outliers.
+fn jitter(info: &VolleyInfo) -> Option<Duration> {
+    let latencies: Vec<f64> = info
+        .results
+        .iter()
+        .flatten()
+        .map(|r| r.latency.as_secs_f64())
+        .collect();
+    if latencies.len() < 2 {
+        return None;
+    }
+    let jitter = latencies.windows(2).fold(0.0, |jitter, w| {
+        jitter + ((w[1] - w[0]).abs() - jitter) / 16.0
+    });
+    Some(Duration::from_secs_f64(jitter))
+}
+
+/// Mean absolute difference between the latencies of consecutive replies, if there are at
+/// least two.
+fn mean_deviation(info: &VolleyInfo) -> Option<Duration> {
+    let latencies: Vec<Duration> = info.results.iter().flatten().map(|r| r.latency).collect();
+    if latencies.len() < 2 {
+        return None;
+    }
+    let sum: Duration = latencies.windows(2).map(|w| w[0].abs_diff(w[1])).sum();
+    Some(sum / (latencies.len() - 1) as u32)
+}
+
+fn group_by_dscp(info: &VolleyInfo, dscp: &[u8]) -> Vec<DscpStats> {
+    if dscp.len() < 2 {
+        return Vec::new();
+    }
+
+    let mut groups: Vec<(DscpStats, Duration)> = Vec::new();
+    for (i, result) in info.results.iter().enumerate() {
+        let value = volley::dscp_of(dscp, info.first_index + i).unwrap();
+        let index = match groups.iter().position(|(group, _)| group.dscp == value) {
+            Some(index) => index,
+            None => {
+                let group = DscpStats {
+                    dscp: value,
+                    received: 0,
+                    sent: 0,
+                    avg: None,
+                };
+                groups.push((group, Duration::ZERO));
+                groups.len() - 1
+            }
+        };
+        let (group, sum) = &mut groups[index];
+        group.sent += 1;
+        if let Some(result) = result {
+            group.received += 1;
+            *sum += result.latency;
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(mut group, sum)| {
+            if group.received > 0 {
+                group.avg = Some(sum / group.received as u32);
+            }
+            group
+        })
+        .collect()
+}
+
+/// Estimates the share of replies a de-jitter buffer of each size would play out late.
+///
+/// Playout is scheduled at the fastest reply's latency plus the buffer size, so a reply is late
+/// if it took longer than that.
+fn late_fractions(info: &VolleyInfo, buffers: &[f64]) -> Vec<(f64, f64)> {
+    let latencies: Vec<f64> = info
+        .results
+        .iter()
+        .flatten()
+        .map(|r| millis(&r.latency))
+        .collect();
+    let base = latencies.iter().copied().fold(f64::INFINITY, f64::min);
+
+    buffers
+        .iter()
+        .map(|&buffer| {
+            if latencies.is_empty() {
+                return (buffer, 0.0);
+            }
+            let late = latencies.iter().filter(|&&l| l > base + buffer).count();
+            (buffer, late as f64 / latencies.len() as f64)
+        })
+        .collect()
+}
+
+/// Counts replies in each bucket bounded by `bounds`, plus a last one for slower replies.
+fn histogram(info: &VolleyInfo, bounds: &[f64]) -> Vec<HistogramBucket> {
+    if bounds.is_empty() {
+        return Vec::new();
+    }
+    let lowers = [0.0].iter().chain(bounds);
+    let uppers = bounds.iter().map(|&upper| Some(upper)).chain([None]);
+    let mut buckets: Vec<HistogramBucket> = lowers
+        .zip(uppers)
+        .map(|(&lower, upper)| HistogramBucket {
+            lower,
+            upper,
+            count: 0,
+        })
+        .collect();
+    for result in info.results.iter().flatten() {
+        let latency = millis(&result.latency);
+        let index = bounds
+            .iter()
+            .position(|&upper| latency <= upper)
+            .unwrap_or(bounds.len());
+        buckets[index].count += 1;
+    }
+    buckets
+}
+
+fn count_size_violations(info: &VolleyInfo, options: &StatsOptions) -> Option<usize> {
+    if options.min_reply_size.is_none() && options.max_reply_size.is_none() {
+        return None;
+    }
+    let min = options.min_reply_size.unwrap_or(0);
+    let max = options.max_reply_size.unwrap_or(usize::MAX);
+    Some(
+        info.results
+            .iter()
+            .flatten()
+            .filter(|r| r.reply_size < min || r.reply_size > max)
+            .count(),
+    )
+}
+
+fn median_millis(info: &VolleyInfo) -> Option<f64> {
+    let mut latencies: Vec<f64> = info
+        .results
+        .iter()
+        .flatten()
+        .map(|r| millis(&r.latency))
+        .collect();
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort_by(|a, b| a.total_cmp(b));
+    Some(latencies[latencies.len() / 2])
+}
+
+/// Finds replies more than `k` median absolute deviations slower than the median. The
+/// deviation is scaled to match the standard deviation of normally distributed latencies, and
+/// kept from falling below MIN_DEVIATION so volleys of nearly identical latencies don't flag
+/// every reply above the median.
+fn find_outliers(info: &VolleyInfo, k: f64) -> Vec<(usize, Duration)> {
+    let median = match median_millis(info) {
+        None => return Vec::new(),
+        Some(median) => median,
+    };
+    let mut deviations: Vec<f64> = info
+        .results
+        .iter()
+        .flatten()
+        .map(|r| (millis(&r.latency) - median).abs())
+        .collect();
+    deviations.sort_by(|a, b| a.total_cmp(b));
+    let deviation = (deviations[deviations.len() / 2] * MAD_SCALE).max(MIN_DEVIATION);
+    let threshold = median + k * deviation;
+
+    info.results
+        .iter()
+        .enumerate()
+        .filter_map(|(seq, result)| match result {
+            Some(result) if millis(&result.latency) > threshold => Some((seq, result.latency)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn find_spikes(info: &VolleyInfo, threshold: SpikeThreshold) -> Vec<usize> {
+    let threshold = match threshold {
+        SpikeThreshold::Millis(threshold) => threshold,
+        SpikeThreshold::MedianMultiple(factor) => match median_millis(info) {
+            None => return Vec::new(),
+            Some(median) => median * factor,
+        },
+    };
+
+    info.results
+        .iter()
+        .enumerate()
+        .filter_map(|(seq, result)| match result {
+            Some(result) if millis(&result.latency) > threshold => Some(seq),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A volley with replies of the given latencies in microseconds, `None` for lost requests.
+    fn volley(latencies: &[Option<u64>]) -> VolleyInfo {
+        let mut info = VolleyInfo::pool([]);
+        info.results = latencies
+            .iter()
+            .map(|latency| {
+                latency.map(|micros| PingResult {
+                    latency: Duration::from_micros(micros),
+                    reply_size: 64,
+                    interface: None,
+                    ip_header: None,
+                })
+            })
+            .collect();
+        info.sent = latencies.len();
+        info.received = info.results.iter().flatten().count();
+        info.lost = info.sent - info.received;
+        info
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} isn't close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let info = volley(&[Some(10_000), None, Some(20_000), Some(30_000)]);
+        let stats = summarize(&info, &StatsOptions::default());
+        assert_eq!((stats.sent, stats.received, stats.lost), (4, 3, 1));
+        assert_eq!(stats.avg, Duration::from_millis(20));
+        assert_eq!(stats.min, Duration::from_millis(10));
+        assert_eq!(stats.max, Duration::from_millis(30));
+        assert_eq!(stats.percentile50, Duration::from_millis(20));
+        assert_eq!(stats.missing, vec![1]);
+        assert_eq!(stats.loss_bursts, vec![1]);
+        assert_close(millis(&stats.jitter.unwrap()), 1.2109375);
+        assert_eq!(stats.mean_deviation, Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn slow_as_loss() {
+        let info = volley(&[Some(10_000), Some(500_000), Some(20_000), Some(30_000)]);
+        let options = StatsOptions {
+            slow_as_loss: Some(Duration::from_millis(100)),
+            ..StatsOptions::default()
+        };
+        let stats = summarize(&info, &options);
+        let expected = summarize(
+            &volley(&[Some(10_000), None, Some(20_000), Some(30_000)]),
+            &options,
+        );
+        assert_eq!((stats.sent, stats.received, stats.lost), (4, 3, 1));
+        assert_eq!(stats.avg, Duration::from_millis(20));
+        assert_eq!(stats.max, Duration::from_millis(30));
+        assert_eq!(stats.missing, vec![1]);
+        assert_eq!(stats.jitter, expected.jitter);
+        assert_close(
+            stats.call_quality.unwrap().r_factor,
+            expected.call_quality.unwrap().r_factor,
+        );
+    }
+
+    #[test]
+    fn rfc_3550_jitter() {
+        // Each difference moves the estimate a sixteenth of the way towards it.
+        assert_close(
+            millis(&jitter(&volley(&[Some(10_000), Some(20_000)])).unwrap()),
+            0.625,
+        );
+        assert_close(
+            millis(&jitter(&volley(&[Some(10_000), Some(20_000), Some(10_000)])).unwrap()),
+            0.625 + (10.0 - 0.625) / 16.0,
+        );
+        // Lost requests are skipped rather than counted as differences.
+        assert_close(
+            millis(&jitter(&volley(&[Some(10_000), None, Some(20_000)])).unwrap()),
+            0.625,
+        );
+        assert!(jitter(&volley(&[Some(10_000), None])).is_none());
+    }
+
+    #[test]
+    fn histogram_edges() {
+        let info = volley(&[
+            Some(500),
+            Some(1_000),
+            Some(1_001),
+            Some(5_000),
+            None,
+            Some(5_500),
+        ]);
+        let buckets = histogram(&info, &[1.0, 5.0]);
+        let bounds: Vec<(f64, Option<f64>)> = buckets.iter().map(|b| (b.lower, b.upper)).collect();
+        assert_eq!(
+            bounds,
+            vec![(0.0, Some(1.0)), (1.0, Some(5.0)), (5.0, None)]
+        );
+        // Upper bounds are inclusive.
+        let counts: Vec<usize> = buckets.iter().map(|b| b.count).collect();
+        assert_eq!(counts, vec![2, 2, 1]);
+        assert!(histogram(&info, &[]).is_empty());
+    }
+
+    #[test]
+    fn e_model() {
+        // Only the 10 ms of codec delay: the best rating the simplified model gives.
+        let best = call_quality(Duration::ZERO, Duration::ZERO, 0.0);
+        assert_close(best.r_factor, 92.95);
+        assert!(best.mos > 4.4 && best.mos <= 4.5);
+
+        // Both delay impairments agree at an effective delay of 160 ms.
+        let below = call_quality(Duration::from_micros(149_999), Duration::ZERO, 0.0);
+        let at = call_quality(Duration::from_millis(150), Duration::ZERO, 0.0);
+        assert_close(at.r_factor, 89.2);
+        assert!((below.r_factor - at.r_factor).abs() < 0.001);
+
+        // Jitter counts double and each percent of loss takes 2.5.
+        let jittery = call_quality(Duration::from_millis(100), Duration::from_millis(30), 0.0);
+        assert_close(
+            jittery.r_factor,
+            93.2 - (100.0 + 60.0 + 10.0 - 120.0) / 10.0,
+        );
+        let lossy = call_quality(Duration::ZERO, Duration::ZERO, 10.0);
+        assert_close(lossy.r_factor, 92.95 - 25.0);
+
+        // Ratings and scores are clamped to their ranges.
+        let unusable = call_quality(Duration::from_secs(1), Duration::ZERO, 50.0);
+        assert_eq!(unusable.r_factor, 0.0);
+        assert_eq!(unusable.mos, 1.0);
+    }
+
+    #[test]
+    fn outliers() {
+        // Median 11 ms, median absolute deviation 2 ms, so 3 MADs is 8.9 ms above the median.
+        let info = volley(&[
+            Some(10_000),
+            Some(12_000),
+            Some(8_000),
+            Some(11_000),
+            None,
+            Some(9_000),
+            Some(19_000),
+            Some(30_000),
+        ]);
+        assert_eq!(
+            find_outliers(&info, 3.0),
+            vec![(7, Duration::from_millis(30))]
+        );
+        assert_eq!(find_outliers(&info, 20.0), vec![]);
+        assert_eq!(find_outliers(&volley(&[None, None]), 3.0), vec![]);
+    }
+
+    #[test]
+    fn outliers_without_deviation() {
+        // Identical latencies have no deviation, and nothing is an outlier.
+        assert_eq!(find_outliers(&volley(&[Some(10_000); 5]), 3.0), vec![]);
+        // With most replies identical, the deviation is kept at MIN_DEVIATION rather than 0, so
+        // a reply a few microseconds slower isn't flagged, while one clearly slower still is.
+        let info = volley(&[
+            Some(10_000),
+            Some(10_000),
+            Some(10_000),
+            Some(10_005),
+            Some(10_000),
+            Some(10_100),
+        ]);
+        assert_eq!(
+            find_outliers(&info, 3.0),
+            vec![(5, Duration::from_micros(10_100))]
+        );
+    }
+
+    #[test]
+    fn dscp_groups_of_a_window() {
+        // A stream window starting at request 3 of values alternating 0 and 46 starts with 46.
+        let mut info = volley(&[Some(10_000), Some(20_000), None]);
+        info.first_index = 3;
+        let groups: Vec<(u8, usize, usize, Option<Duration>)> = group_by_dscp(&info, &[0, 46])
+            .iter()
+            .map(|g| (g.dscp, g.sent, g.received, g.avg))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (46, 2, 1, Some(Duration::from_millis(10))),
+                (0, 1, 1, Some(Duration::from_millis(20))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use rusqlite::{params, Connection};
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::stats::VolleyStats;
+use crate::volley::VolleyInfo;
+
+/// Version of the database schema, kept in `PRAGMA user_version`.
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS volleys (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    target TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    sent INTEGER NOT NULL,
+    received INTEGER NOT NULL,
+    lost INTEGER NOT NULL,
+    avg_ns INTEGER,
+    min_ns INTEGER,
+    max_ns INTEGER,
+    p50_ns INTEGER,
+    p99_ns INTEGER,
+    truncated INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS volleys_target_time ON volleys (target, time);
+CREATE TABLE IF NOT EXISTS pings (
+    volley_id INTEGER NOT NULL REFERENCES volleys (id),
+    seq INTEGER NOT NULL,
+    latency_ns INTEGER,
+    reply_size INTEGER,
+    PRIMARY KEY (volley_id, seq)
+);
+";
+
+/// Where --store writes results, as given on the command line.
+#[derive(Clone, Debug)]
+pub enum StoreLocation {
+    /// `sqlite:<path>`
+    Sqlite(PathBuf),
+}
+
+impl FromStr for StoreLocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("sqlite", path)) if !path.is_empty() => Ok(StoreLocation::Sqlite(path.into())),
+            Some(("sqlite", _)) => Err("sqlite: needs a path, e.g. sqlite:results.db".to_string()),
+            _ => Err(format!("unsupported store {}, expected sqlite:<path>", s)),
+        }
+    }
+}
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Writes volley summaries, and optionally the result of every request, to a SQLite database.
+pub struct SqliteStore {
+    connection: Connection,
+    /// Whether to write a row per request to `pings`.
+    pings: bool,
+}
+
+impl SqliteStore {
+    /// Opens or creates the database at `path`. A database with a different schema version is
+    /// rejected rather than migrated.
+    pub fn open(path: &Path, pings: bool) -> io::Result<SqliteStore> {
+        let open = || -> rusqlite::Result<(Connection, i64)> {
+            let connection = Connection::open(path)?;
+            // Another process may be reading the database for a report.
+            connection.busy_timeout(Duration::from_secs(5))?;
+            let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+            Ok((connection, version))
+        };
+        let (connection, version) = open()
+            .map_err(|e| io::Error::other(format!("Failed to open {}: {}", path.display(), e)))?;
+        if version != 0 && version != SCHEMA_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: schema version {} isn't supported, expected {}",
+                    path.display(),
+                    version,
+                    SCHEMA_VERSION
+                ),
+            ));
+        }
+        let create = || -> rusqlite::Result<()> {
+            connection.execute_batch(SCHEMA)?;
+            connection.pragma_update(None, "user_version", SCHEMA_VERSION)
+        };
+        if let Err(e) = create() {
+            return Err(io::Error::other(format!(
+                "Failed to create tables in {}: {}",
+                path.display(),
+                e
+            )));
+        }
+        Ok(SqliteStore { connection, pings })
+    }
+
+    /// Writes a volley, and its requests if enabled, in one transaction.
+    pub fn write(
+        &mut self,
+        time: &str,
+        label: &str,
+        addr: IpAddr,
+        info: &VolleyInfo,
+        stats: &VolleyStats,
+    ) -> io::Result<()> {
+        let nanos = |duration: Duration| duration.as_nanos() as i64;
+        let replied = |duration: Duration| (stats.received > 0).then(|| nanos(duration));
+        let percentile = |duration: Duration| stats.percentiles.then(|| nanos(duration));
+
+        let transaction = self.connection.transaction().map_err(sql_error)?;
+        transaction
+            .execute(
+                "INSERT INTO volleys (time, target, ip, sent, received, lost, avg_ns, min_ns, \
+                 max_ns, p50_ns, p99_ns, truncated) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                params![
+                    time,
+                    label,
+                    addr.to_string(),
+                    stats.sent as i64,
+                    stats.received as i64,
+                    stats.lost as i64,
+                    replied(stats.avg),
+                    replied(stats.min),
+                    replied(stats.max),
+                    percentile(stats.percentile50),
+                    percentile(stats.percentile99),
+                    info.truncated,
+                ],
+            )
+            .map_err(sql_error)?;
+        if self.pings {
+            let volley_id = transaction.last_insert_rowid();
+            for (seq, result) in info.results.iter().enumerate() {
+                transaction
+                    .execute(
+                        "INSERT INTO pings (volley_id, seq, latency_ns, reply_size) \
+                         VALUES (?1, ?2, ?3, ?4)",
+                        params![
+                            volley_id,
+                            seq as i64,
+                            result.as_ref().map(|r| nanos(r.latency)),
+                            result.as_ref().map(|r| r.reply_size as i64),
+                        ],
+                    )
+                    .map_err(sql_error)?;
+            }
+        }
+        transaction.commit().map_err(sql_error)
+    }
+}
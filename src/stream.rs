@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::icmp_error::IcmpError;
+use crate::volley::{self, Parsed, PingResult, Strays, VolleyConfig, VolleyInfo};
+use crate::{log, socket};
+
+/// A request awaiting the end of its summary window, with what its replies showed, so that
+/// they count towards its window however late they arrive.
+struct Request {
+    index: usize,
+    seq: u16,
+    time: Instant,
+    wall_time: SystemTime,
+    sent: bool,
+    result: Option<PingResult>,
+    corrupted: bool,
+    lenient: bool,
+    /// Extra replies after the first.
+    duplicates: usize,
+    /// How many requests the reply arrived behind, if after a reply to a later request.
+    reorder_distance: Option<usize>,
+    icmp_errors: Vec<IcmpError>,
+}
+
+/// Counters for datagrams that can't be tied to a request, which count towards the window they
+/// arrived in.
+#[derive(Default)]
+struct Counters {
+    checksum_errors: usize,
+    rejected_sources: usize,
+    strays: Strays,
+}
+
+/// Pings `target` continuously at `rate` requests per second, calling `on_window` with a summary
+/// of the requests sent in each `summary_interval` once all of them have been answered or timed
+/// out. Runs until `on_window` returns false or an error occurs.
+///
+/// Unlike repeated volleys there is no pause between windows, and a late reply still counts
+/// for its request if it arrives within the timeout after the window ended.
+pub fn measure_stream(
+    target: IpAddr,
+    config: &VolleyConfig,
+    rate: f64,
+    summary_interval: Duration,
+    mut on_window: impl FnMut(chrono::DateTime<chrono::Local>, VolleyInfo) -> bool,
+) -> Result<(), String> {
+    let (mut tx, rx) = volley::open_channel(target, config)?;
+
+    let identifier = rand::random::<u16>();
+    let (replies_tx, replies_rx) = mpsc::channel();
+    let receiver_config = config.clone();
+    thread::spawn(move || {
+        let mut buffer = vec![0; 65536];
+        loop {
+            let info =
+                match socket::recv_with_timeout(rx.fd(), &mut buffer, receiver_config.timeout) {
+                    Err(e) => {
+                        log::warning!("Error receiving packet: {}", e);
+                        return;
+                    }
+                    Ok(None) => continue,
+                    Ok(Some(info)) => info,
+                };
+            let parsed = volley::parse_reply(&buffer, &info, &receiver_config, target, identifier);
+            let payload = match &parsed {
+                Parsed::Ignored => continue,
+                Parsed::Reply(reply) => volley::reply_payload(&buffer, &info, reply).to_vec(),
+                _ => Vec::new(),
+            };
+            if replies_tx.send((parsed, payload)).is_err() {
+                return;
+            }
+        }
+    });
+
+    let period = Duration::from_secs_f64(1.0 / rate);
+    let interface = socket::outgoing_interface(target, config.source);
+    let mut requests: VecDeque<Request> = VecDeque::new();
+    // Counters of the windows datagrams arrived in, from the oldest one not yet summarized.
+    let mut counters: VecDeque<Counters> = VecDeque::new();
+    let mut highest_index: Option<usize> = None;
+    let mut index = 0;
+    let mut next_send = Instant::now();
+    let mut window_start = next_send;
+    let mut window_start_time = chrono::Local::now();
+
+    loop {
+        let now = Instant::now();
+        if now >= next_send {
+            let sent = match volley::send_request(&mut tx, target, config, identifier, index) {
+                Err(e) => {
+                    log::verbose!("Failed to send packet: {}", e);
+                    false
+                }
+                Ok(_) => true,
+            };
+            requests.push_back(Request {
+                index,
+                seq: config.wire_seq(index),
+                time: now,
+                wall_time: SystemTime::now(),
+                sent,
+                result: None,
+                corrupted: false,
+                lenient: false,
+                duplicates: 0,
+                reorder_distance: None,
+                icmp_errors: Vec::new(),
+            });
+            index += 1;
+            next_send += period;
+        }
+
+        let window_end = window_start + summary_interval;
+        if now >= window_end + config.timeout {
+            let count = requests.iter().take_while(|r| r.time < window_end).count();
+            let window: Vec<Request> = requests.drain(..count).collect();
+            let info = summarize_window(
+                window,
+                counters.pop_front().unwrap_or_default(),
+                config.timeout,
+                interface,
+            );
+            if !on_window(window_start_time, info) {
+                return Ok(());
+            }
+            window_start = window_end;
+            window_start_time += chrono::Duration::from_std(summary_interval).unwrap_or_default();
+        }
+
+        let next_event = next_send.min(window_start + summary_interval + config.timeout);
+        let mut wait = next_event.saturating_duration_since(Instant::now());
+        // Poll instead of blocking near a send, so the loop spins until it's due.
+        if config.precise_pacing
+            && next_send.saturating_duration_since(Instant::now()) < volley::SPIN_AHEAD
+        {
+            wait = Duration::ZERO;
+        }
+        let parsed = replies_rx.recv_timeout(wait);
+        // Later windows than the oldest one may already have started.
+        let arrival_window = (Instant::now()
+            .saturating_duration_since(window_start)
+            .as_nanos()
+            / summary_interval.as_nanos().max(1)) as usize;
+        if counters.len() <= arrival_window {
+            counters.resize_with(arrival_window + 1, Counters::default);
+        }
+        let window_counters = &mut counters[arrival_window];
+        match parsed {
+            Ok((Parsed::Reply(reply), payload)) => {
+                // Sequence numbers wrap, so match the most recent request with this one.
+                let request = match requests.iter_mut().rev().find(|r| r.seq == reply.seq) {
+                    None => continue,
+                    Some(request) => request,
+                };
+                let latency = reply.time - request.time;
+                if latency > config.timeout {
+                    continue;
+                }
+                if request.result.is_some() {
+                    request.duplicates += 1;
+                    continue;
+                }
+
+                // The payload follows the request's index, which outgrows the sequence numbers
+                // parse_reply checks it by.
+                request.corrupted = payload != config.payload(identifier, request.index);
+                request.lenient = reply.lenient;
+                match highest_index {
+                    Some(highest) if request.index < highest => {
+                        request.reorder_distance = Some(highest - request.index);
+                    }
+                    _ => highest_index = Some(request.index),
+                }
+                request.result = Some(PingResult {
+                    latency,
+                    reply_size: reply.size,
+                    interface: reply.interface,
+                    ip_header: reply.ip_header,
+                });
+            }
+            Ok((Parsed::ChecksumError, _)) => window_counters.checksum_errors += 1,
+            Ok((Parsed::RejectedSource, _)) => window_counters.rejected_sources += 1,
+            Ok((Parsed::Stray(stray), _)) => window_counters.strays.count(stray),
+            Ok((Parsed::Error { seq, error }, _)) => {
+                if let Some(request) = requests.iter_mut().rev().find(|r| r.seq == seq) {
+                    request.icmp_errors.push(error);
+                }
+            }
+            Ok((Parsed::Ignored, _)) => {}
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err("Receiver stopped".to_string());
+            }
+        }
+    }
+}
+
+fn summarize_window(
+    window: Vec<Request>,
+    counters: Counters,
+    timeout: Duration,
+    interface: Option<u32>,
+) -> VolleyInfo {
+    let first_index = window.first().map_or(0, |r| r.index);
+    let send_times: Vec<Instant> = window.iter().map(|r| r.time).collect();
+    let wall_times: Vec<SystemTime> = window.iter().map(|r| r.wall_time).collect();
+    let sent = window.iter().filter(|r| r.sent).count();
+    let answered = || window.iter().filter(|r| r.result.is_some());
+    let corrupted = answered().filter(|r| r.corrupted).count();
+    let lenient_matches = answered().filter(|r| r.lenient).count();
+    let duplicates = window.iter().map(|r| r.duplicates).sum();
+    let reordered = window
+        .iter()
+        .filter(|r| r.reorder_distance.is_some())
+        .count();
+    let max_reorder_distance = window
+        .iter()
+        .filter_map(|r| r.reorder_distance)
+        .max()
+        .unwrap_or(0);
+
+    let mut results: Vec<Option<PingResult>> = Vec::with_capacity(window.len());
+    let mut icmp_errors: Vec<(usize, IcmpError)> = Vec::new();
+    // ICMP errors by the request's position in the window, like the results.
+    for (position, request) in window.into_iter().enumerate() {
+        results.push(request.result);
+        icmp_errors.extend(
+            request
+                .icmp_errors
+                .into_iter()
+                .map(|error| (position, error)),
+        );
+    }
+    let received = results.iter().flatten().count();
+    let (max_in_flight, avg_in_flight) = volley::in_flight(&send_times, &results, timeout);
+
+    VolleyInfo {
+        sent,
+        received,
+        lost: results.len() - received,
+        corrupted,
+        checksum_errors: counters.checksum_errors,
+        rejected_sources: counters.rejected_sources,
+        strays: counters.strays,
+        icmp_errors,
+        duplicates,
+        lenient_matches,
+        excess_replies: (received + duplicates).saturating_sub(sent),
+        reordered,
+        max_reorder_distance,
+        max_in_flight,
+        avg_in_flight,
+        window_waits: 0,
+        first_index,
+        interface,
+        profile: None,
+        truncated: false,
+        send_times: wall_times,
+        results,
+    }
+}
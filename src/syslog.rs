@@ -0,0 +1,116 @@
+use clap::ValueEnum;
+use std::ffi::CStr;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Facility {
+    Kern = 0,
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    Authpriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+const SEVERITY_INFO: u8 = 6;
+
+const LOCAL_SOCKETS: [&str; 2] = ["/dev/log", "/var/run/syslog"];
+
+enum Transport {
+    Local(UnixDatagram),
+    Remote(UdpSocket),
+}
+
+/// Sends RFC 5424 messages to the local syslog daemon or a remote collector.
+pub struct Syslog {
+    transport: Transport,
+    facility: Facility,
+    hostname: String,
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if ret != 0 {
+        return "-".to_string();
+    }
+    match CStr::from_bytes_until_nul(&buffer) {
+        Ok(name) if !name.is_empty() => name.to_string_lossy().into_owned(),
+        _ => "-".to_string(),
+    }
+}
+
+impl Syslog {
+    pub fn local(facility: Facility) -> io::Result<Syslog> {
+        let socket = UnixDatagram::unbound()?;
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "No syslog socket found");
+        for path in LOCAL_SOCKETS {
+            match socket.connect(path) {
+                Ok(_) => {
+                    return Ok(Syslog {
+                        transport: Transport::Local(socket),
+                        facility,
+                        hostname: hostname(),
+                    })
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    pub fn remote(server: &str, facility: Facility) -> io::Result<Syslog> {
+        let addr = match server.to_socket_addrs()?.next() {
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No addresses found for {}", server),
+                ))
+            }
+            Some(addr) => addr,
+        };
+        let socket = if addr.is_ipv4() {
+            UdpSocket::bind("0.0.0.0:0")?
+        } else {
+            UdpSocket::bind("[::]:0")?
+        };
+        socket.connect(addr)?;
+        Ok(Syslog {
+            transport: Transport::Remote(socket),
+            facility,
+            hostname: hostname(),
+        })
+    }
+
+    pub fn send(&self, message: &str) -> io::Result<()> {
+        let line = format!(
+            "<{}>1 {} {} epingm {} - - {}",
+            self.facility as u8 * 8 + SEVERITY_INFO,
+            chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, false),
+            self.hostname,
+            std::process::id(),
+            message
+        );
+        match &self.transport {
+            Transport::Local(socket) => socket.send(line.as_bytes())?,
+            Transport::Remote(socket) => socket.send(line.as_bytes())?,
+        };
+        Ok(())
+    }
+}
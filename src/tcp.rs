@@ -0,0 +1,38 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::log;
+use crate::volley::{self, PingResult, VolleyConfig, VolleyResult};
+
+/// Time from starting a connection to `addr` until the target answered it, either by accepting
+/// it or by refusing it with a reset. Either way the answer took one round trip. `None` if
+/// there was no answer within `timeout`.
+fn connect(addr: SocketAddr, timeout: Duration) -> Option<Duration> {
+    let start = Instant::now();
+    match TcpStream::connect_timeout(&addr, timeout) {
+        Ok(_) => Some(start.elapsed()),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Some(start.elapsed()),
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => None,
+        Err(e) => {
+            log::verbose!("Failed to connect to {}: {}", addr, e);
+            None
+        }
+    }
+}
+
+/// Measures a volley like `volley::measure_volley`, but times TCP connections to `port` instead
+/// of echo requests, for targets that drop ICMP. Connections are closed as soon as they're
+/// established, and need no privileges.
+pub fn measure_volley(target: IpAddr, port: u16, config: &VolleyConfig) -> VolleyResult {
+    let addr = SocketAddr::new(target, port);
+    let timeout = config.timeout;
+    volley::measure_blocking(config, move |_| {
+        connect(addr, timeout).map(|latency| PingResult {
+            latency,
+            reply_size: 0,
+            interface: None,
+            ip_header: None,
+        })
+    })
+}
@@ -0,0 +1,381 @@
+use pnet::packet::icmp::{self, IcmpPacket, MutableIcmpPacket};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::Packet;
+use pnet::transport::TransportChannelType::Layer4;
+use pnet::transport::TransportProtocol::Ipv4;
+use pnet::transport::TransportReceiver;
+use pnet::util;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::volley::{self, PingResult, Strays, VolleyConfig, VolleyInfo, VolleyResult};
+use crate::{log, socket};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// One-way delays estimated from ICMP timestamp replies, in milliseconds.
+///
+/// Both include the offset between the local and remote clocks, so they are only meaningful
+/// if the clocks are synchronized. The clock offset estimate assumes a symmetric path.
+pub struct OneWayDelay {
+    pub forward: i64,
+    pub reverse: i64,
+    /// Remote clock minus local clock, estimated as if the path were symmetric.
+    pub clock_offset: i64,
+    pub samples: usize,
+}
+
+/// Milliseconds since midnight UTC, the unit of ICMP timestamps.
+fn millis_since_midnight() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.as_millis() % MILLIS_PER_DAY as u128) as u32
+}
+
+/// Difference of two timestamps, allowing for midnight between them.
+fn diff(later: u32, earlier: u32) -> i64 {
+    let diff = (later as i64 - earlier as i64).rem_euclid(MILLIS_PER_DAY);
+    if diff >= MILLIS_PER_DAY / 2 {
+        diff - MILLIS_PER_DAY
+    } else {
+        diff
+    }
+}
+
+fn median(values: &mut [i64]) -> i64 {
+    values.sort();
+    values[values.len() / 2]
+}
+
+/// Fields of a timestamp reply, in milliseconds since midnight UTC.
+struct TimestampReply {
+    seq: u16,
+    originate: u32,
+    receive: u32,
+    transmit: u32,
+}
+
+impl TimestampReply {
+    /// Forward and reverse delays of the reply arriving at `arrival`, if the target's
+    /// timestamps are in the standard unit. The high bit marks those that aren't.
+    fn delays(&self, arrival: u32) -> Option<(i64, i64)> {
+        if self.receive & 0x8000_0000 != 0 || self.transmit & 0x8000_0000 != 0 {
+            return None;
+        }
+        Some((
+            diff(self.receive, self.originate),
+            diff(arrival, self.transmit),
+        ))
+    }
+}
+
+/// A timestamp request with the current time as its originate timestamp.
+fn request(identifier: u16, seq: u16) -> [u8; 20] {
+    // Type, code and checksum, then identifier, sequence number and the originate, receive and
+    // transmit timestamps.
+    let mut packet = [0u8; 20];
+    let originate = millis_since_midnight();
+    let mut request = MutableIcmpPacket::new(&mut packet).unwrap();
+    request.set_icmp_type(icmp::IcmpTypes::Timestamp);
+    let mut payload = [0u8; 16];
+    payload[0..2].copy_from_slice(&identifier.to_be_bytes());
+    payload[2..4].copy_from_slice(&seq.to_be_bytes());
+    payload[4..8].copy_from_slice(&originate.to_be_bytes());
+    request.set_payload(&payload);
+    let checksum = util::checksum(request.packet(), 1);
+    request.set_checksum(checksum);
+    packet
+}
+
+/// Parses a datagram from `source` read from a raw IPv4 socket as a timestamp reply from
+/// `target` to a request with `identifier`.
+fn parse_reply(
+    data: &[u8],
+    source: IpAddr,
+    target: Ipv4Addr,
+    identifier: u16,
+) -> Option<TimestampReply> {
+    if source != IpAddr::V4(target) {
+        return None;
+    }
+    let ip_packet = Ipv4Packet::new(data)?;
+    let data = &data[(ip_packet.get_header_length() as usize * 4).min(data.len())..];
+    let reply = match IcmpPacket::new(data) {
+        Some(reply) if reply.get_icmp_type() == icmp::IcmpTypes::TimestampReply => reply,
+        _ => return None,
+    };
+    let fields = reply.payload();
+    if fields.len() < 16 || fields[0..2] != identifier.to_be_bytes() {
+        return None;
+    }
+    let field = |at: usize| u32::from_be_bytes(fields[at..at + 4].try_into().unwrap());
+    Some(TimestampReply {
+        seq: u16::from_be_bytes([fields[2], fields[3]]),
+        originate: field(4),
+        receive: field(8),
+        transmit: field(12),
+    })
+}
+
+/// Takes the medians of the forward and reverse delays of several replies. `None` if there are
+/// none.
+fn estimate(mut forward: Vec<i64>, mut reverse: Vec<i64>) -> Option<OneWayDelay> {
+    if forward.is_empty() {
+        return None;
+    }
+    let samples = forward.len();
+    let forward = median(&mut forward);
+    let reverse = median(&mut reverse);
+    Some(OneWayDelay {
+        forward,
+        reverse,
+        clock_offset: (forward - reverse) / 2,
+        samples,
+    })
+}
+
+/// Sends `samples` ICMP timestamp requests to `target` one at a time and estimates the one-way
+/// delays from the replies. Returns `None` if no usable reply arrived.
+pub fn measure_one_way(
+    target: Ipv4Addr,
+    samples: usize,
+    timeout: Duration,
+) -> io::Result<Option<OneWayDelay>> {
+    let protocol = Layer4(Ipv4(IpNextHeaderProtocols::Icmp));
+    let (mut tx, rx) = pnet::transport::transport_channel(4096, protocol)?;
+    let identifier = rand::random::<u16>();
+    let mut buffer = vec![0; 65536];
+
+    let mut forward = Vec::new();
+    let mut reverse = Vec::new();
+    for seq in 0..samples as u16 {
+        let mut packet = request(identifier, seq);
+        tx.send_to(
+            MutableIcmpPacket::new(&mut packet).unwrap(),
+            IpAddr::V4(target),
+        )?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            let info = match socket::recv_with_timeout(rx.socket.fd, &mut buffer, deadline - now)? {
+                None => break,
+                Some(info) => info,
+            };
+            let arrival = millis_since_midnight();
+            let reply = match parse_reply(&buffer[..info.size], info.source, target, identifier) {
+                Some(reply) if reply.seq == seq => reply,
+                _ => continue,
+            };
+            if let Some((to, from)) = reply.delays(arrival) {
+                forward.push(to);
+                reverse.push(from);
+            }
+            break;
+        }
+    }
+
+    Ok(estimate(forward, reverse))
+}
+
+/// A reply read by the receiver of `measure_volley`.
+struct Arrival {
+    time: Instant,
+    millis: u32,
+    reply: TimestampReply,
+}
+
+/// Reads replies to requests with `identifier` until the stop time sent through `stop`.
+fn receive(
+    rx: TransportReceiver,
+    target: Ipv4Addr,
+    identifier: u16,
+    timeout: Duration,
+    stop: mpsc::Receiver<Instant>,
+) -> Vec<Arrival> {
+    let mut arrivals = Vec::new();
+    let mut buffer = vec![0; 65536];
+    let mut stop_at = None;
+    loop {
+        if stop_at.is_none() {
+            stop_at = stop.try_recv().ok();
+        }
+        let wait = match stop_at {
+            None => timeout,
+            Some(stop_at) => match stop_at.checked_duration_since(Instant::now()) {
+                None => return arrivals,
+                Some(wait) => wait,
+            },
+        };
+        let info = match socket::recv_with_timeout(rx.socket.fd, &mut buffer, wait) {
+            Ok(None) => continue,
+            Ok(Some(info)) => info,
+            Err(e) => {
+                log::warning!("Error receiving packet: {}", e);
+                return arrivals;
+            }
+        };
+        let time = Instant::now();
+        let millis = millis_since_midnight();
+        if let Some(reply) = parse_reply(&buffer[..info.size], info.source, target, identifier) {
+            arrivals.push(Arrival {
+                time,
+                millis,
+                reply,
+            });
+        }
+    }
+}
+
+/// Measures a volley like `volley::measure_volley`, but with ICMP timestamp requests instead
+/// of echo requests, and estimates the one-way delays from the timestamps of the replies.
+/// Timestamps have millisecond resolution, but latency is measured with the local clock alone.
+///
+/// Options that shape echo requests, such as payloads, DSCP and ECN, don't apply.
+pub fn measure_volley(
+    target: IpAddr,
+    config: &VolleyConfig,
+) -> (VolleyResult, Option<OneWayDelay>) {
+    let target = match target {
+        IpAddr::V4(target) => target,
+        IpAddr::V6(_) => {
+            return (
+                VolleyResult::Error("ICMP timestamps aren't available over IPv6".to_string()),
+                None,
+            )
+        }
+    };
+    let protocol = Layer4(Ipv4(IpNextHeaderProtocols::Icmp));
+    let (mut tx, rx) = match pnet::transport::transport_channel(4096, protocol) {
+        Ok(channel) => channel,
+        Err(e) => {
+            return (
+                VolleyResult::Error(format!("Failed to create transport channel: {}", e)),
+                None,
+            )
+        }
+    };
+    let identifier = rand::random::<u16>();
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let timeout = config.timeout;
+    let receiver = thread::spawn(move || receive(rx, target, identifier, timeout, stop_rx));
+
+    let mut send_times: Vec<Instant> = Vec::new();
+    let mut wall_times: Vec<SystemTime> = Vec::new();
+    let mut truncated = false;
+    let mut rng = match config.seed {
+        None => StdRng::from_entropy(),
+        Some(seed) => StdRng::seed_from_u64(seed),
+    };
+    let mut next_send = Instant::now();
+    let deadline = config.deadline.map(|deadline| next_send + deadline);
+    for index in 0..config.count {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            truncated = true;
+            break;
+        }
+        let mut packet = request(identifier, config.wire_seq(index));
+        send_times.push(Instant::now());
+        wall_times.push(SystemTime::now());
+        let sent = tx.send_to(
+            MutableIcmpPacket::new(&mut packet).unwrap(),
+            IpAddr::V4(target),
+        );
+        if let Err(e) = sent {
+            log::verbose!("Failed to send packet: {}", e);
+        }
+
+        next_send += config.gap(index, &mut rng);
+        let wake = match deadline {
+            Some(deadline) => next_send.min(deadline),
+            None => next_send,
+        };
+        volley::pace(wake, config.precise_pacing);
+    }
+
+    let last_send = send_times.last().copied().unwrap_or_else(Instant::now);
+    let stop_at = match deadline {
+        Some(deadline) if deadline < last_send + timeout => {
+            truncated = true;
+            deadline
+        }
+        _ => last_send + timeout,
+    };
+    _ = stop_tx.send(stop_at);
+    let arrivals = match receiver.join() {
+        Err(_) => {
+            return (
+                VolleyResult::Error("Receiver thread panicked".to_string()),
+                None,
+            )
+        }
+        Ok(arrivals) => arrivals,
+    };
+
+    let sent = send_times.len();
+    let mut results: Vec<Option<PingResult>> = vec![None; sent];
+    let mut duplicates = 0;
+    let mut forward = Vec::new();
+    let mut reverse = Vec::new();
+    for arrival in arrivals {
+        let index = config.index_of(arrival.reply.seq);
+        if index >= sent || arrival.time > send_times[index] + timeout {
+            continue;
+        }
+        if results[index].is_some() {
+            duplicates += 1;
+            continue;
+        }
+        results[index] = Some(PingResult {
+            latency: arrival.time - send_times[index],
+            reply_size: 12,
+            interface: None,
+            ip_header: None,
+        });
+        if let Some((to, from)) = arrival.reply.delays(arrival.millis) {
+            forward.push(to);
+            reverse.push(from);
+        }
+    }
+
+    let received = results.iter().flatten().count();
+    let (max_in_flight, avg_in_flight) = volley::in_flight(&send_times, &results, timeout);
+    let info = VolleyInfo {
+        sent,
+        received,
+        lost: sent - received,
+        corrupted: 0,
+        checksum_errors: 0,
+        rejected_sources: 0,
+        strays: Strays::default(),
+        icmp_errors: Vec::new(),
+        duplicates,
+        lenient_matches: 0,
+        excess_replies: 0,
+        reordered: 0,
+        max_reorder_distance: 0,
+        max_in_flight,
+        avg_in_flight,
+        window_waits: 0,
+        first_index: 0,
+        interface: socket::outgoing_interface(IpAddr::V4(target), None),
+        profile: None,
+        truncated,
+        send_times: wall_times,
+        results,
+    };
+    (
+        VolleyResult::Success(Box::new(info)),
+        estimate(forward, reverse),
+    )
+}
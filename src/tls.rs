@@ -0,0 +1,117 @@
+use rustls::client::Resumption;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore};
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::log;
+use crate::volley::{self, PingResult, VolleyConfig, VolleyResult};
+
+/// Port of --probe tls if --port isn't given.
+pub const DEFAULT_PORT: u16 = 443;
+
+/// Client configuration verifying certificates against the bundled Mozilla roots. Sessions are
+/// resumed across connections made with the same configuration, unless `resumption` is false.
+pub fn client_config(resumption: bool) -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let mut config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    if !resumption {
+        config.resumption = Resumption::disabled();
+    }
+    Arc::new(config)
+}
+
+/// A client connection to `host`, verifying that the certificate is for it.
+pub fn connection(config: &Arc<ClientConfig>, host: &str) -> io::Result<ClientConnection> {
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", host, e)))?;
+    ClientConnection::new(config.clone(), name).map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// TLS handshakes to time against every target, on connections to its address with its host
+/// name.
+pub struct TlsProbe {
+    config: Arc<ClientConfig>,
+    port: u16,
+}
+
+impl TlsProbe {
+    pub fn new(port: u16, resumption: bool) -> TlsProbe {
+        TlsProbe {
+            config: client_config(resumption),
+            port,
+        }
+    }
+
+    /// Connects to `addr` and times the handshake from the first message to its completion,
+    /// excluding the TCP connection. `None` if it didn't complete within `timeout`, which
+    /// covers the connection too.
+    fn handshake(
+        &self,
+        addr: SocketAddr,
+        host: &str,
+        timeout: Duration,
+    ) -> io::Result<Option<Duration>> {
+        let connect_start = Instant::now();
+        let mut stream = match TcpStream::connect_timeout(&addr, timeout) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+            result => result?,
+        };
+        let remaining = timeout.saturating_sub(connect_start.elapsed());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        stream.set_write_timeout(Some(remaining))?;
+
+        let mut connection = connection(&self.config, host)?;
+        let start = Instant::now();
+        while connection.is_handshaking() {
+            match connection.complete_io(&mut stream) {
+                Ok(_) => {}
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(start.elapsed()))
+    }
+}
+
+/// Measures a volley like `volley::measure_volley`, but times a full TLS handshake with the
+/// target per ping, each on a new connection, for the performance of TLS-terminating edges.
+/// `host` is sent for SNI and used to verify the certificate.
+pub fn measure_volley(
+    target: IpAddr,
+    host: &str,
+    probe: &Arc<TlsProbe>,
+    config: &VolleyConfig,
+) -> VolleyResult {
+    let addr = SocketAddr::new(target, probe.port);
+    let host = host.to_string();
+    let probe = probe.clone();
+    let timeout = config.timeout;
+    volley::measure_blocking(config, move |_| {
+        match probe.handshake(addr, &host, timeout) {
+            Ok(latency) => latency.map(|latency| PingResult {
+                latency,
+                reply_size: 0,
+                interface: None,
+                ip_header: None,
+            }),
+            Err(e) => {
+                log::verbose!("Failed TLS handshake with {}: {}", addr, e);
+                None
+            }
+        }
+    })
+}
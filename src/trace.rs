@@ -0,0 +1,257 @@
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use crate::icmp_error::IcmpError;
+use crate::volley::{self, IcmpReceiver, IcmpSender, Parsed, VolleyConfig};
+use crate::{log, socket};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TraceFormat {
+    Text,
+    /// A row per probe with its hop, responder and latency.
+    Csv,
+    /// The whole path as one JSON object.
+    Json,
+}
+
+#[derive(Args, Debug)]
+pub struct TraceArgs {
+    /// Target to trace the path to.
+    pub target: String,
+
+    /// Hop limit of the first probes, to skip hops known to be uninteresting.
+    #[arg(long, default_value = "1")]
+    first_hop: u8,
+
+    /// Largest hop limit to probe with before giving up on reaching the target.
+    #[arg(long, default_value = "30")]
+    max_hops: u8,
+
+    /// Number of probes sent to each hop.
+    #[arg(long, default_value = "3")]
+    probes: usize,
+
+    /// Output format of the path.
+    #[arg(long, default_value = "text")]
+    format: TraceFormat,
+}
+
+/// Answer to a probe.
+#[derive(Clone, Copy)]
+struct Answer {
+    /// The router whose time exceeded message answered, or the target.
+    source: IpAddr,
+    latency: Duration,
+    /// Error the answer reported other than the hop limit being exceeded, which ends the trace.
+    error: Option<IcmpError>,
+    reached: bool,
+}
+
+#[derive(Serialize)]
+struct JsonProbe {
+    ip: Option<String>,
+    latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonHop {
+    hop: u8,
+    probes: Vec<JsonProbe>,
+}
+
+#[derive(Serialize)]
+struct JsonTrace {
+    target: String,
+    ip: String,
+    reached: bool,
+    hops: Vec<JsonHop>,
+}
+
+/// Sends an echo request with the hop limit `hops` and waits for its answer.
+fn probe(
+    tx: &mut IcmpSender,
+    rx: &IcmpReceiver,
+    target: IpAddr,
+    config: &VolleyConfig,
+    identifier: u16,
+    hops: u8,
+    seq: u16,
+) -> io::Result<Option<Answer>> {
+    socket::set_hop_limit(tx.fd(), target, hops)?;
+    let start = Instant::now();
+    let payload = config.payload(identifier, config.index_of(seq));
+    volley::send_echo_request(tx, target, &payload, identifier, seq)?;
+
+    let mut buffer = vec![0; 65536];
+    loop {
+        let remaining = config.timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        let info = match socket::recv_with_timeout(rx.fd(), &mut buffer, remaining)? {
+            None => return Ok(None),
+            Some(info) => info,
+        };
+        let answer = match volley::parse_reply(&buffer, &info, config, target, identifier) {
+            Parsed::Reply(reply) if reply.seq == seq => Answer {
+                source: info.source,
+                latency: reply.time - start,
+                error: None,
+                reached: true,
+            },
+            Parsed::Error {
+                seq: error_seq,
+                error,
+            } if error_seq == seq => Answer {
+                source: info.source,
+                latency: info.time - start,
+                error: (error != IcmpError::TimeExceeded).then_some(error),
+                reached: info.source == target,
+            },
+            _ => continue,
+        };
+        return Ok(Some(answer));
+    }
+}
+
+fn format_latency(latency: Duration) -> String {
+    format!("{:.3}", latency.as_secs_f64() * 1000.0)
+}
+
+/// Prints the answers to the probes of a hop like traceroute does, naming each responder
+/// before its latencies.
+fn print_text_hop(hop: u8, answers: &[Option<Answer>]) {
+    let mut line = format!("{:>2} ", hop);
+    let mut previous = None;
+    for answer in answers {
+        match answer {
+            None => line.push_str(" *"),
+            Some(answer) => {
+                if previous != Some(answer.source) {
+                    line.push_str(&format!("  {}", answer.source));
+                    previous = Some(answer.source);
+                }
+                line.push_str(&format!("  {} ms", format_latency(answer.latency)));
+                if let Some(error) = answer.error {
+                    line.push_str(&format!(" ({})", error));
+                }
+            }
+        }
+    }
+    println!("{}", line);
+}
+
+/// Traces the path to `addr` by sending echo requests with increasing hop limits, `probes` per
+/// hop, until the target answers, a router reports it unreachable or `max_hops` is reached.
+pub fn run(args: &TraceArgs, addr: IpAddr, config: &VolleyConfig) -> ExitCode {
+    if args.probes == 0 || args.first_hop == 0 || args.first_hop > args.max_hops {
+        eprintln!(
+            "--probes and --first-hop must be at least 1, and --first-hop at most --max-hops"
+        );
+        return ExitCode::FAILURE;
+    }
+    if config.unprivileged {
+        eprintln!("Tracing needs the ICMP errors of raw sockets, which take root or CAP_NET_RAW");
+        return ExitCode::FAILURE;
+    }
+    let (mut tx, rx) = match volley::open_channel(addr, config) {
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        Ok(channel) => channel,
+    };
+    let identifier = rand::random::<u16>();
+
+    match args.format {
+        TraceFormat::Text => println!(
+            "trace to {} ({}), {} hops max",
+            args.target, addr, args.max_hops
+        ),
+        TraceFormat::Csv => println!("hop,probe,ip,latency_ms"),
+        TraceFormat::Json => {}
+    }
+    let mut hops = Vec::new();
+    let mut reached = false;
+    let mut seq = config.seq_start;
+    for hop in args.first_hop..=args.max_hops {
+        let mut answers = Vec::with_capacity(args.probes);
+        for _ in 0..args.probes {
+            let answer = match probe(&mut tx, &rx, addr, config, identifier, hop, seq) {
+                Err(e) => {
+                    log::verbose!("Failed to probe hop {}: {}", hop, e);
+                    None
+                }
+                Ok(answer) => answer,
+            };
+            answers.push(answer);
+            seq = seq.wrapping_add(1);
+        }
+
+        match args.format {
+            TraceFormat::Text => print_text_hop(hop, &answers),
+            TraceFormat::Csv => {
+                for (index, answer) in answers.iter().enumerate() {
+                    let (ip, latency) = match answer {
+                        None => ("-".to_string(), "timeout".to_string()),
+                        Some(answer) => (answer.source.to_string(), format_latency(answer.latency)),
+                    };
+                    println!("{},{},{},{}", hop, index, ip, latency);
+                }
+            }
+            TraceFormat::Json => {}
+        }
+        _ = io::stdout().flush();
+
+        let done = answers
+            .iter()
+            .flatten()
+            .any(|answer| answer.reached || answer.error.is_some());
+        reached = answers.iter().flatten().any(|answer| answer.reached);
+        hops.push((hop, answers));
+        if done {
+            break;
+        }
+    }
+
+    if let TraceFormat::Json = args.format {
+        let trace = JsonTrace {
+            target: args.target.clone(),
+            ip: addr.to_string(),
+            reached,
+            hops: hops
+                .into_iter()
+                .map(|(hop, answers)| JsonHop {
+                    hop,
+                    probes: answers
+                        .into_iter()
+                        .map(|answer| JsonProbe {
+                            ip: answer.map(|answer| answer.source.to_string()),
+                            latency_ms: answer.map(|answer| answer.latency.as_secs_f64() * 1000.0),
+                            error: answer.and_then(|answer| answer.error.map(|e| e.to_string())),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+        match serde_json::to_string(&trace) {
+            Err(e) => {
+                eprintln!("Failed to serialize trace: {}", e);
+                return ExitCode::FAILURE;
+            }
+            Ok(json) => println!("{}", json),
+        }
+    }
+
+    if reached {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,76 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::log;
+use crate::volley::{self, PingResult, VolleyConfig, VolleyResult};
+
+/// Largest reply read, the largest UDP payload.
+const MAX_REPLY_SIZE: usize = 65535;
+
+/// Sends `payload` to `addr` and times the answer: the datagram echoed back, or the ICMP port
+/// unreachable error a closed port answers with, which a connected socket reports as a refused
+/// connection. Unreachable errors are returned with a reply size of 0. `None` if there was no
+/// answer within `timeout`.
+fn probe(addr: SocketAddr, payload: &[u8], timeout: Duration) -> io::Result<Option<PingResult>> {
+    let local: IpAddr = match addr {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local, 0))?;
+    socket.connect(addr)?;
+
+    let start = Instant::now();
+    socket.send(payload)?;
+    let mut buffer = vec![0; MAX_REPLY_SIZE];
+    loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let reply_size = match socket.recv(&mut buffer) {
+            Ok(size) => size,
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => 0,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        if reply_size > 0 && buffer[..reply_size] != *payload {
+            // Something other than the echo of this request; keep waiting for that.
+            log::verbose!(
+                "Ignored a reply from {} that doesn't echo the request",
+                addr
+            );
+            continue;
+        }
+        return Ok(Some(PingResult {
+            latency: start.elapsed(),
+            reply_size,
+            interface: None,
+            ip_header: None,
+        }));
+    }
+}
+
+/// Measures a volley like `volley::measure_volley`, but sends UDP datagrams to `port` instead of
+/// echo requests. A request is answered by a UDP echo server sending the datagram back, or by
+/// the target reporting the port unreachable, so any closed port measures latency. Needs no
+/// privileges.
+pub fn measure_volley(target: IpAddr, port: u16, config: &VolleyConfig) -> VolleyResult {
+    let addr = SocketAddr::new(target, port);
+    let requests = config.clone();
+    volley::measure_blocking(config, move |index| {
+        // Datagrams have no identifier to seed the payload with, and are checked as a whole.
+        match probe(addr, &requests.payload(0, index), requests.timeout) {
+            Ok(result) => result,
+            Err(e) => {
+                log::verbose!("Failed to probe {}: {}", addr, e);
+                None
+            }
+        }
+    })
+}
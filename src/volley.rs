@@ -1,41 +1,406 @@
-use oneshot::TryRecvError;
+use clap::ValueEnum;
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
 use pnet::packet::icmp;
 use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::{icmpv6, MutablePacket, Packet};
+use pnet::packet::ipv4::{Ipv4Flags, Ipv4Packet};
+use pnet::packet::{icmpv6, Packet};
 use pnet::transport::TransportChannelType::Layer4;
 use pnet::transport::TransportProtocol::{Ipv4, Ipv6};
-use pnet::transport::{icmp_packet_iter, TransportSender};
+use pnet::transport::{TransportReceiver, TransportSender};
 use pnet::util;
-use rand::{thread_rng, RngCore};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use std::collections::{HashMap, VecDeque};
 use std::net::IpAddr;
-use std::time::{Duration, Instant};
-use std::{thread, vec, io};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, io, thread, vec};
+
+use crate::cidr::Cidr;
+use crate::icmp_error::{self, IcmpError};
+use crate::{log, socket};
 
 #[derive(Debug, Clone)]
 pub struct PingResult {
     pub latency: Duration,
     pub reply_size: usize,
+    /// Index of the interface the reply arrived on, if known.
+    pub interface: Option<u32>,
+    /// Fragmentation details from the IPv4 header, where the socket exposes it.
+    pub ip_header: Option<IpHeaderInfo>,
+}
+
+/// IPv4 header fields of a reply that show whether it was fragmented and how it was marked.
+#[derive(Debug, Clone, Copy)]
+pub struct IpHeaderInfo {
+    pub identification: u16,
+    /// Whether the more-fragments flag or a fragment offset is set, i.e. the socket delivered a
+    /// single fragment rather than a reassembled packet.
+    pub fragment: bool,
+    /// Length of the whole IP packet in bytes, after any reassembly.
+    pub total_length: usize,
+    /// ECN codepoint, the low two bits of the TOS byte.
+    pub ecn: u8,
+}
+
+/// How the time between requests in a volley is chosen.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum IntervalDistribution {
+    /// Always the interval.
+    Periodic,
+    /// Exponentially distributed with the interval as the mean, so requests form a Poisson
+    /// process that samples network conditions without bias (RFC 2330).
+    Poisson,
+}
+
+/// Content of generated payloads, for comparing links that compress traffic.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Entropy {
+    /// All zeros, which compresses as well as anything can.
+    Low,
+    /// Random bytes, which don't compress.
+    High,
+}
+
+impl Entropy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Entropy::Low => "low",
+            Entropy::High => "high",
+        }
+    }
+}
+
+/// ECN codepoint to mark requests with (RFC 3168).
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Ecn {
+    /// ECN-capable transport, ECT(0).
+    Ect0 = 0b10,
+    /// ECN-capable transport, ECT(1).
+    Ect1 = 0b01,
+    /// Congestion experienced.
+    Ce = 0b11,
+}
+
+/// Name of an ECN codepoint.
+pub fn ecn_name(ecn: u8) -> &'static str {
+    match ecn & 0b11 {
+        0b00 => "Not-ECT",
+        0b01 => "ECT(1)",
+        0b10 => "ECT(0)",
+        _ => "CE",
+    }
+}
+
+#[derive(Clone)]
+pub struct VolleyConfig {
+    pub count: usize,
+    /// Payload size in bytes when sending generated payloads.
+    pub size: usize,
+    /// Content of generated payloads.
+    pub entropy: Entropy,
+    pub interval: Duration,
+    pub interval_distribution: IntervalDistribution,
+    /// Gaps after each request, used in rotation instead of the interval if not empty.
+    pub gaps: Arc<Vec<Duration>>,
+    /// Seed for random intervals, making them the same in every volley.
+    pub seed: Option<u64>,
+    pub timeout: Duration,
+    /// Payloads sent in rotation by sequence number. Payloads are generated if empty.
+    pub payloads: Arc<Vec<Vec<u8>>>,
+    /// Drop replies with an invalid ICMP checksum.
+    pub verify_checksum: bool,
+    /// Sequence number sent with the first request of the volley.
+    pub seq_start: u16,
+    /// After sending, also send the target an echo reply with a bad checksum and a duplicate
+    /// reply to the first request. Only useful against loopback, to test the receive path.
+    pub inject_faults: bool,
+    /// Most requests awaiting a reply or timeout before sending waits for one to complete.
+    pub window: Option<usize>,
+    /// Accept replies with another identifier by sequence number alone, for middleboxes that
+    /// rewrite the identifier. Replies to other pings may then be miscounted as ours.
+    pub lenient_identifier: bool,
+    /// DSCP values sent in rotation by sequence number. The system default is used if empty.
+    pub dscp: Arc<Vec<u8>>,
+    /// ECN codepoint requests are marked with, if any.
+    pub ecn: Option<Ecn>,
+    /// TTL, or hop limit over IPv6, of requests. The system default is used if `None`.
+    pub ttl: Option<u8>,
+    /// Set the don't fragment bit on IPv4 requests, and don't fragment IPv6 ones.
+    pub dont_fragment: bool,
+    /// Local address requests are sent from. Chosen by the routing table if `None`.
+    pub source: Option<IpAddr>,
+    /// Spin until each send instead of only sleeping, for sub-millisecond intervals.
+    pub precise_pacing: bool,
+    /// Subnets replies may come from. If empty, only replies from the target are accepted.
+    pub accept_sources: Arc<Vec<Cidr>>,
+    /// Time the tool's own send and receive work, reported in `VolleyInfo::profile`.
+    pub profile: bool,
+    /// Longest a volley may take, cutting short sending and waiting for replies.
+    pub deadline: Option<Duration>,
+    /// Ping over ICMP datagram sockets instead of raw ones, which don't need root or
+    /// CAP_NET_RAW but don't receive ICMP errors.
+    pub unprivileged: bool,
+    /// Time replies by when the kernel received them instead of when the receiver read them.
+    pub kernel_timestamps: bool,
+    /// Time requests and replies by the NIC's timestamps of sending and receiving them, where it
+    /// takes them.
+    pub hardware_timestamps: bool,
+    /// Most requests sent, and datagrams read, per syscall. Only requests due within
+    /// `MAX_BATCH_GAP` of each other are sent together.
+    pub batch_size: usize,
 }
 
+impl VolleyConfig {
+    fn payload_size(&self) -> usize {
+        match self.payloads.iter().map(|p| p.len()).max() {
+            Some(size) => size,
+            None => self.size,
+        }
+    }
+
+    /// Sequence number sent on the wire for the request at `index`.
+    pub fn wire_seq(&self, index: usize) -> u16 {
+        self.seq_start.wrapping_add(index as u16)
+    }
+
+    /// Index of the request sent with the sequence number `seq`.
+    pub fn index_of(&self, seq: u16) -> usize {
+        seq.wrapping_sub(self.seq_start) as usize
+    }
+
+    /// Payload from the payload set for the request at `index`, if a set is configured.
+    fn fixed_payload(&self, index: usize) -> Option<&[u8]> {
+        if self.payloads.is_empty() {
+            return None;
+        }
+        Some(&self.payloads[index % self.payloads.len()])
+    }
+
+    /// Time from sending the request at `index` to sending the next one.
+    pub fn gap(&self, index: usize, rng: &mut StdRng) -> Duration {
+        match self.interval_distribution {
+            _ if !self.gaps.is_empty() => self.gaps[index % self.gaps.len()],
+            IntervalDistribution::Periodic => self.interval,
+            IntervalDistribution::Poisson => self.interval.mul_f64(-(1.0 - rng.gen::<f64>()).ln()),
+        }
+    }
+
+    /// DSCP value of the request at `index`, if any are configured.
+    fn dscp(&self, index: usize) -> Option<u8> {
+        dscp_of(&self.dscp, index)
+    }
+
+    /// Traffic class byte, DSCP and ECN, of the request at `index` if either is configured.
+    fn traffic_class(&self, index: usize) -> Option<u8> {
+        if self.dscp.is_empty() && self.ecn.is_none() {
+            return None;
+        }
+        let dscp = self.dscp(index).unwrap_or(0);
+        let ecn = self.ecn.map_or(0, |ecn| ecn as u8);
+        Some(dscp << 2 | ecn)
+    }
+
+    /// Whether a reply from `source` to a ping of `target` is accepted.
+    fn accepts_source(&self, source: IpAddr, target: IpAddr) -> bool {
+        if self.accept_sources.is_empty() {
+            return source == target;
+        }
+        self.accept_sources.iter().any(|cidr| cidr.contains(source))
+    }
+
+    /// Payload of the request at `index` sent with `identifier`. High entropy payloads are
+    /// seeded by the identifier and sequence number, so that replies can be checked against them.
+    pub fn payload(&self, identifier: u16, index: usize) -> Vec<u8> {
+        match self.fixed_payload(index) {
+            Some(payload) => payload.to_vec(),
+            None => {
+                let mut payload = vec![0; self.size];
+                if self.entropy == Entropy::High {
+                    let seed = (identifier as u64) << 16 | self.wire_seq(index) as u64;
+                    StdRng::seed_from_u64(seed).fill_bytes(&mut payload);
+                }
+                payload
+            }
+        }
+    }
+}
+
+/// DSCP value of the request at `index` when rotating through `dscp`.
+pub fn dscp_of(dscp: &[u8], index: usize) -> Option<u8> {
+    if dscp.is_empty() {
+        return None;
+    }
+    Some(dscp[index % dscp.len()])
+}
+
+#[derive(Clone)]
 pub struct VolleyInfo {
     pub results: Vec<Option<PingResult>>,
+    /// Index of the request `results` start at. Nonzero for the windows of a stream, whose
+    /// requests are numbered across windows.
+    pub first_index: usize,
     pub sent: usize,
     pub received: usize,
     pub lost: usize,
+    /// Number of replies whose payload differed from the one sent.
+    pub corrupted: usize,
+    /// Number of replies dropped for an invalid ICMP checksum.
+    pub checksum_errors: usize,
+    /// Number of replies dropped for coming from outside the accepted subnets.
+    pub rejected_sources: usize,
+    /// ICMP messages read that weren't for us, by kind.
+    pub strays: Strays,
+    /// ICMP errors about requests, by request index.
+    pub icmp_errors: Vec<(usize, IcmpError)>,
+    /// Number of extra replies to requests that already had one.
+    pub duplicates: usize,
+    /// Number of replies matched by sequence number alone, with another identifier.
+    pub lenient_matches: usize,
+    /// Number of matched replies beyond the number of requests sent, a sign of reflection or
+    /// amplification.
+    pub excess_replies: usize,
+    /// Number of replies that arrived after a reply to a later request.
+    pub reordered: usize,
+    /// Most requests a reply arrived behind, counting from the latest request answered before
+    /// it.
+    pub max_reorder_distance: usize,
+    /// Most requests awaiting a reply or timeout at any one time.
+    pub max_in_flight: usize,
+    /// Time-weighted average number of requests awaiting a reply or timeout.
+    pub avg_in_flight: f64,
+    /// Number of requests held back because the window was full.
+    pub window_waits: usize,
+    /// Index of the interface requests were routed through, if known.
+    pub interface: Option<u32>,
+    /// Time spent by the tool itself, if profiling.
+    pub profile: Option<Profile>,
+    /// Whether the deadline stopped the volley before every request was sent and answered or
+    /// timed out. Results then only cover the requests that were sent.
+    pub truncated: bool,
+    /// Wall-clock time each request was sent, by index. Empty if unknown, e.g. when replayed.
+    pub send_times: Vec<SystemTime>,
+}
+
+impl VolleyInfo {
+    /// Pools several volleys into one, as if their requests had been a single volley. Results are
+    /// concatenated and counts summed. ICMP errors, in-flight counts and interfaces are per volley
+    /// and don't carry over.
+    pub fn pool<'a>(infos: impl IntoIterator<Item = &'a VolleyInfo>) -> VolleyInfo {
+        let mut pool = VolleyInfo {
+            results: Vec::new(),
+            sent: 0,
+            received: 0,
+            lost: 0,
+            corrupted: 0,
+            checksum_errors: 0,
+            rejected_sources: 0,
+            strays: Strays::default(),
+            icmp_errors: Vec::new(),
+            duplicates: 0,
+            lenient_matches: 0,
+            excess_replies: 0,
+            reordered: 0,
+            max_reorder_distance: 0,
+            max_in_flight: 0,
+            avg_in_flight: 0.0,
+            window_waits: 0,
+            first_index: 0,
+            interface: None,
+            profile: None,
+            truncated: false,
+            send_times: Vec::new(),
+        };
+        for info in infos {
+            pool.results.extend(info.results.iter().cloned());
+            pool.send_times.extend(&info.send_times);
+            pool.sent += info.sent;
+            pool.received += info.received;
+            pool.lost += info.lost;
+            pool.corrupted += info.corrupted;
+            pool.checksum_errors += info.checksum_errors;
+            pool.rejected_sources += info.rejected_sources;
+            pool.strays.add(&info.strays);
+            pool.duplicates += info.duplicates;
+            pool.lenient_matches += info.lenient_matches;
+            pool.excess_replies += info.excess_replies;
+            pool.reordered += info.reordered;
+            pool.max_reorder_distance = pool.max_reorder_distance.max(info.max_reorder_distance);
+            pool.window_waits += info.window_waits;
+            pool.truncated |= info.truncated;
+        }
+        pool
+    }
+}
+
+/// Time spent in the tool during a volley, to tell its overhead apart from network latency.
+#[derive(Clone, Copy, Default)]
+pub struct Profile {
+    /// Building and sending requests, mostly the send syscalls.
+    pub send: Duration,
+    /// Parsing and matching datagrams read by the receiver, excluding waits for them.
+    pub receive: Duration,
+    /// Datagrams the receiver read, including ones that weren't replies to us.
+    pub datagrams: usize,
 }
 
 pub enum VolleyResult {
-    Success(VolleyInfo),
+    Success(Box<VolleyInfo>),
     Error(String),
 }
 
-pub fn measure_volley(
+/// Largest length field of an IP packet.
+pub const MAX_IP_LENGTH: usize = 65535;
+
+/// Linux's ICMP datagram sockets replace the identifier of requests with one of their own,
+/// deliver only the replies carrying it and strip the IPv4 header of replies.
+const DATAGRAM_MATCHES_IDENTIFIER: bool = cfg!(target_os = "linux");
+
+/// Sending side of an ICMP socket.
+pub enum IcmpSender {
+    Raw(TransportSender),
+    /// A datagram socket, shared with the receiving side.
+    Datagram(Arc<OwnedFd>),
+}
+
+impl IcmpSender {
+    pub fn fd(&self) -> RawFd {
+        match self {
+            IcmpSender::Raw(tx) => tx.socket.fd,
+            IcmpSender::Datagram(fd) => fd.as_raw_fd(),
+        }
+    }
+
+    fn send_to<T: Packet>(&mut self, packet: T, target: IpAddr) -> io::Result<usize> {
+        match self {
+            IcmpSender::Raw(tx) => tx.send_to(packet, target),
+            IcmpSender::Datagram(fd) => socket::send_to(fd.as_raw_fd(), packet.packet(), target),
+        }
+    }
+}
+
+/// Receiving side of an ICMP socket.
+pub enum IcmpReceiver {
+    Raw(TransportReceiver),
+    Datagram(Arc<OwnedFd>),
+}
+
+impl IcmpReceiver {
+    pub fn fd(&self) -> RawFd {
+        match self {
+            IcmpReceiver::Raw(rx) => rx.socket.fd,
+            IcmpReceiver::Datagram(fd) => fd.as_raw_fd(),
+        }
+    }
+}
+
+/// Opens the ICMP socket for pinging `target` with the payloads of `config`, a datagram one if
+/// `config.unprivileged` is set.
+pub fn open_channel(
     target: IpAddr,
-    count: usize,
-    size: usize,
-    interval: Duration,
-    timeout: Duration,
-) -> VolleyResult {
+    config: &VolleyConfig,
+) -> Result<(IcmpSender, IcmpReceiver), String> {
     let protocol = match target {
         IpAddr::V4(_) => Layer4(Ipv4(IpNextHeaderProtocols::Icmp)),
         IpAddr::V6(_) => Layer4(Ipv6(IpNextHeaderProtocols::Icmpv6)),
@@ -46,93 +411,787 @@ pub fn measure_volley(
         IpAddr::V6(_) => 40,
     };
 
+    // The IPv4 total length covers the IP header, the IPv6 payload length doesn't.
+    let max_payload_size = match target {
+        IpAddr::V4(_) => MAX_IP_LENGTH - ip_header_size - 8,
+        IpAddr::V6(_) => MAX_IP_LENGTH - 8,
+    };
+    if config.payload_size() > max_payload_size {
+        return Err(format!(
+            "Payload size {} exceeds the maximum of {} bytes for {}",
+            config.payload_size(),
+            max_payload_size,
+            target
+        ));
+    }
+
     // 14 bytes for ethernet frame header
     // ip_header_size bytes for IP header
     // 8 bytes for ICMP header
-    // size bytes for payload
-    let packet_size = 14 + ip_header_size + 8 + size;
+    // payload_size bytes for payload
+    // Bounded by the check above, so the buffer size can't overflow.
+    let packet_size = 14 + ip_header_size + 8 + config.payload_size();
 
-    let (mut tx, rx) = match pnet::transport::transport_channel(packet_size * 16, protocol) {
-        Ok((tx, rx)) => (tx, rx),
-        Err(e) => return VolleyResult::Error(format!("Failed to create transport channel: {}", e)),
-    };
-    let (stop_signal_tx, stop_signal_rx) = oneshot::channel();
-
-    let identifier = rand::random::<u16>();
-    let receiver = thread::spawn(move || {
-        return receive_ipv4(rx, count, timeout, target, identifier, stop_signal_rx);
-    });
-
-    let mut volley_info = VolleyInfo {
-        results: vec![None; count],
-        sent: 0,
-        received: 0,
-        lost: 0,
+    let (tx, rx) = if config.unprivileged {
+        match socket::open_icmp_datagram(target) {
+            Ok(fd) => {
+                let fd = Arc::new(fd);
+                (IcmpSender::Datagram(fd.clone()), IcmpReceiver::Datagram(fd))
+            }
+            Err(e) => return Err(format!("Failed to open ICMP datagram socket: {}", e)),
+        }
+    } else {
+        match pnet::transport::transport_channel(packet_size * 16, protocol) {
+            Ok((tx, rx)) => (IcmpSender::Raw(tx), IcmpReceiver::Raw(rx)),
+            Err(e) => return Err(format!("Failed to create transport channel: {}", e)),
+        }
     };
-    let mut request_send_times: Vec<Instant> = Vec::new();
-
-    let mut next_packet = Instant::now();
-    for seq in 0..count {
-        request_send_times.push(Instant::now());
-        let send_result = match target {
-            IpAddr::V4(_) => send_ipv4_echo_request(&mut tx, target, size, identifier, seq as u16),
-            IpAddr::V6(_) => send_ipv6_echo_request(&mut tx, target, size, identifier, seq as u16),
-        };
-        match send_result {
-            Err(e) => {
-                eprintln!("Failed to send packet: {}", e);
+
+    if let Some(source) = config.source {
+        if source.is_ipv4() != target.is_ipv4() {
+            return Err(format!("Can't ping {} from {}", target, source));
+        }
+        if let Err(e) = socket::bind(tx.fd(), source) {
+            return Err(format!("Failed to bind to {}: {}", source, e));
+        }
+    }
+    if let Some(ttl) = config.ttl {
+        if let Err(e) = socket::set_hop_limit(tx.fd(), target, ttl) {
+            return Err(format!("Failed to set TTL {}: {}", ttl, e));
+        }
+    }
+    if config.dont_fragment {
+        if let Err(e) = socket::set_dont_fragment(tx.fd(), target) {
+            return Err(format!("Failed to set the don't fragment bit: {}", e));
+        }
+    }
+    if let Err(e) = socket::enable_packet_info(rx.fd(), target) {
+        log::verbose!("Failed to enable packet info: {}", e);
+    }
+    if config.kernel_timestamps {
+        if let Err(e) = socket::enable_timestamps(rx.fd()) {
+            log::verbose!("Failed to enable kernel timestamps: {}", e);
+        }
+    }
+    Ok((tx, rx))
+}
+
+/// Pings a target over an ICMP socket that is kept open across volleys. Its volleys run on the
+/// event loop of `measure_volleys`, on the calling thread.
+pub struct Prober {
+    target: IpAddr,
+    tx: IcmpSender,
+    rx: IcmpReceiver,
+}
+
+impl Prober {
+    pub fn new(target: IpAddr, config: &VolleyConfig) -> Result<Prober, String> {
+        let (tx, rx) = open_channel(target, config)?;
+        if config.hardware_timestamps {
+            let result = match socket::outgoing_interface(target, config.source) {
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No interface routes to the target",
+                )),
+                Some(index) => {
+                    socket::enable_hardware_timestamps(rx.fd(), &socket::interface_name(index))
+                }
+            };
+            if let Err(e) = result {
+                log::warning!("Failed to enable hardware timestamps for {}: {}", target, e);
             }
-            Ok(_) => {
-                volley_info.sent += 1;
+        }
+        Ok(Prober { target, tx, rx })
+    }
+
+    pub fn target(&self) -> IpAddr {
+        self.target
+    }
+
+    /// Reads the datagrams that arrived since the previous volley, such as its late replies,
+    /// so a lenient match can't take them for replies to this one. At most `limit` are read,
+    /// so that other ICMP traffic can't hold up the volley.
+    fn discard_stale(&self, limit: usize) {
+        let mut buffer = vec![0; 65536];
+        for _ in 0..limit {
+            match socket::recv_with_timeout(self.rx.fd(), &mut buffer, Duration::ZERO) {
+                Ok(Some(_)) => {}
+                _ => break,
             }
         }
+    }
+
+    /// Measures a volley, blocking until it's done.
+    pub fn measure_volley(&mut self, config: &VolleyConfig) -> VolleyResult {
+        let mut result = None;
+        measure_volleys(
+            [(self, Instant::now())],
+            config,
+            1,
+            || false,
+            |_, volley| result = Some(volley),
+        );
+        result.expect("Volley wasn't measured")
+    }
+}
+
+/// Measures a volley with each prober on one thread, starting each no earlier than the time
+/// paired with it and no more than `limit` at once, in order. A single event loop sends the
+/// requests of every running volley on schedule and reads the replies as their sockets become
+/// readable. `done` is called with the index of each volley as it finishes.
+///
+/// Once `cancelled` returns true, volleys that haven't started are skipped.
+pub fn measure_volleys<'a>(
+    probers: impl IntoIterator<Item = (&'a mut Prober, Instant)>,
+    config: &VolleyConfig,
+    limit: usize,
+    cancelled: impl Fn() -> bool,
+    mut done: impl FnMut(usize, VolleyResult),
+) {
+    let mut pending: VecDeque<_> = probers.into_iter().enumerate().collect();
+    let mut running: Vec<(usize, Volley)> = Vec::new();
+    loop {
+        if cancelled() {
+            pending.clear();
+        }
+        let mut now = Instant::now();
+        while running.len() < limit && pending.front().is_some_and(|(_, (_, start))| now >= *start)
+        {
+            let (i, (prober, _)) = pending.pop_front().unwrap();
+            running.push((i, Volley::start(prober, config)));
+            now = Instant::now();
+        }
 
+        for (_, volley) in &mut running {
+            volley.send_due(Instant::now());
+        }
+        let mut i = 0;
+        while i < running.len() {
+            if running[i].1.is_done(Instant::now()) {
+                let (index, volley) = running.remove(i);
+                done(index, volley.finish());
+            } else {
+                i += 1;
+            }
+        }
+        if running.is_empty() && pending.is_empty() {
+            return;
+        }
 
-        next_packet += interval;
-        thread::sleep(next_packet - Instant::now());
+        // Sleep until the next request, start or stop is due, waking for replies meanwhile.
+        let now = Instant::now();
+        let mut wake = running
+            .iter()
+            .map(|(_, volley)| volley.next_wake(now))
+            .min();
+        if running.len() < limit {
+            if let Some((_, (_, start))) = pending.front() {
+                // Wake regularly to notice a cancellation while waiting to start.
+                let start = (*start).min(now + CANCEL_POLL);
+                wake = Some(wake.map_or(start, |wake| wake.min(start)));
+            }
+        }
+        let mut timeout = wake.map_or(CANCEL_POLL, |wake| wake.saturating_duration_since(now));
+        if config.precise_pacing && running.iter().any(|(_, volley)| volley.sending()) {
+            // Spin on the poll instead of sleeping through the last stretch before a send.
+            if timeout <= SPIN_AHEAD {
+                timeout = Duration::ZERO;
+            } else {
+                timeout -= SPIN_AHEAD;
+            }
+        }
+
+        let readable: Vec<&mut Volley> = running
+            .iter_mut()
+            .map(|(_, volley)| volley)
+            .filter(|volley| volley.receiving)
+            .collect();
+        let fds: Vec<RawFd> = readable
+            .iter()
+            .map(|volley| volley.prober.rx.fd())
+            .collect();
+        match socket::poll_readable(&fds, timeout) {
+            Ok(events) => {
+                for (volley, events) in readable.into_iter().zip(events) {
+                    if events != 0 {
+                        volley.read();
+                    }
+                }
+            }
+            Err(e) => {
+                log::warning!("Error waiting for packets: {}", e);
+                for volley in readable {
+                    volley.receiving = false;
+                }
+            }
+        }
     }
+}
 
-    _ = stop_signal_tx.send(Instant::now() + timeout);
-    let results = receiver.join().expect("Failed to join receiver thread");
+/// How often `measure_volleys` checks for a cancellation while no volley is running.
+const CANCEL_POLL: Duration = Duration::from_millis(100);
 
-    for result in results {
-        let seq = result.seq as usize;
-        if seq >= count {
-            eprintln!(
-                "Received packet with invalid sequence number: {}",
-                result.seq
+/// A volley running on the event loop of `measure_volleys`.
+struct Volley<'a> {
+    prober: &'a mut Prober,
+    config: &'a VolleyConfig,
+    identifier: u16,
+    info: VolleyInfo,
+    profile: Profile,
+    rng: StdRng,
+    /// When each request was sent, in order.
+    send_times: Vec<Instant>,
+    /// Whether each request was answered, or an error reported about it.
+    completed: Vec<bool>,
+    next_send: Instant,
+    deadline: Option<Instant>,
+    /// Sending is held up until fewer requests are outstanding than the window allows.
+    window_closed: bool,
+    /// When to stop waiting for replies, once every request is sent.
+    stop_time: Option<Instant>,
+    /// The deadline stopped the volley before its timeout.
+    cut_short: bool,
+    received: Received,
+    /// False once reading the socket failed.
+    receiving: bool,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl<'a> Volley<'a> {
+    fn start(prober: &'a mut Prober, config: &'a VolleyConfig) -> Volley<'a> {
+        let count = config.count;
+        prober.discard_stale(count + MAX_DRAIN);
+        let now = Instant::now();
+        Volley {
+            identifier: rand::random::<u16>(),
+            info: VolleyInfo {
+                results: vec![None; count],
+                sent: 0,
+                received: 0,
+                lost: 0,
+                corrupted: 0,
+                checksum_errors: 0,
+                rejected_sources: 0,
+                strays: Strays::default(),
+                icmp_errors: Vec::new(),
+                duplicates: 0,
+                excess_replies: 0,
+                lenient_matches: 0,
+                reordered: 0,
+                max_reorder_distance: 0,
+                max_in_flight: 0,
+                avg_in_flight: 0.0,
+                window_waits: 0,
+                first_index: 0,
+                interface: socket::outgoing_interface(prober.target, config.source),
+                profile: None,
+                truncated: false,
+                send_times: Vec::with_capacity(count),
+            },
+            prober,
+            config,
+            profile: Profile::default(),
+            rng: match config.seed {
+                None => StdRng::from_entropy(),
+                Some(seed) => StdRng::seed_from_u64(seed),
+            },
+            send_times: Vec::with_capacity(count),
+            completed: vec![false; count],
+            next_send: now,
+            deadline: config.deadline.map(|deadline| now + deadline),
+            window_closed: false,
+            stop_time: None,
+            cut_short: false,
+            received: Received {
+                replies: Vec::with_capacity(count),
+                checksum_errors: 0,
+                rejected_sources: 0,
+                strays: Strays::default(),
+                errors: Vec::new(),
+                completed: Vec::new(),
+                processing: Duration::ZERO,
+                datagrams: 0,
+                hardware_send_times: HashMap::new(),
+            },
+            receiving: true,
+            buffers: vec![vec![0; 65536]; config.batch_size],
+        }
+    }
+
+    fn sending(&self) -> bool {
+        self.stop_time.is_none()
+    }
+
+    /// When the oldest outstanding request times out, if as many requests are outstanding as
+    /// the window allows.
+    fn window_opens(&self, now: Instant) -> Option<Instant> {
+        let window = self.config.window?;
+        let mut outstanding = self
+            .send_times
+            .iter()
+            .zip(&self.completed)
+            .filter(|(sent, done)| !**done && now < **sent + self.config.timeout)
+            .map(|(sent, _)| *sent);
+        let oldest = outstanding.next()?;
+        (outstanding.count() + 1 >= window).then_some(oldest + self.config.timeout)
+    }
+
+    /// When the volley next needs attention, other than for a reply.
+    fn next_wake(&self, now: Instant) -> Instant {
+        if let Some(stop_time) = self.stop_time {
+            return stop_time;
+        }
+        let wake = match self.window_opens(now) {
+            Some(opens) => opens.max(self.next_send),
+            None => self.next_send,
+        };
+        match self.deadline {
+            Some(deadline) => wake.min(deadline),
+            None => wake,
+        }
+    }
+
+    /// Sends the next request if it's due, along with those due right after it as a batch.
+    fn send_due(&mut self, now: Instant) {
+        if !self.sending() {
+            return;
+        }
+        let config = self.config;
+        let count = config.count;
+        let sent_all = self.send_times.len() == count;
+        if self.deadline.is_some_and(|deadline| now >= deadline) {
+            self.info.truncated = !sent_all;
+            self.finish_sending();
+            return;
+        }
+        if now < self.next_send {
+            return;
+        }
+        if sent_all {
+            self.finish_sending();
+            return;
+        }
+        if self.window_opens(now).is_some() {
+            if !self.window_closed {
+                self.window_closed = true;
+                self.info.window_waits += 1;
+            }
+            return;
+        }
+        if self.window_closed {
+            self.window_closed = false;
+            self.next_send = now;
+        }
+
+        let mut batch = Vec::with_capacity(config.batch_size);
+        loop {
+            let index = self.send_times.len() + batch.len();
+            batch.push(index);
+            let gap = config.gap(index, &mut self.rng);
+            self.next_send += gap;
+            // Requests due right after join the batch, to be sent in the same syscall.
+            let next = index + 1;
+            if batch.len() < config.batch_size
+                && next < count
+                && gap <= MAX_BATCH_GAP
+                && config.traffic_class(next) == config.traffic_class(batch[0])
+            {
+                continue;
+            }
+            break;
+        }
+
+        // The requests of a batch leave together, so they share its send time.
+        let send_time = Instant::now();
+        let wall_time = SystemTime::now();
+        for _ in &batch {
+            self.send_times.push(send_time);
+            self.info.send_times.push(wall_time);
+        }
+        let target = self.prober.target;
+        let (sent, error) =
+            send_requests(&mut self.prober.tx, target, config, self.identifier, &batch);
+        self.info.sent += sent;
+        if let Some(e) = error {
+            log::verbose!(
+                "Failed to send {} of {} packets: {}",
+                batch.len() - sent,
+                batch.len(),
+                e
             );
-            continue;
         }
-        let latency = result.time - request_send_times[seq];
-        if latency > timeout {
-            continue;
+        if config.profile {
+            self.profile.send += send_time.elapsed();
+        }
+    }
+
+    /// Stops sending and sets when to stop waiting for replies.
+    fn finish_sending(&mut self) {
+        // Only requests that were sent have results.
+        let count = self.send_times.len();
+        self.info.results.truncate(count);
+
+        let config = self.config;
+        if config.inject_faults && count > 0 {
+            let target = self.prober.target;
+            if let Err(e) = inject_faults(&mut self.prober.tx, target, config, self.identifier) {
+                log::warning!("Failed to inject replies: {}", e);
+            }
+        }
+
+        let mut stop_time = Instant::now() + config.timeout;
+        if let Some(deadline) = self.deadline {
+            self.cut_short = deadline < stop_time;
+            stop_time = stop_time.min(deadline);
         }
+        self.stop_time = Some(stop_time);
+    }
+
+    /// Whether a reply has arrived for every request. Injected and leniently matched replies
+    /// may take the place of real ones, so those volleys wait for the timeout instead.
+    fn answered(&self) -> bool {
+        self.received.replies.len() >= self.config.count
+            && !self.config.inject_faults
+            && !self.config.lenient_identifier
+    }
 
-        if let Some(_) = volley_info.results[seq] {
-            eprintln!("Received duplicate packet with sequence number: {}", result.seq);
-            continue;
+    fn is_done(&self, now: Instant) -> bool {
+        match self.stop_time {
+            None => false,
+            Some(stop_time) => now >= stop_time || !self.receiving || self.answered(),
         }
+    }
 
-        volley_info.received += 1;
-        volley_info.results[seq] = Some(PingResult {
-            latency,
-            reply_size: result.size,
+    /// Reads the datagrams queued on the socket, up to a batch.
+    fn read(&mut self) {
+        let config = self.config;
+        let target = self.prober.target;
+        match socket::recv_batch_with_timeout(
+            self.prober.rx.fd(),
+            &mut self.buffers,
+            Duration::ZERO,
+        ) {
+            Ok(infos) => {
+                for (buffer, info) in self.buffers.iter().zip(&infos) {
+                    self.received
+                        .read(buffer, info, config, target, self.identifier);
+                }
+            }
+            Err(e) => {
+                log::warning!("Error receiving packet: {}", e);
+                self.receiving = false;
+            }
+        }
+        for seq in self.received.completed.drain(..) {
+            if let Some(done) = self.completed.get_mut(config.index_of(seq)) {
+                *done = true;
+            }
+        }
+    }
+
+    /// Matches the replies to the requests.
+    fn finish(mut self) -> VolleyResult {
+        let config = self.config;
+        let target = self.prober.target;
+        let timeout = config.timeout;
+        if self.answered() {
+            // Read replies that are already buffered, such as duplicates, without waiting.
+            for _ in 0..MAX_DRAIN {
+                let buffer = &mut self.buffers[0];
+                match socket::recv_with_timeout(self.prober.rx.fd(), buffer, Duration::ZERO) {
+                    Ok(Some(info)) => {
+                        self.received
+                            .read(buffer, &info, config, target, self.identifier)
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        let mut volley_info = self.info;
+        let received = self.received;
+        let request_send_times = self.send_times;
+        let count = request_send_times.len();
+        let cut_short = self.cut_short;
+        volley_info.checksum_errors = received.checksum_errors;
+        volley_info.rejected_sources = received.rejected_sources;
+        volley_info.strays = received.strays;
+        volley_info.icmp_errors = received
+            .errors
+            .into_iter()
+            .map(|(seq, error)| (config.index_of(seq), error))
+            .filter(|(index, _)| *index < count)
+            .collect();
+        if config.profile {
+            let mut profile = self.profile;
+            profile.receive = received.processing;
+            profile.datagrams = received.datagrams;
+            volley_info.profile = Some(profile);
+        }
+        if config.hardware_timestamps {
+            let hardware_timed = received
+                .replies
+                .iter()
+                .filter(|reply| {
+                    reply.hardware_time.is_some()
+                        && received.hardware_send_times.contains_key(&reply.seq)
+                })
+                .count();
+            log::debug!(
+                "{} of {} replies from {} timed by hardware",
+                hardware_timed,
+                received.replies.len(),
+                target
+            );
+        }
+
+        // Replies are in arrival order.
+        let mut highest_seq: Option<usize> = None;
+        // Whether each result was matched leniently, and whether it was corrupted.
+        let mut matches = vec![(false, false); count];
+        for result in received.replies {
+            let seq = config.index_of(result.seq);
+            if seq >= count && result.lenient {
+                continue;
+            }
+            if seq >= count {
+                log::verbose!(
+                    "Received packet with invalid sequence number: {}",
+                    result.seq
+                );
+                continue;
+            }
+            // Hardware timestamps are on the NIC's clock, so only compare them with each other.
+            let latency = match (
+                result.hardware_time,
+                received.hardware_send_times.get(&result.seq),
+            ) {
+                (Some(received), Some(&sent)) if received >= sent => received - sent,
+                _ => result.time - request_send_times[seq],
+            };
+            if latency > timeout {
+                continue;
+            }
+
+            if volley_info.results[seq].is_some() {
+                let (lenient, corrupted) = matches[seq];
+                if !lenient || result.lenient {
+                    volley_info.duplicates += 1;
+                    continue;
+                }
+                // An exact match takes precedence over one by sequence number alone.
+                volley_info.received -= 1;
+                volley_info.lenient_matches -= 1;
+                if corrupted {
+                    volley_info.corrupted -= 1;
+                }
+            }
+
+            matches[seq] = (result.lenient, result.corrupted);
+            if result.lenient {
+                volley_info.lenient_matches += 1;
+            }
+            if result.corrupted {
+                volley_info.corrupted += 1;
+            }
+            match highest_seq {
+                Some(highest) if seq < highest => {
+                    volley_info.reordered += 1;
+                    volley_info.max_reorder_distance =
+                        volley_info.max_reorder_distance.max(highest - seq);
+                }
+                _ => highest_seq = Some(seq),
+            }
+
+            volley_info.received += 1;
+            volley_info.results[seq] = Some(PingResult {
+                latency,
+                reply_size: result.size,
+                interface: result.interface,
+                ip_header: result.ip_header,
+            });
+        }
+        volley_info.lost = count - volley_info.received;
+        if cut_short && volley_info.received < count {
+            volley_info.truncated = true;
+        }
+        volley_info.excess_replies =
+            (volley_info.received + volley_info.duplicates).saturating_sub(volley_info.sent);
+        (volley_info.max_in_flight, volley_info.avg_in_flight) =
+            in_flight(&request_send_times, &volley_info.results, timeout);
+
+        VolleyResult::Success(Box::new(volley_info))
+    }
+}
+
+/// Measures a volley over a socket of its own. To measure repeatedly, a `Prober` saves opening
+/// one each time.
+pub fn measure_volley(target: IpAddr, config: &VolleyConfig) -> VolleyResult {
+    match Prober::new(target, config) {
+        Err(e) => VolleyResult::Error(e),
+        Ok(mut prober) => prober.measure_volley(config),
+    }
+}
+
+/// Measures a volley with a probe that blocks until its request is answered, such as a
+/// connection, running each request on a thread of its own to keep the pacing. `probe` returns
+/// the result of the request at an index, `None` if it wasn't answered within the timeout.
+///
+/// Follows the intervals and deadline of `config`; options that shape ICMP requests don't apply.
+pub fn measure_blocking<F>(config: &VolleyConfig, probe: F) -> VolleyResult
+where
+    F: Fn(usize) -> Option<PingResult> + Send + Sync + 'static,
+{
+    let probe = Arc::new(probe);
+    let (results_tx, results_rx) = mpsc::channel();
+
+    let mut send_times: Vec<Instant> = Vec::new();
+    let mut wall_times: Vec<SystemTime> = Vec::new();
+    let mut truncated = false;
+    let mut rng = match config.seed {
+        None => StdRng::from_entropy(),
+        Some(seed) => StdRng::seed_from_u64(seed),
+    };
+    let mut next_send = Instant::now();
+    let deadline = config.deadline.map(|deadline| next_send + deadline);
+    for index in 0..config.count {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            truncated = true;
+            break;
+        }
+        send_times.push(Instant::now());
+        wall_times.push(SystemTime::now());
+        let probe = probe.clone();
+        let results = results_tx.clone();
+        thread::spawn(move || {
+            _ = results.send((index, probe(index)));
         });
+
+        next_send += config.gap(index, &mut rng);
+        let wake = match deadline {
+            Some(deadline) => next_send.min(deadline),
+            None => next_send,
+        };
+        pace(wake, config.precise_pacing);
+    }
+    drop(results_tx);
+
+    let sent = send_times.len();
+    let mut results: Vec<Option<PingResult>> = vec![None; sent];
+    loop {
+        // Every probe gives up after the timeout, so only a deadline needs a timeout here.
+        let outcome = match deadline {
+            None => results_rx
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            Some(deadline) => {
+                results_rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+        };
+        match outcome {
+            Ok((index, result)) => results[index] = result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                truncated = true;
+                break;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
     }
-    volley_info.lost = count - volley_info.received;
 
-    return VolleyResult::Success(volley_info);
+    let received = results.iter().flatten().count();
+    let (max_in_flight, avg_in_flight) = in_flight(&send_times, &results, config.timeout);
+    VolleyResult::Success(Box::new(VolleyInfo {
+        sent,
+        received,
+        lost: sent - received,
+        corrupted: 0,
+        checksum_errors: 0,
+        rejected_sources: 0,
+        strays: Strays::default(),
+        icmp_errors: Vec::new(),
+        duplicates: 0,
+        lenient_matches: 0,
+        excess_replies: 0,
+        reordered: 0,
+        max_reorder_distance: 0,
+        max_in_flight,
+        avg_in_flight,
+        window_waits: 0,
+        first_index: 0,
+        interface: None,
+        profile: None,
+        truncated,
+        send_times: wall_times,
+        results,
+    }))
 }
 
-fn send_ipv4_echo_request(
-    tx: &mut TransportSender,
-    target: IpAddr,
-    size: usize,
-    identifier: u16,
-    seq: u16,
-) -> io::Result<()> {
-    let packet_size = 8 + size;
+/// How long before a send precise pacing stops sleeping and starts spinning, covering the
+/// oversleep of `thread::sleep`.
+pub const SPIN_AHEAD: Duration = Duration::from_millis(2);
+
+/// Longest gap between requests that are sent in one batch.
+pub const MAX_BATCH_GAP: Duration = Duration::from_millis(1);
+
+/// Waits until `deadline`. With `precise`, sleeps until shortly before it and then spins,
+/// keeping a core busy for accuracy that sleeping alone can't give.
+pub fn pace(deadline: Instant, precise: bool) {
+    if !precise {
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        return;
+    }
+    thread::sleep(
+        deadline
+            .saturating_duration_since(Instant::now())
+            .saturating_sub(SPIN_AHEAD),
+    );
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+/// Returns the maximum and time-weighted average number of outstanding requests, counting each
+/// request from when it was sent until its reply arrived or it timed out.
+pub fn in_flight(
+    send_times: &[Instant],
+    results: &[Option<PingResult>],
+    timeout: Duration,
+) -> (usize, f64) {
+    let mut events: Vec<(Instant, i64)> = Vec::new();
+    for (send_time, result) in send_times.iter().zip(results) {
+        let done = match result {
+            Some(result) => *send_time + result.latency,
+            None => *send_time + timeout,
+        };
+        events.push((*send_time, 1));
+        events.push((done, -1));
+    }
+    // Replies before sends at the same instant, so that an instant reply doesn't overlap.
+    events.sort();
+    let (first, last) = match (events.first(), events.last()) {
+        (Some(first), Some(last)) => (first.0, last.0),
+        _ => return (0, 0.0),
+    };
+
+    let mut current: i64 = 0;
+    let mut max: i64 = 0;
+    let mut weighted = 0.0;
+    let mut previous = first;
+    for (time, delta) in events {
+        weighted += current as f64 * (time - previous).as_secs_f64();
+        previous = time;
+        current += delta;
+        max = max.max(current);
+    }
+
+    let span = (last - first).as_secs_f64();
+    let avg = if span > 0.0 { weighted / span } else { 0.0 };
+    (max as usize, avg)
+}
+
+/// An ICMP echo request with `payload`.
+fn ipv4_echo_request(payload: &[u8], identifier: u16, seq: u16) -> Vec<u8> {
+    let packet_size = 8 + payload.len();
     let mut packet = vec![0; packet_size];
 
     let mut icmp_packet = icmp::echo_request::MutableEchoRequestPacket::new(&mut packet)
@@ -141,27 +1200,16 @@ fn send_ipv4_echo_request(
     icmp_packet.set_icmp_type(icmp::IcmpTypes::EchoRequest);
     icmp_packet.set_identifier(identifier);
     icmp_packet.set_sequence_number(seq);
-    thread_rng().fill_bytes(icmp_packet.payload_mut());
+    icmp_packet.set_payload(payload);
 
-    let checksum = util::checksum(&icmp_packet.packet(), 1);
+    let checksum = util::checksum(icmp_packet.packet(), 1);
     icmp_packet.set_checksum(checksum);
-
-    match tx.send_to(icmp_packet, target) {
-        Err(e) => return Err(e),
-        Ok(_) => {}
-    }
-
-    Ok(())
+    packet
 }
 
-fn send_ipv6_echo_request(
-    tx: &mut TransportSender,
-    target: IpAddr,
-    size: usize,
-    identifier: u16,
-    seq: u16,
-) -> io::Result<()> {
-    let packet_size = 8 + size;
+/// An ICMPv6 echo request with `payload`.
+fn ipv6_echo_request(payload: &[u8], identifier: u16, seq: u16) -> Vec<u8> {
+    let packet_size = 8 + payload.len();
     let mut packet = vec![0; packet_size];
     let mut icmp_packet = icmpv6::echo_request::MutableEchoRequestPacket::new(&mut packet)
         .expect("Failed to create ICMP packet");
@@ -169,95 +1217,536 @@ fn send_ipv6_echo_request(
     icmp_packet.set_icmpv6_type(icmpv6::Icmpv6Types::EchoRequest);
     icmp_packet.set_identifier(identifier);
     icmp_packet.set_sequence_number(seq);
-    thread_rng().fill_bytes(icmp_packet.payload_mut());
+    icmp_packet.set_payload(payload);
 
-    let checksum = util::checksum(&icmp_packet.packet(), 1);
+    let checksum = util::checksum(icmp_packet.packet(), 1);
     icmp_packet.set_checksum(checksum);
+    packet
+}
 
-    match tx.send_to(icmp_packet, target) {
-        Err(e) => return Err(e),
-        Ok(_) => {}
+/// An echo request with `payload` over ICMP or ICMPv6, depending on `target`.
+fn echo_request(target: IpAddr, payload: &[u8], identifier: u16, seq: u16) -> Vec<u8> {
+    match target {
+        IpAddr::V4(_) => ipv4_echo_request(payload, identifier, seq),
+        IpAddr::V6(_) => ipv6_echo_request(payload, identifier, seq),
     }
+}
 
-    Ok(())
+/// Marks requests from the one at `index` on with its DSCP value and ECN codepoint, if
+/// configured and different from the previous request's.
+fn set_traffic_class(tx: &IcmpSender, target: IpAddr, config: &VolleyConfig, index: usize) {
+    if let Some(traffic_class) = config.traffic_class(index) {
+        if index == 0 || config.traffic_class(index - 1) != Some(traffic_class) {
+            if let Err(e) = socket::set_traffic_class(tx.fd(), target, traffic_class) {
+                log::warning!("Failed to set traffic class {:#04x}: {}", traffic_class, e);
+            }
+        }
+    }
+}
+
+/// Sends the request at `index`, marked with its DSCP value and ECN codepoint if configured.
+pub fn send_request(
+    tx: &mut IcmpSender,
+    target: IpAddr,
+    config: &VolleyConfig,
+    identifier: u16,
+    index: usize,
+) -> io::Result<()> {
+    set_traffic_class(tx, target, config, index);
+    let payload = config.payload(identifier, index);
+    send_echo_request(tx, target, &payload, identifier, config.wire_seq(index))
+}
+
+/// Sends the requests at `indices`, which share a traffic class, in one syscall where the
+/// platform allows. Returns how many were sent, and the last error if any weren't.
+fn send_requests(
+    tx: &mut IcmpSender,
+    target: IpAddr,
+    config: &VolleyConfig,
+    identifier: u16,
+    indices: &[usize],
+) -> (usize, Option<io::Error>) {
+    if let [index] = indices {
+        return match send_request(tx, target, config, identifier, *index) {
+            Ok(()) => (1, None),
+            Err(e) => (0, Some(e)),
+        };
+    }
+    set_traffic_class(tx, target, config, indices[0]);
+    let packets: Vec<Vec<u8>> = indices
+        .iter()
+        .map(|&index| {
+            echo_request(
+                target,
+                &config.payload(identifier, index),
+                identifier,
+                config.wire_seq(index),
+            )
+        })
+        .collect();
+    socket::send_batch(tx.fd(), &packets, target)
 }
 
-struct ReplyResult {
+/// Sends an echo request with `payload` over ICMP or ICMPv6, depending on `target`.
+pub fn send_echo_request(
+    tx: &mut IcmpSender,
+    target: IpAddr,
+    payload: &[u8],
+    identifier: u16,
     seq: u16,
-    time: Instant,
-    size: usize,
+) -> io::Result<()> {
+    let packet = echo_request(target, payload, identifier, seq);
+    let result = match target {
+        IpAddr::V4(_) => tx.send_to(
+            icmp::IcmpPacket::new(&packet).expect("Failed to create ICMP packet"),
+            target,
+        ),
+        IpAddr::V6(_) => tx.send_to(
+            icmpv6::Icmpv6Packet::new(&packet).expect("Failed to create ICMPv6 packet"),
+            target,
+        ),
+    };
+    result.map(|_| ())
 }
 
-fn receive_ipv4(
-    mut rx: pnet::transport::TransportReceiver,
-    count: usize,
-    timeout: Duration,
+/// Sends the target echo replies to the first request of the volley: one with a bad checksum and
+/// one valid duplicate. Over loopback these arrive back on the receiving socket.
+fn inject_faults(
+    tx: &mut IcmpSender,
     target: IpAddr,
+    config: &VolleyConfig,
     identifier: u16,
-    stop_signal: oneshot::Receiver<Instant>,
-) -> Vec<ReplyResult> {
-    let mut results: Vec<ReplyResult> = Vec::new();
-    let mut iter = icmp_packet_iter(&mut rx);
-    let mut stop_time: Option<Instant> = None;
+) -> io::Result<()> {
+    let payload = config.payload(identifier, 0);
+    let mut packet = vec![0; 8 + payload.len()];
+    let mut icmp_packet = icmp::echo_reply::MutableEchoReplyPacket::new(&mut packet)
+        .expect("Failed to create ICMP echo reply packet");
 
-    results.reserve(count);
+    icmp_packet.set_icmp_type(icmp::IcmpTypes::EchoReply);
+    icmp_packet.set_identifier(identifier);
+    icmp_packet.set_sequence_number(config.wire_seq(0));
+    icmp_packet.set_payload(&payload);
 
-    loop {
-        if results.len() >= count {
-            break;
+    let checksum = util::checksum(icmp_packet.packet(), 1);
+    icmp_packet.set_checksum(!checksum);
+    tx.send_to(icmp_packet.to_immutable(), target)?;
+
+    icmp_packet.set_checksum(checksum);
+    tx.send_to(icmp_packet.to_immutable(), target)?;
+    Ok(())
+}
+
+/// Datagrams read during a volley.
+struct Received {
+    /// Replies in arrival order.
+    replies: Vec<ReplyResult>,
+    checksum_errors: usize,
+    rejected_sources: usize,
+    strays: Strays,
+    /// ICMP errors about our requests, by sequence number.
+    errors: Vec<(u16, IcmpError)>,
+    /// Sequence numbers of requests answered or reported since last taken, which no longer hold
+    /// up the window.
+    completed: Vec<u16>,
+    /// Time spent parsing and matching datagrams, if profiling.
+    processing: Duration,
+    datagrams: usize,
+    /// When the NIC sent our requests, by sequence number, if it timestamps them.
+    hardware_send_times: HashMap<u16, Duration>,
+}
+
+impl Received {
+    /// Parses and adds the datagram read into `buffer`.
+    fn read(
+        &mut self,
+        buffer: &[u8],
+        info: &socket::RecvInfo,
+        config: &VolleyConfig,
+        target: IpAddr,
+        identifier: u16,
+    ) {
+        let start = config.profile.then(Instant::now);
+        if info.sent {
+            let request = parse_sent_request(&buffer[..info.size]);
+            if let (Some((request_identifier, seq)), Some(time)) = (request, info.hardware_time) {
+                if request_identifier == identifier {
+                    self.hardware_send_times.insert(seq, time);
+                }
+            }
+        } else {
+            self.add(parse_reply(buffer, info, config, target, identifier));
+        }
+        if let Some(start) = start {
+            self.processing += start.elapsed();
+            self.datagrams += 1;
         }
+    }
 
-        if stop_time == None {
-            stop_time = match stop_signal.try_recv() {
-                Ok(stop_time) => Some(stop_time),
-                Err(TryRecvError::Empty) => None,
-                Err(e) => panic!("Unexpected error receiving {}", e),
-            };
+    fn add(&mut self, parsed: Parsed) {
+        match parsed {
+            Parsed::Reply(reply) => {
+                self.completed.push(reply.seq);
+                self.replies.push(reply);
+            }
+            Parsed::ChecksumError => self.checksum_errors += 1,
+            Parsed::RejectedSource => self.rejected_sources += 1,
+            Parsed::Stray(stray) => self.strays.count(stray),
+            Parsed::Error { seq, error } => {
+                // No reply will follow, so the request no longer holds up the window.
+                self.completed.push(seq);
+                self.errors.push((seq, error));
+            }
+            Parsed::Ignored => {}
         }
-        let timeout = match stop_time {
-            Some(stop_time) => {
-                let now = Instant::now();
-                if now >= stop_time {
-                    break;
-                }
-                stop_time - now
+    }
+}
+
+/// Most datagrams read after the last expected reply, so that a flood can't hold up the volley.
+const MAX_DRAIN: usize = 64;
+
+pub struct ReplyResult {
+    pub seq: u16,
+    pub time: Instant,
+    pub size: usize,
+    pub interface: Option<u32>,
+    pub ip_header: Option<IpHeaderInfo>,
+    pub corrupted: bool,
+    /// Matched by sequence number alone, with another identifier.
+    pub lenient: bool,
+    /// When the NIC received the reply, on its own clock, if it timestamped it.
+    pub hardware_time: Option<Duration>,
+}
+
+/// Payload of `reply`, parsed from the datagram read into `buffer`, whose end it runs to.
+pub fn reply_payload<'a>(
+    buffer: &'a [u8],
+    info: &socket::RecvInfo,
+    reply: &ReplyResult,
+) -> &'a [u8] {
+    &buffer[info.size - reply.size..info.size]
+}
+
+/// Outcome of reading a datagram from the ICMP socket.
+pub enum Parsed {
+    Reply(ReplyResult),
+    /// An echo reply to us with an invalid checksum.
+    ChecksumError,
+    /// An echo reply to us from outside the accepted subnets.
+    RejectedSource,
+    /// An ICMP error about one of our requests, from the target or a router on the way.
+    Error {
+        seq: u16,
+        error: IcmpError,
+    },
+    /// An ICMP message that wasn't for us.
+    Stray(Stray),
+    /// Copies of our own requests, and datagrams that aren't ICMP messages.
+    Ignored,
+}
+
+/// Kind of an ICMP message that wasn't for us.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stray {
+    /// An echo reply from another host than the target.
+    OtherHost,
+    /// An echo reply with another identifier, e.g. to another ping running at the same time.
+    OtherIdentifier,
+    Redirect,
+    /// A destination unreachable message about another request or connection.
+    Unreachable,
+    /// Anything else, such as echo requests from other hosts or other ICMP errors.
+    Other,
+}
+
+/// Counts of ICMP messages that weren't for us, by kind.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Strays {
+    pub other_hosts: usize,
+    pub other_identifiers: usize,
+    pub redirects: usize,
+    pub unreachable: usize,
+    pub other: usize,
+}
+
+impl Strays {
+    pub fn count(&mut self, stray: Stray) {
+        match stray {
+            Stray::OtherHost => self.other_hosts += 1,
+            Stray::OtherIdentifier => self.other_identifiers += 1,
+            Stray::Redirect => self.redirects += 1,
+            Stray::Unreachable => self.unreachable += 1,
+            Stray::Other => self.other += 1,
+        }
+    }
+
+    pub fn add(&mut self, other: &Strays) {
+        self.other_hosts += other.other_hosts;
+        self.other_identifiers += other.other_identifiers;
+        self.redirects += other.redirects;
+        self.unreachable += other.unreachable;
+        self.other += other.other;
+    }
+
+    pub fn total(&self) -> usize {
+        self.other_hosts + self.other_identifiers + self.redirects + self.unreachable + self.other
+    }
+}
+
+impl fmt::Display for Strays {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds = [
+            (self.other_hosts, "from other hosts"),
+            (self.other_identifiers, "with other identifiers"),
+            (self.redirects, "redirects"),
+            (self.unreachable, "destination unreachable"),
+            (self.other, "other"),
+        ];
+        let counts: Vec<String> = kinds
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, kind)| format!("{} {}", count, kind))
+            .collect();
+        write!(f, "{}", counts.join(", "))
+    }
+}
+
+/// Kind of the stray ICMP message `message`, by its type.
+fn stray_kind(message: &[u8], target: IpAddr) -> Stray {
+    match (target, message.first()) {
+        (IpAddr::V4(_), Some(5)) | (IpAddr::V6(_), Some(137)) => Stray::Redirect,
+        (IpAddr::V4(_), Some(3)) | (IpAddr::V6(_), Some(1)) => Stray::Unreachable,
+        _ => Stray::Other,
+    }
+}
+
+/// Whether `message` is an echo request with `identifier`, one of ours seen over loopback.
+fn is_own_request(message: &[u8], target: IpAddr, identifier: u16) -> bool {
+    let request_type = match target {
+        IpAddr::V4(_) => 8,
+        IpAddr::V6(_) => 128,
+    };
+    message.len() >= 8
+        && message[0] == request_type
+        && u16::from_be_bytes([message[4], message[5]]) == identifier
+}
+
+/// Identifier and sequence number of the echo request in `frame`, a copy of one we sent, whole
+/// down to its Ethernet header.
+fn parse_sent_request(frame: &[u8]) -> Option<(u16, u16)> {
+    let ethernet = EthernetPacket::new(frame)?;
+    let payload = ethernet.payload();
+    let (message, is_request) = match ethernet.get_ethertype() {
+        EtherTypes::Ipv4 => {
+            let header_length = Ipv4Packet::new(payload)?.get_header_length() as usize * 4;
+            let message = payload.get(header_length..)?;
+            let icmp_type = icmp::IcmpPacket::new(message)?.get_icmp_type();
+            (message, icmp_type == icmp::IcmpTypes::EchoRequest)
+        }
+        // Our requests carry no extension headers after the fixed 40 bytes.
+        EtherTypes::Ipv6 => {
+            let message = payload.get(40..)?;
+            let icmp_type = icmpv6::Icmpv6Packet::new(message)?.get_icmpv6_type();
+            (message, icmp_type == icmpv6::Icmpv6Types::EchoRequest)
+        }
+        _ => return None,
+    };
+    if !is_request {
+        return None;
+    }
+    // Echo requests are laid out the same over ICMPv6.
+    let request = icmp::echo_request::EchoRequestPacket::new(message)?;
+    Some((request.get_identifier(), request.get_sequence_number()))
+}
+
+/// The fields of an echo reply that replies are matched and checked by.
+struct EchoReply<'a> {
+    identifier: u16,
+    seq: u16,
+    checksum_valid: bool,
+    payload: &'a [u8],
+}
+
+/// Parses an ICMP message as an echo reply, `None` if it's another kind of message.
+fn parse_ipv4_echo_reply(message: &[u8]) -> Option<EchoReply<'_>> {
+    let packet = icmp::IcmpPacket::new(message)?;
+    // Over loopback the socket also sees our own requests.
+    if packet.get_icmp_type() != icmp::IcmpTypes::EchoReply {
+        return None;
+    }
+    let reply = icmp::echo_reply::EchoReplyPacket::new(message)?;
+    Some(EchoReply {
+        identifier: reply.get_identifier(),
+        seq: reply.get_sequence_number(),
+        checksum_valid: reply.get_checksum() == util::checksum(reply.packet(), 1),
+        payload: &message[8..],
+    })
+}
+
+/// Parses an ICMPv6 message as an echo reply, `None` if it's another kind of message.
+fn parse_ipv6_echo_reply(message: &[u8]) -> Option<EchoReply<'_>> {
+    let packet = icmpv6::Icmpv6Packet::new(message)?;
+    if packet.get_icmpv6_type() != icmpv6::Icmpv6Types::EchoReply {
+        return None;
+    }
+    let reply = icmpv6::echo_reply::EchoReplyPacket::new(message)?;
+    Some(EchoReply {
+        identifier: reply.get_identifier(),
+        seq: reply.get_sequence_number(),
+        // The checksum covers a pseudo-header with the addresses, which the kernel verifies
+        // before delivering ICMPv6 to raw sockets.
+        checksum_valid: true,
+        payload: &message[8..],
+    })
+}
+
+/// Parses a datagram read into `buffer` as an echo reply to a ping of `target` with
+/// `identifier`.
+pub fn parse_reply(
+    buffer: &[u8],
+    info: &socket::RecvInfo,
+    config: &VolleyConfig,
+    target: IpAddr,
+    identifier: u16,
+) -> Parsed {
+    if info.sent {
+        return Parsed::Ignored;
+    }
+    let data = &buffer[..info.size];
+    let datagram_matched = config.unprivileged && DATAGRAM_MATCHES_IDENTIFIER;
+    // Raw IPv4 sockets deliver the IP header along with the ICMP message.
+    let (data, ip_header) = match target {
+        IpAddr::V4(_) if datagram_matched => (data, None),
+        IpAddr::V4(_) => match Ipv4Packet::new(data) {
+            Some(ip_packet) => {
+                let header_length = ip_packet.get_header_length() as usize * 4;
+                let ip_header = IpHeaderInfo {
+                    identification: ip_packet.get_identification(),
+                    fragment: ip_packet.get_flags() & Ipv4Flags::MoreFragments != 0
+                        || ip_packet.get_fragment_offset() != 0,
+                    total_length: ip_packet.get_total_length() as usize,
+                    ecn: ip_packet.get_ecn(),
+                };
+                (&data[header_length.min(data.len())..], Some(ip_header))
             }
-            None => timeout,
+            None => return Parsed::Ignored,
+        },
+        IpAddr::V6(_) => (data, None),
+    };
+
+    // Errors come from any router on the way, so match them by the request they quote.
+    let report = match target {
+        IpAddr::V4(_) => icmp_error::parse_ipv4(data),
+        IpAddr::V6(_) => icmp_error::parse_ipv6(data),
+    };
+    if let Some(report) = report {
+        if report.destination != target || report.identifier != identifier {
+            return Parsed::Stray(stray_kind(data, target));
+        }
+        return Parsed::Error {
+            seq: report.seq,
+            error: report.error,
         };
+    }
 
-        match iter.next_with_timeout(timeout) {
-            Ok(Some((packet, addr))) => {
-                if addr != target {
-                    continue;
-                }
-                if packet.get_icmp_type() != icmp::IcmpTypes::EchoReply {
-                    break;
-                }
-                let icmp_reply = match icmp::echo_reply::EchoReplyPacket::new(packet.packet()) {
-                    Some(reply) => reply,
-                    None => continue,
-                };
-                if icmp_reply.get_checksum() != util::checksum(&icmp_reply.packet(), 1) {
-                    eprintln!("Received packet with invalid checksum");
-                    continue;
-                }
-                if icmp_reply.get_identifier() != identifier {
-                    continue;
-                }
+    let echo_reply = match target {
+        IpAddr::V4(_) => parse_ipv4_echo_reply(data),
+        IpAddr::V6(_) => parse_ipv6_echo_reply(data),
+    };
+    let echo_reply = match echo_reply {
+        Some(echo_reply) => echo_reply,
+        None if is_own_request(data, target, identifier) => return Parsed::Ignored,
+        None => return Parsed::Stray(stray_kind(data, target)),
+    };
+    // Without accepted subnets, replies from other hosts are strays rather than rejected.
+    if config.accept_sources.is_empty() && info.source != target {
+        return Parsed::Stray(Stray::OtherHost);
+    }
+    let lenient = echo_reply.identifier != identifier && !datagram_matched;
+    if lenient && !config.lenient_identifier {
+        return Parsed::Stray(Stray::OtherIdentifier);
+    }
+    if !config.accepts_source(info.source, target) {
+        return Parsed::RejectedSource;
+    }
+    if config.verify_checksum && !echo_reply.checksum_valid {
+        return Parsed::ChecksumError;
+    }
 
-                results.push(ReplyResult {
-                    seq: icmp_reply.get_sequence_number(),
-                    time: Instant::now(),
-                    size: icmp_reply.payload().len(),
-                });
-            }
-            Ok(None) => {}
-            Err(e) => {
-                eprintln!("Error receiving packet: {}", e);
-                return results;
+    let seq = echo_reply.seq;
+    let index = config.index_of(seq);
+    let corrupted = match config.fixed_payload(index) {
+        Some(expected) => echo_reply.payload != expected,
+        None => echo_reply.payload != config.payload(identifier, index),
+    };
+
+    Parsed::Reply(ReplyResult {
+        seq,
+        time: info.time,
+        size: echo_reply.payload.len(),
+        interface: info.interface,
+        ip_header,
+        corrupted,
+        lenient,
+        hardware_time: info.hardware_time,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(seq_start: u16) -> VolleyConfig {
+        VolleyConfig {
+            count: 10,
+            size: 56,
+            entropy: Entropy::Low,
+            interval: Duration::from_millis(10),
+            interval_distribution: IntervalDistribution::Periodic,
+            gaps: Arc::new(Vec::new()),
+            seed: None,
+            timeout: Duration::from_secs(1),
+            payloads: Arc::new(Vec::new()),
+            verify_checksum: true,
+            seq_start,
+            inject_faults: false,
+            window: None,
+            lenient_identifier: false,
+            dscp: Arc::new(Vec::new()),
+            ecn: None,
+            ttl: None,
+            dont_fragment: false,
+            source: None,
+            precise_pacing: false,
+            accept_sources: Arc::new(Vec::new()),
+            profile: false,
+            deadline: None,
+            unprivileged: false,
+            kernel_timestamps: false,
+            hardware_timestamps: false,
+            batch_size: 1,
+        }
+    }
+
+    #[test]
+    fn sequence_numbers_round_trip() {
+        for seq_start in [0, 1, u16::MAX - 2, u16::MAX] {
+            let config = config(seq_start);
+            for index in 0..10 {
+                assert_eq!(config.index_of(config.wire_seq(index)), index);
             }
         }
     }
 
-    return results;
+    #[test]
+    fn sequence_numbers_wrap() {
+        let config = config(u16::MAX - 1);
+        assert_eq!(config.wire_seq(0), u16::MAX - 1);
+        assert_eq!(config.wire_seq(1), u16::MAX);
+        assert_eq!(config.wire_seq(2), 0);
+        assert_eq!(config.wire_seq(3), 1);
+        assert_eq!(config.index_of(0), 2);
+        assert_eq!(config.index_of(1), 3);
+        // A sequence number from before the volley isn't mistaken for an early request.
+        assert_eq!(config.index_of(u16::MAX - 2), usize::from(u16::MAX));
+    }
 }